@@ -1,8 +1,75 @@
-use avm_rs_memory::{mem::Memory, wrappers::stack::Stack};
+use std::fmt::Display;
 
-use crate::register::*;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CPUFault {
+    IllegalInstruction { address: u64, opcode: u32 },
+    MemoryFault { address: u64 },
+    DivideByZero { address: u64 },
+    // Stack overflow or underflow of the instruction at `address`.
+    StackFault { address: u64 },
+}
+
+impl Display for CPUFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CPUFault::IllegalInstruction { address, opcode } => write!(f, "Illegal instruction 0x{opcode:08X} at 0x{address:04X}"),
+            CPUFault::MemoryFault { address } => write!(f, "Memory fault at 0x{address:04X}"),
+            CPUFault::DivideByZero { address } => write!(f, "Divide by zero at 0x{address:04X}"),
+            CPUFault::StackFault { address } => write!(f, "Stack fault at 0x{address:04X}"),
+        }
+    }
+}
+
+impl std::error::Error for CPUFault {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    Continue,
+    Halted,
+    Fault(CPUFault),
+    Breakpoint,
+    WaitingForInterrupt,
+}
+
+impl StepResult {
+    pub fn is_continue(&self) -> bool {
+        *self == StepResult::Continue
+    }
+}
 
 pub trait CPUTrait {
-    fn step(&mut self);
+    fn step(&mut self) -> StepResult;
     fn reset(&mut self);
-}
\ No newline at end of file
+    // Stops the CPU, every following `step` returns `StepResult::Halted`
+    // until `reset`.
+    fn halt(&mut self);
+    // Moves the entry point to `entry` and resets the CPU to start there.
+    fn start_at(&mut self, entry: u64);
+
+    // Each step counts as one cycle. Returns the first result that isn't
+    // `Continue`, or `Continue` when the cycles ran out.
+    fn run_for(&mut self, cycles: u64) -> StepResult {
+        for _ in 0..cycles {
+            let result = self.step();
+            if !result.is_continue() {
+                return result;
+            }
+        }
+        StepResult::Continue
+    }
+
+    // Steps until `predicate` holds before the next step, or a step returns
+    // anything but `Continue`.
+    fn run_until(&mut self, mut predicate: impl FnMut(&Self) -> bool) -> StepResult
+    where
+        Self: Sized,
+    {
+        while !predicate(self) {
+            let result = self.step();
+            if !result.is_continue() {
+                return result;
+            }
+        }
+        StepResult::Continue
+    }
+}
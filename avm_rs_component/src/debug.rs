@@ -0,0 +1,30 @@
+use crate::{cpu::CPUTrait, register::{RegisterError, RegisterValue}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionKind {
+    Call,
+    Return,
+    Other,
+}
+
+// Implemented by CPUs that can be debugged. Registers are listed in a fixed
+// order, the program counter among them, and are set by their index in it.
+pub trait DebugTarget: CPUTrait {
+    fn pc(&self) -> u64;
+    fn set_pc(&mut self, pc: u64);
+    fn registers(&self) -> Vec<(&'static str, RegisterValue)>;
+    fn set_register(&mut self, index: usize, value: RegisterValue) -> Result<(), RegisterError>;
+
+    // Whether the instruction at `address` calls or returns, None when it
+    // can't be read or decoded.
+    fn instruction_kind(&self, address: u64) -> Option<InstructionKind>;
+
+    // Return addresses of the active calls, innermost first, as far as the
+    // CPU can tell. One that keeps them in a link register may only know the
+    // innermost one, or none once it returned.
+    fn return_addresses(&self) -> Vec<u64>;
+
+    fn register_index(&self, name: &str) -> Option<usize> {
+        self.registers().iter().position(|(register, _)| register.eq_ignore_ascii_case(name))
+    }
+}
@@ -0,0 +1,303 @@
+use std::fmt::Display;
+
+use avm_rs_memory::mem::MemorySliceTrait;
+
+use crate::cpu::CPUFault;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    // The instruction runs past the end of memory.
+    OutOfBounds { address: u64 },
+    // The instruction is longer than the reader's limit.
+    TooLong { address: u64, limit: usize },
+    VarintOverflow { address: u64 },
+    InvalidEncoding { address: u64, opcode: u32 },
+    // The decoder asked for an immediate size the reader doesn't support.
+    InvalidImmediateSize { address: u64, size: usize },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::OutOfBounds { address } => write!(f, "Instruction at 0x{address:04X} runs past the end of memory"),
+            DecodeError::TooLong { address, limit } => write!(f, "Instruction at 0x{address:04X} is longer than {limit} bytes"),
+            DecodeError::VarintOverflow { address } => write!(f, "Varint at 0x{address:04X} doesn't fit in 64 bits"),
+            DecodeError::InvalidEncoding { address, opcode } => write!(f, "Invalid encoding 0x{opcode:X} at 0x{address:04X}"),
+            DecodeError::InvalidImmediateSize { address, size } => write!(f, "Immediate of instruction at 0x{address:04X} is {size} bytes, but must be 1 to 8 bytes"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for CPUFault {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::OutOfBounds { address } => CPUFault::MemoryFault { address },
+            DecodeError::TooLong { address, .. } | DecodeError::VarintOverflow { address } | DecodeError::InvalidImmediateSize { address, .. } => CPUFault::IllegalInstruction { address, opcode: 0 },
+            DecodeError::InvalidEncoding { address, opcode } => CPUFault::IllegalInstruction { address, opcode },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+// x86-style operand byte: mode in bits 6-7, reg in bits 3-5, rm in bits 0-2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModRM {
+    pub mode: u8,
+    pub reg: u8,
+    pub rm: u8,
+}
+
+impl ModRM {
+    pub fn from_byte(byte: u8) -> Self {
+        Self {
+            mode: byte >> 6,
+            reg: (byte >> 3) & 0b111,
+            rm: byte & 0b111,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        (self.mode << 6) | ((self.reg & 0b111) << 3) | (self.rm & 0b111)
+    }
+}
+
+// Scale-index-base byte, laid out like `ModRM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sib {
+    pub scale: u8,
+    pub index: u8,
+    pub base: u8,
+}
+
+impl Sib {
+    pub fn from_byte(byte: u8) -> Self {
+        Self {
+            scale: byte >> 6,
+            index: (byte >> 3) & 0b111,
+            base: byte & 0b111,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        (self.scale << 6) | ((self.index & 0b111) << 3) | (self.base & 0b111)
+    }
+}
+
+// Reads one instruction byte by byte through the memory's fetch methods.
+// Every read checks bounds and the length limit, so a malformed or truncated
+// instruction is an error instead of a panic.
+pub struct InstructionReader<'a, M: MemorySliceTrait + ?Sized> {
+    memory: &'a M,
+    start: usize,
+    position: usize,
+    limit: usize,
+}
+
+impl<'a, M: MemorySliceTrait + ?Sized> InstructionReader<'a, M> {
+    pub fn new(memory: &'a M, at: usize) -> Self {
+        Self {
+            memory,
+            start: at,
+            position: at,
+            limit: usize::MAX,
+        }
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    // Bytes consumed so far.
+    pub fn length(&self) -> usize {
+        self.position - self.start
+    }
+
+    pub fn invalid(&self, opcode: u32) -> DecodeError {
+        DecodeError::InvalidEncoding { address: self.start as u64, opcode }
+    }
+
+    fn check(&self, length: usize) -> Result<(), DecodeError> {
+        if self.length().checked_add(length).is_none_or(|n| n > self.limit) {
+            return Err(DecodeError::TooLong { address: self.start as u64, limit: self.limit });
+        }
+        if self.position.checked_add(length).is_none_or(|end| end > self.memory.len()) {
+            return Err(DecodeError::OutOfBounds { address: self.start as u64 });
+        }
+        Ok(())
+    }
+
+    pub fn peek_u8(&self) -> Result<u8, DecodeError> {
+        self.check(1)?;
+        Ok(self.memory.fetch_u8(self.position))
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        let value = self.peek_u8()?;
+        self.position += 1;
+        Ok(value)
+    }
+
+    pub fn i8(&mut self) -> Result<i8, DecodeError> {
+        Ok(self.u8()? as i8)
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<Vec<u8>, DecodeError> {
+        self.check(length)?;
+        let bytes = self.memory.fetch_bytes(self.position, length);
+        self.position += length;
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, length: usize) -> Result<(), DecodeError> {
+        self.check(length)?;
+        self.position += length;
+        Ok(())
+    }
+
+    // Unsigned immediate of 1 to 8 bytes.
+    pub fn immediate(&mut self, size: usize, endian: Endian) -> Result<u64, DecodeError> {
+        if !(1..=8).contains(&size) {
+            return Err(DecodeError::InvalidImmediateSize { address: self.start as u64, size });
+        }
+        let bytes = self.bytes(size)?;
+        let fold = |value: u64, byte: &u8| (value << 8) | *byte as u64;
+        Ok(match endian {
+            Endian::Big => bytes.iter().fold(0, fold),
+            Endian::Little => bytes.iter().rev().fold(0, fold),
+        })
+    }
+
+    // Immediate of 1 to 8 bytes, sign-extended from its top bit.
+    pub fn signed_immediate(&mut self, size: usize, endian: Endian) -> Result<i64, DecodeError> {
+        let value = self.immediate(size, endian)?;
+        let shift = 64 - size as u32 * 8;
+        Ok(((value << shift) as i64) >> shift)
+    }
+
+    pub fn uleb128(&mut self) -> Result<u64, DecodeError> {
+        let address = self.position as u64;
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift == 63 && byte & 0x7E != 0 || shift > 63 {
+                return Err(DecodeError::VarintOverflow { address });
+            }
+            value |= ((byte & 0x7F) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    pub fn sleb128(&mut self) -> Result<i64, DecodeError> {
+        let address = self.position as u64;
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            // The 10th byte holds bit 63, its other bits must repeat it.
+            if shift == 63 && !matches!(byte & 0x7F, 0x00 | 0x7F) || shift > 63 {
+                return Err(DecodeError::VarintOverflow { address });
+            }
+            value |= ((byte & 0x7F) as i64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1i64 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    // Consumes the leading bytes found in `prefixes`, in the order read.
+    pub fn prefixes(&mut self, prefixes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let mut found = Vec::new();
+        while prefixes.contains(&self.peek_u8()?) {
+            found.push(self.u8()?);
+        }
+        Ok(found)
+    }
+
+    pub fn modrm(&mut self) -> Result<ModRM, DecodeError> {
+        Ok(ModRM::from_byte(self.u8()?))
+    }
+
+    pub fn sib(&mut self) -> Result<Sib, DecodeError> {
+        Ok(Sib::from_byte(self.u8()?))
+    }
+}
+
+pub fn encode_uleb128(mut value: u64, output: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+pub fn encode_sleb128(mut value: i64, output: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded<T> {
+    pub instruction: T,
+    pub address: usize,
+    pub length: usize,
+}
+
+impl<T> Decoded<T> {
+    pub fn next_address(&self) -> usize {
+        self.address + self.length
+    }
+}
+
+// Implemented once per instruction set, `decode` reads a single instruction.
+pub trait InstructionDecoder {
+    type Instruction;
+    // Longest valid instruction in bytes.
+    const MAX_LENGTH: usize;
+
+    fn decode<M: MemorySliceTrait + ?Sized>(&self, reader: &mut InstructionReader<M>) -> Result<Self::Instruction, DecodeError>;
+
+    fn decode_at<M: MemorySliceTrait + ?Sized>(&self, memory: &M, at: usize) -> Result<Decoded<Self::Instruction>, DecodeError> {
+        let mut reader = InstructionReader::new(memory, at).with_limit(Self::MAX_LENGTH);
+        let instruction = self.decode(&mut reader)?;
+        Ok(Decoded {
+            instruction,
+            address: at,
+            length: reader.length(),
+        })
+    }
+}
@@ -0,0 +1,122 @@
+use crate::register::{ArithmeticResult, IntegerRegisterTrait};
+
+#[macro_export]
+macro_rules! define_flags {
+    ( $name:ident, $type:ty, { $( $flag:ident = $bit:literal ),* $(,)? } ) => {
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct $name(pub $type);
+        impl $name {
+            $( pub const $flag: $type = 1 << $bit; )*
+            pub const NAMES: &'static [(&'static str, $type)] = &[ $( (stringify!($flag), 1 << $bit) ),* ];
+
+            pub fn contains(&self, flags: $type) -> bool {
+                self.0 & flags == flags
+            }
+            pub fn insert(&mut self, flags: $type) {
+                self.0 |= flags;
+            }
+            pub fn remove(&mut self, flags: $type) {
+                self.0 &= !flags;
+            }
+            pub fn toggle(&mut self, flags: $type) {
+                self.0 ^= flags;
+            }
+            pub fn set_flags(&mut self, flags: $type, value: bool) {
+                if value {
+                    self.insert(flags);
+                } else {
+                    self.remove(flags);
+                }
+            }
+            pub fn clear(&mut self) {
+                self.0 = 0;
+            }
+        }
+        impl $crate::register::RegisterTrait<$type> for $name {
+            fn get(&self) -> $type {
+                self.0
+            }
+            fn set(&mut self, value: $type) {
+                self.0 = value;
+            }
+        }
+        impl $crate::register::DynRegisterTrait for $name {
+            fn width(&self) -> u32 {
+                <$type as $crate::register::RegisterPrimitive>::BITS
+            }
+            fn value(&self) -> $crate::register::RegisterValue {
+                $crate::register::RegisterPrimitive::to_value(self.0)
+            }
+            fn bits(&self) -> u128 {
+                $crate::register::RegisterPrimitive::to_bits(self.0)
+            }
+            fn set_bits(&mut self, bits: u128) {
+                self.0 = <$type as $crate::register::RegisterPrimitive>::from_bits(bits);
+            }
+        }
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let names = Self::NAMES.iter()
+                    .filter(|(_, flag)| self.contains(*flag))
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>();
+                if names.is_empty() {
+                    write!(f, "-")
+                } else {
+                    write!(f, "{}", names.join("|"))
+                }
+            }
+        }
+    };
+}
+
+// Bit positions follow x86 EFLAGS.
+define_flags!(FlagsRegister, u32, {
+    CARRY = 0,
+    PARITY = 2,
+    ZERO = 6,
+    SIGN = 7,
+    INTERRUPT_ENABLE = 9,
+    OVERFLOW = 11,
+});
+
+impl FlagsRegister {
+    pub fn carry(&self) -> bool {
+        self.contains(Self::CARRY)
+    }
+
+    pub fn parity(&self) -> bool {
+        self.contains(Self::PARITY)
+    }
+
+    pub fn zero(&self) -> bool {
+        self.contains(Self::ZERO)
+    }
+
+    pub fn sign(&self) -> bool {
+        self.contains(Self::SIGN)
+    }
+
+    pub fn interrupt_enable(&self) -> bool {
+        self.contains(Self::INTERRUPT_ENABLE)
+    }
+
+    pub fn overflow(&self) -> bool {
+        self.contains(Self::OVERFLOW)
+    }
+
+    // Sets zero, sign and parity from `value` and clears carry and overflow,
+    // which is what logic and move instructions usually do.
+    pub fn update_from_value<R: IntegerRegisterTrait>(&mut self, value: &R) {
+        self.set_flags(Self::ZERO, value.is_zero());
+        self.set_flags(Self::SIGN, value.is_negative());
+        self.set_flags(Self::PARITY, value.has_even_parity());
+        self.remove(Self::CARRY | Self::OVERFLOW);
+    }
+
+    pub fn update_from_result<R: IntegerRegisterTrait>(&mut self, result: &ArithmeticResult<R>) {
+        self.update_from_value(&result.value);
+        self.set_flags(Self::CARRY, result.carry);
+        self.set_flags(Self::OVERFLOW, result.overflow);
+    }
+}
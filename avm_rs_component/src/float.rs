@@ -0,0 +1,477 @@
+use std::cmp::Ordering;
+
+use crate::define_flags;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    #[default]
+    NearestEven,
+    TowardZero,
+    Up,
+    Down,
+}
+
+// Bit positions follow the x86 MXCSR exception flags.
+define_flags!(FloatExceptions, u8, {
+    INVALID = 0,
+    DIVIDE_BY_ZERO = 2,
+    OVERFLOW = 3,
+    UNDERFLOW = 4,
+    INEXACT = 5,
+});
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NanMode {
+    // The first NaN operand is returned, quieted.
+    #[default]
+    Propagate,
+    // Every NaN result is the canonical quiet NaN.
+    Canonical,
+}
+
+pub trait FloatOperand: Copy + PartialOrd + std::ops::Neg<Output = Self> {
+    const ZERO: Self;
+    const MAX: Self;
+    const MIN_POSITIVE: Self;
+    const CANONICAL_NAN: Self;
+
+    fn is_nan(self) -> bool;
+    fn is_signaling(self) -> bool;
+    fn quiet(self) -> Self;
+    fn is_finite(self) -> bool;
+    fn is_infinite(self) -> bool;
+    fn is_sign_positive(self) -> bool;
+    fn abs(self) -> Self;
+    fn next_up(self) -> Self;
+    fn next_down(self) -> Self;
+
+    fn host_add(self, other: Self) -> Self;
+    fn host_mul(self, other: Self) -> Self;
+    fn host_div(self, other: Self) -> Self;
+    fn host_sqrt(self) -> Self;
+
+    // Each returns the sign of `exact - result`, where `result` is the host's
+    // round-to-nearest-even answer for the operation.
+    fn add_error(self, other: Self, result: Self) -> Ordering;
+    fn mul_error(self, other: Self, result: Self) -> Ordering;
+    fn div_error(self, other: Self, result: Self) -> Ordering;
+    fn sqrt_error(self, result: Self) -> Ordering;
+}
+
+fn sign_of<T: PartialOrd + Default>(value: T) -> Ordering {
+    value.partial_cmp(&T::default()).unwrap_or(Ordering::Equal)
+}
+
+macro_rules! impl_float_operand {
+    ( $type:ident, $quiet_bit:literal, $canonical:literal ) => {
+        impl FloatOperand for $type {
+            const ZERO: Self = 0.0;
+            const MAX: Self = $type::MAX;
+            const MIN_POSITIVE: Self = $type::MIN_POSITIVE;
+            const CANONICAL_NAN: Self = $type::from_bits($canonical);
+
+            fn is_nan(self) -> bool {
+                $type::is_nan(self)
+            }
+            fn is_signaling(self) -> bool {
+                self.is_nan() && self.to_bits() & (1 << $quiet_bit) == 0
+            }
+            fn quiet(self) -> Self {
+                $type::from_bits(self.to_bits() | (1 << $quiet_bit))
+            }
+            fn is_finite(self) -> bool {
+                $type::is_finite(self)
+            }
+            fn is_infinite(self) -> bool {
+                $type::is_infinite(self)
+            }
+            fn is_sign_positive(self) -> bool {
+                $type::is_sign_positive(self)
+            }
+            fn abs(self) -> Self {
+                $type::abs(self)
+            }
+            fn next_up(self) -> Self {
+                $type::next_up(self)
+            }
+            fn next_down(self) -> Self {
+                $type::next_down(self)
+            }
+
+            fn host_add(self, other: Self) -> Self {
+                self + other
+            }
+            fn host_mul(self, other: Self) -> Self {
+                self * other
+            }
+            fn host_div(self, other: Self) -> Self {
+                self / other
+            }
+            fn host_sqrt(self) -> Self {
+                self.sqrt()
+            }
+
+            fn add_error(self, other: Self, result: Self) -> Ordering {
+                let other_part = result - self;
+                let error = (self - (result - other_part)) + (other - other_part);
+                sign_of(error)
+            }
+            fn mul_error(self, other: Self, result: Self) -> Ordering {
+                impl_float_operand!(@mul_error $type, self, other, result)
+            }
+            fn div_error(self, other: Self, result: Self) -> Ordering {
+                let remainder = impl_float_operand!(@residual $type, result, other, self);
+                if other.is_sign_positive() { remainder } else { remainder.reverse() }
+            }
+            fn sqrt_error(self, result: Self) -> Ordering {
+                impl_float_operand!(@residual $type, result, result, self)
+            }
+        }
+    };
+    // The product of two f32 is exact in f64, f64 products need a fused
+    // multiply-add to recover the rounding error. Products too small for
+    // even the scaled one to hold round to zero, the error then has their
+    // sign.
+    ( @mul_error f32, $a:expr, $b:expr, $result:expr ) => {
+        sign_of(($a as f64) * ($b as f64) - ($result as f64))
+    };
+    ( @mul_error f64, $a:expr, $b:expr, $result:expr ) => {{
+        let (a, b, result) = ($a, $b, $result);
+        if result == 0.0 && a != 0.0 && b != 0.0 {
+            if a.is_sign_positive() == b.is_sign_positive() { Ordering::Greater } else { Ordering::Less }
+        } else if result.abs() >= TINY_F64 {
+            sign_of(a.mul_add(b, -result))
+        } else if a.abs() < b.abs() {
+            sign_of((a * SCALE_F64).mul_add(b, -(result * SCALE_F64)))
+        } else {
+            sign_of(a.mul_add(b * SCALE_F64, -(result * SCALE_F64)))
+        }
+    }};
+    // Sign of `c - a * b`.
+    ( @residual f32, $a:expr, $b:expr, $c:expr ) => {
+        sign_of((-($a as f64)).mul_add($b as f64, $c as f64))
+    };
+    ( @residual f64, $a:expr, $b:expr, $c:expr ) => {{
+        let (a, b, c) = ($a, $b, $c);
+        if a.abs() >= TINY_F64 {
+            sign_of((-a).mul_add(b, c))
+        } else {
+            sign_of((-(a * SCALE_F64)).mul_add(b, c * SCALE_F64))
+        }
+    }};
+}
+
+// Near the subnormal range the f64 error terms would underflow to zero, so
+// they are computed on operands scaled up by an exact power of two.
+const TINY_F64: f64 = f64::from_bits((1023 - 900) << 52);
+const SCALE_F64: f64 = f64::from_bits((1023 + 110) << 52);
+
+impl_float_operand!(f32, 22, 0x7FC0_0000);
+impl_float_operand!(f64, 51, 0x7FF8_0000_0000_0000);
+
+// Floating-point control and status: the rounding mode and NaN policy used by
+// every operation, and the sticky exception flags they raise. Results don't
+// depend on the host's rounding mode or NaN propagation rules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FloatControl {
+    pub rounding: RoundingMode,
+    pub nan_mode: NanMode,
+    pub exceptions: FloatExceptions,
+}
+
+impl FloatControl {
+    pub fn new(rounding: RoundingMode, nan_mode: NanMode) -> Self {
+        Self {
+            rounding,
+            nan_mode,
+            exceptions: FloatExceptions::default(),
+        }
+    }
+
+    pub fn clear_exceptions(&mut self) {
+        self.exceptions.clear();
+    }
+
+    fn nan_result<F: FloatOperand>(&mut self, operands: &[F]) -> Option<F> {
+        let first = operands.iter().copied().find(|x| x.is_nan())?;
+        if operands.iter().any(|x| x.is_signaling()) {
+            self.exceptions.insert(FloatExceptions::INVALID);
+        }
+        Some(match self.nan_mode {
+            NanMode::Propagate => first.quiet(),
+            NanMode::Canonical => F::CANONICAL_NAN,
+        })
+    }
+
+    fn round<F: FloatOperand>(&mut self, result: F, operands_finite: bool, error: impl FnOnce() -> Ordering) -> F {
+        if result.is_nan() {
+            self.exceptions.insert(FloatExceptions::INVALID);
+            return F::CANONICAL_NAN;
+        }
+
+        if result.is_infinite() {
+            if !operands_finite {
+                return result;
+            }
+            self.exceptions.insert(FloatExceptions::OVERFLOW | FloatExceptions::INEXACT);
+            let positive = result.is_sign_positive();
+            return match self.rounding {
+                RoundingMode::NearestEven => result,
+                RoundingMode::Up if positive => result,
+                RoundingMode::Down if !positive => result,
+                _ if positive => F::MAX,
+                _ => -F::MAX,
+            };
+        }
+
+        let error = error();
+        if error == Ordering::Equal {
+            return result;
+        }
+        self.exceptions.insert(FloatExceptions::INEXACT);
+
+        let rounded = match (self.rounding, error) {
+            (RoundingMode::TowardZero, Ordering::Less) if result > F::ZERO => result.next_down(),
+            (RoundingMode::TowardZero, Ordering::Greater) if result < F::ZERO => result.next_up(),
+            (RoundingMode::Up, Ordering::Greater) => result.next_up(),
+            (RoundingMode::Down, Ordering::Less) => result.next_down(),
+            _ => result,
+        };
+        if rounded.is_infinite() {
+            self.exceptions.insert(FloatExceptions::OVERFLOW);
+        }
+        if rounded.abs() < F::MIN_POSITIVE {
+            self.exceptions.insert(FloatExceptions::UNDERFLOW);
+        }
+        rounded
+    }
+
+    pub fn add<F: FloatOperand>(&mut self, a: F, b: F) -> F {
+        if let Some(nan) = self.nan_result(&[a, b]) {
+            return nan;
+        }
+        let result = a.host_add(b);
+        // An exact zero sum is -0 when rounding down, unless both operands are +0.
+        if result == F::ZERO && self.rounding == RoundingMode::Down && !(a == F::ZERO && b == F::ZERO && a.is_sign_positive() && b.is_sign_positive()) {
+            return -F::ZERO;
+        }
+        self.round(result, a.is_finite() && b.is_finite(), || a.add_error(b, result))
+    }
+
+    pub fn sub<F: FloatOperand>(&mut self, a: F, b: F) -> F {
+        if let Some(nan) = self.nan_result(&[a, b]) {
+            return nan;
+        }
+        self.add(a, -b)
+    }
+
+    pub fn mul<F: FloatOperand>(&mut self, a: F, b: F) -> F {
+        if let Some(nan) = self.nan_result(&[a, b]) {
+            return nan;
+        }
+        let result = a.host_mul(b);
+        self.round(result, a.is_finite() && b.is_finite(), || a.mul_error(b, result))
+    }
+
+    pub fn div<F: FloatOperand>(&mut self, a: F, b: F) -> F {
+        if let Some(nan) = self.nan_result(&[a, b]) {
+            return nan;
+        }
+        let result = a.host_div(b);
+        if b == F::ZERO && a != F::ZERO && a.is_finite() {
+            self.exceptions.insert(FloatExceptions::DIVIDE_BY_ZERO);
+            return result;
+        }
+        self.round(result, a.is_finite() && b.is_finite(), || a.div_error(b, result))
+    }
+
+    pub fn sqrt<F: FloatOperand>(&mut self, a: F) -> F {
+        if let Some(nan) = self.nan_result(&[a]) {
+            return nan;
+        }
+        let result = a.host_sqrt();
+        self.round(result, a.is_finite(), || a.sqrt_error(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [RoundingMode; 4] = [RoundingMode::NearestEven, RoundingMode::TowardZero, RoundingMode::Up, RoundingMode::Down];
+
+    fn control(rounding: RoundingMode) -> FloatControl {
+        FloatControl::new(rounding, NanMode::Propagate)
+    }
+
+    // The result of `operation` in every rounding mode, in the order of
+    // `MODES`, with the exceptions it raised.
+    fn each_mode<F>(operation: impl Fn(&mut FloatControl) -> F) -> Vec<(F, u8)> {
+        MODES.iter().map(|&mode| {
+            let mut control = control(mode);
+            let result = operation(&mut control);
+            (result, control.exceptions.0)
+        }).collect()
+    }
+
+    fn bits32(results: &[(f32, u8)]) -> Vec<(u32, u8)> {
+        results.iter().map(|&(x, flags)| (x.to_bits(), flags)).collect()
+    }
+
+    fn bits64(results: &[(f64, u8)]) -> Vec<(u64, u8)> {
+        results.iter().map(|&(x, flags)| (x.to_bits(), flags)).collect()
+    }
+
+    // Nearest, toward zero, up and down for the f32 nearest to `exact`,
+    // with the flags an inexact result raises.
+    fn expected32(exact: f64, nearest: f32, down: f32, up: f32, flags: u8) -> Vec<(u32, u8)> {
+        let toward_zero = if exact > 0.0 { down } else { up };
+        bits32(&[nearest, toward_zero, up, down].map(|x| (x, flags | FloatExceptions::INEXACT)))
+    }
+
+    const INEXACT: u8 = FloatExceptions::INEXACT;
+    const UNDERFLOW: u8 = FloatExceptions::UNDERFLOW | FloatExceptions::INEXACT;
+    const OVERFLOW: u8 = FloatExceptions::OVERFLOW | FloatExceptions::INEXACT;
+
+    #[test]
+    fn rounds_inexact_operations() {
+        // 1 + 2^-30 is closer to 1, 1 + 2^-22 + 2^-46 to 1 + 2^-22.
+        let tiny = f32::from_bits((127 - 30) << 23);
+        let one = 1.0f32;
+        assert_eq!(bits32(&each_mode(|c| c.add(one, tiny))), expected32(1.0, one, one, one.next_up(), 0));
+        assert_eq!(bits32(&each_mode(|c| c.sub(-one, tiny))), expected32(-1.0, -one, (-one).next_down(), -one, 0));
+        let wide = one.next_up();
+        let square = 1.0 + f32::EPSILON * 2.0;
+        assert_eq!(bits32(&each_mode(|c| c.mul(wide, wide))), expected32(1.0, square, square, square.next_up(), 0));
+        assert_eq!(bits32(&each_mode(|c| c.mul(-wide, wide))), expected32(-1.0, -square, (-square).next_down(), -square, 0));
+
+        // 1/3 and sqrt(2) round up to the nearest value.
+        let third = 1.0f32 / 3.0;
+        assert_eq!(bits32(&each_mode(|c| c.div(1.0f32, 3.0))), expected32(1.0, third, third.next_down(), third, 0));
+        assert_eq!(bits32(&each_mode(|c| c.div(-1.0f32, 3.0))), expected32(-1.0, -third, -third, (-third).next_up(), 0));
+        let root = 2.0f64.sqrt();
+        assert_eq!(bits64(&each_mode(|c| c.sqrt(2.0f64))), bits64(&[(root, INEXACT), (root.next_down(), INEXACT), (root, INEXACT), (root.next_down(), INEXACT)]));
+        let third = 1.0f64 / 3.0;
+        assert_eq!(bits64(&each_mode(|c| c.div(1.0f64, -3.0))), bits64(&[(-third, INEXACT), (-third, INEXACT), (-third, INEXACT), ((-third).next_down(), INEXACT)]));
+    }
+
+    #[test]
+    fn rounds_to_zero_and_subnormals() {
+        // Half the smallest subnormal is a tie, nearest-even takes the zero.
+        let smallest = f32::from_bits(1);
+        assert_eq!(bits32(&each_mode(|c| c.mul(smallest, 0.5))), expected32(1.0, 0.0, 0.0, smallest, FloatExceptions::UNDERFLOW));
+        assert_eq!(bits32(&each_mode(|c| c.mul(-smallest, 0.5))), expected32(-1.0, -0.0, -smallest, -0.0, FloatExceptions::UNDERFLOW));
+        let smallest = f64::from_bits(1);
+        let results = each_mode(|c| c.mul(f64::MIN_POSITIVE, f64::MIN_POSITIVE));
+        assert_eq!(bits64(&results), bits64(&[(0.0, UNDERFLOW), (0.0, UNDERFLOW), (smallest, UNDERFLOW), (0.0, UNDERFLOW)]));
+        let results = each_mode(|c| c.div(-f64::MIN_POSITIVE, f64::MAX));
+        assert_eq!(bits64(&results), bits64(&[(-0.0, UNDERFLOW), (-0.0, UNDERFLOW), (-0.0, UNDERFLOW), (-smallest, UNDERFLOW)]));
+
+        // MIN_POSITIVE / 3 lands between two subnormals, closer to the upper
+        // one for f32 and to the lower one for f64.
+        let third = f32::MIN_POSITIVE / 3.0;
+        assert_eq!(bits32(&each_mode(|c| c.div(f32::MIN_POSITIVE, 3.0))), expected32(1.0, third, third.next_down(), third, FloatExceptions::UNDERFLOW));
+        let third = f64::MIN_POSITIVE / 3.0;
+        assert_eq!(bits64(&each_mode(|c| c.div(f64::MIN_POSITIVE, 3.0))), bits64(&[(third, UNDERFLOW), (third, UNDERFLOW), (third.next_up(), UNDERFLOW), (third, UNDERFLOW)]));
+
+        // Exact subnormals don't underflow.
+        assert_eq!(each_mode(|c| c.mul(f32::MIN_POSITIVE, 0.75)), [(f32::MIN_POSITIVE * 0.75, 0); 4]);
+
+        // Exact zero sums are +0, except when rounding down.
+        assert_eq!(bits64(&each_mode(|c| c.add(1.5f64, -1.5))), bits64(&[(0.0, 0), (0.0, 0), (0.0, 0), (-0.0, 0)]));
+        assert_eq!(bits64(&each_mode(|c| c.add(-0.0f64, -0.0))), bits64(&[(-0.0, 0); 4]));
+    }
+
+    #[test]
+    fn rounds_overflow_to_infinity_or_max() {
+        let results = each_mode(|c| c.mul(f32::MAX, 2.0));
+        assert_eq!(bits32(&results), bits32(&[(f32::INFINITY, OVERFLOW), (f32::MAX, OVERFLOW), (f32::INFINITY, OVERFLOW), (f32::MAX, OVERFLOW)]));
+        let results = each_mode(|c| c.add(-f64::MAX, -f64::MAX));
+        assert_eq!(bits64(&results), bits64(&[(f64::NEG_INFINITY, OVERFLOW), (-f64::MAX, OVERFLOW), (-f64::MAX, OVERFLOW), (f64::NEG_INFINITY, OVERFLOW)]));
+        let results = each_mode(|c| c.div(f64::MAX, 0.5));
+        assert_eq!(bits64(&results), bits64(&[(f64::INFINITY, OVERFLOW), (f64::MAX, OVERFLOW), (f64::INFINITY, OVERFLOW), (f64::MAX, OVERFLOW)]));
+
+        // Just past MAX is closer to MAX, only rounding up overflows.
+        let ulp = f32::MAX - f32::MAX.next_down();
+        let results = each_mode(|c| c.add(f32::MAX, ulp / 4.0));
+        assert_eq!(bits32(&results), bits32(&[(f32::MAX, INEXACT), (f32::MAX, INEXACT), (f32::INFINITY, OVERFLOW), (f32::MAX, INEXACT)]));
+
+        // Infinite operands aren't an overflow.
+        assert_eq!(each_mode(|c| c.mul(f64::INFINITY, 2.0)), [(f64::INFINITY, 0); 4]);
+        let results = each_mode(|c| c.div(1.0f64, -0.0));
+        assert_eq!(results, [(f64::NEG_INFINITY, FloatExceptions::DIVIDE_BY_ZERO); 4]);
+    }
+
+    type Operation = fn(&mut FloatControl, f32, f32) -> f32;
+
+    // Against the exact result, which f64 holds for f32 sums and products and
+    // is close enough to for quotients and square roots to round the same.
+    #[test]
+    fn matches_exact_f32_results() {
+        let values = [1.0f32, 3.0, 0.1, -7.25, 1e-3, 1.5e30, -3.4e38, 1e-40, 2.0e-45, 123456.79, -0.33333334, 16777217.0];
+        for &a in &values {
+            for &b in &values {
+                let (a64, b64) = (a as f64, b as f64);
+                // Sums of operands too far apart aren't exact in f64 either.
+                let sum = Some(a64 + b64).filter(|&x| x - a64 == b64 && x - b64 == a64);
+                let cases: [(Option<f64>, Operation); 4] = [
+                    (sum, |c, a, b| c.add(a, b)),
+                    (Some(a64 * b64), |c, a, b| c.mul(a, b)),
+                    (Some(a64 / b64), |c, a, b| c.div(a, b)),
+                    (Some(a64.abs().sqrt()), |c, a, _| c.sqrt(a.abs())),
+                ];
+                for (index, (exact, operation)) in cases.into_iter().enumerate() {
+                    let Some(exact) = exact else {
+                        continue;
+                    };
+                    let nearest = exact as f32;
+                    let (down, up) = match (nearest as f64).partial_cmp(&exact).unwrap() {
+                        Ordering::Equal => (nearest, nearest),
+                        Ordering::Less => (nearest, nearest.next_up()),
+                        Ordering::Greater => (nearest.next_down(), nearest),
+                    };
+                    let toward_zero = if exact > 0.0 { down } else { up };
+                    for (mode, expected) in MODES.iter().zip([nearest, toward_zero, up, down]) {
+                        let result = operation(&mut control(*mode), a, b);
+                        assert_eq!(result.to_bits(), expected.to_bits(), "operation {index} on {a:e} and {b:e} in {mode:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn propagates_or_canonicalizes_nans() {
+        let quiet = f64::from_bits(0x7FF8_0000_0000_1234);
+        let signaling = f32::from_bits(0x7F80_0042);
+        let canonical = f32::CANONICAL_NAN.to_bits();
+
+        let mut control = control(RoundingMode::NearestEven);
+        assert_eq!(control.add(1.0, quiet).to_bits(), quiet.to_bits());
+        assert_eq!(control.mul(-quiet, quiet).to_bits(), (-quiet).to_bits());
+        assert_eq!(control.exceptions.0, 0);
+        assert_eq!(control.sqrt(signaling).to_bits(), 0x7FC0_0042);
+        assert_eq!(control.exceptions.0, FloatExceptions::INVALID);
+
+        // The first NaN wins, but a signaling one anywhere is invalid.
+        control.clear_exceptions();
+        assert_eq!(control.div(f32::NAN, signaling).to_bits(), f32::NAN.to_bits());
+        assert_eq!(control.exceptions.0, FloatExceptions::INVALID);
+
+        let mut control = FloatControl::new(RoundingMode::Up, NanMode::Canonical);
+        assert_eq!(control.sub(quiet, 1.0).to_bits(), f64::CANONICAL_NAN.to_bits());
+        assert_eq!(control.exceptions.0, 0);
+        assert_eq!(control.add(1.0, signaling).to_bits(), canonical);
+        assert_eq!(control.exceptions.0, FloatExceptions::INVALID);
+
+        // Invalid operations give the canonical NaN in either mode.
+        for nan_mode in [NanMode::Propagate, NanMode::Canonical] {
+            let operations: [fn(&mut FloatControl) -> f32; 4] = [|c| c.sub(f32::INFINITY, f32::INFINITY), |c| c.mul(0.0, f32::INFINITY), |c| c.div(0.0, -0.0), |c| c.sqrt(-1.0)];
+            for operation in operations {
+                let mut control = FloatControl::new(RoundingMode::NearestEven, nan_mode);
+                assert_eq!(operation(&mut control).to_bits(), canonical);
+                assert_eq!(control.exceptions.0, FloatExceptions::INVALID);
+            }
+        }
+    }
+}
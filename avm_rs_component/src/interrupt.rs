@@ -0,0 +1,282 @@
+use std::sync::{Arc, Mutex};
+
+use avm_rs_memory::{access_memory, mem::Memory};
+
+use crate::cpu::{CPUFault, CPUTrait, StepResult};
+
+// Vectors below `IRQ_BASE` are reserved for synchronous exceptions, line `n`
+// of the interrupt controller is delivered through vector `IRQ_BASE + n`.
+pub const DIVIDE_BY_ZERO_VECTOR: usize = 0;
+pub const ILLEGAL_INSTRUCTION_VECTOR: usize = 1;
+pub const MEMORY_FAULT_VECTOR: usize = 2;
+pub const STACK_FAULT_VECTOR: usize = 3;
+pub const IRQ_BASE: usize = 16;
+
+pub fn exception_vector(fault: &CPUFault) -> usize {
+    match fault {
+        CPUFault::DivideByZero { .. } => DIVIDE_BY_ZERO_VECTOR,
+        CPUFault::IllegalInstruction { .. } => ILLEGAL_INSTRUCTION_VECTOR,
+        CPUFault::MemoryFault { .. } => MEMORY_FAULT_VECTOR,
+        CPUFault::StackFault { .. } => STACK_FAULT_VECTOR,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterruptLine {
+    pub priority: u8,
+    pub masked: bool,
+    pub pending: bool,
+}
+
+// `raise` latches the line as pending until it is acknowledged or cleared.
+// Higher priorities are delivered first, ties go to the lowest line number.
+#[derive(Debug, Clone)]
+pub struct InterruptController {
+    lines: Vec<InterruptLine>,
+}
+
+impl InterruptController {
+    pub fn new(lines: usize) -> Self {
+        Self {
+            lines: vec![InterruptLine::default(); lines],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    fn line(&self, line: usize) -> &InterruptLine {
+        self.lines.get(line).unwrap_or_else(|| panic!("Interrupt controller has {} lines, but trying to access line {line}", self.lines.len()))
+    }
+
+    fn line_mut(&mut self, line: usize) -> &mut InterruptLine {
+        let count = self.lines.len();
+        self.lines.get_mut(line).unwrap_or_else(|| panic!("Interrupt controller has {count} lines, but trying to access line {line}"))
+    }
+
+    pub fn state(&self, line: usize) -> InterruptLine {
+        *self.line(line)
+    }
+
+    pub fn set_priority(&mut self, line: usize, priority: u8) {
+        self.line_mut(line).priority = priority;
+    }
+
+    pub fn mask(&mut self, line: usize) {
+        self.line_mut(line).masked = true;
+    }
+
+    pub fn unmask(&mut self, line: usize) {
+        self.line_mut(line).masked = false;
+    }
+
+    pub fn mask_all(&mut self) {
+        self.lines.iter_mut().for_each(|line| line.masked = true);
+    }
+
+    pub fn unmask_all(&mut self) {
+        self.lines.iter_mut().for_each(|line| line.masked = false);
+    }
+
+    pub fn raise(&mut self, line: usize) {
+        self.line_mut(line).pending = true;
+    }
+
+    pub fn clear(&mut self, line: usize) {
+        self.line_mut(line).pending = false;
+    }
+
+    // The line that would be delivered next, without acknowledging it.
+    pub fn pending(&self) -> Option<usize> {
+        self.lines.iter()
+            .enumerate()
+            .filter(|(_, line)| line.pending && !line.masked)
+            .min_by_key(|(index, line)| (std::cmp::Reverse(line.priority), *index))
+            .map(|(index, _)| index)
+    }
+
+    pub fn acknowledge(&mut self) -> Option<usize> {
+        let line = self.pending()?;
+        self.lines[line].pending = false;
+        Some(line)
+    }
+
+    pub fn reset(&mut self) {
+        self.lines.fill(InterruptLine::default());
+    }
+}
+
+pub type SharedInterruptController = Arc<Mutex<InterruptController>>;
+
+pub fn create_interrupt_controller(lines: usize) -> SharedInterruptController {
+    Arc::new(Mutex::new(InterruptController::new(lines)))
+}
+
+// Handed to a device so it can raise its own line and nothing else.
+#[derive(Debug, Clone)]
+pub struct InterruptHandle {
+    controller: SharedInterruptController,
+    line: usize,
+}
+
+impl InterruptHandle {
+    pub fn new(controller: &SharedInterruptController, line: usize) -> Self {
+        controller.lock().unwrap().line(line);
+        Self {
+            controller: controller.clone(),
+            line,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn raise(&self) {
+        self.controller.lock().unwrap().raise(self.line);
+    }
+
+    pub fn clear(&self) {
+        self.controller.lock().unwrap().clear(self.line);
+    }
+}
+
+// Table of u64 handler addresses in guest memory, entry `n` at
+// `base + n * 8`. A zero entry means the vector has no handler.
+#[derive(Debug, Clone)]
+pub struct InterruptVectorTable {
+    memory: Memory,
+    base: usize,
+    vectors: usize,
+}
+
+impl InterruptVectorTable {
+    pub fn new(memory: Memory, base: usize, vectors: usize) -> Self {
+        access_memory!(memory).assert_access_range(base, vectors * 8);
+        Self { memory, base, vectors }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors == 0
+    }
+
+    fn assert_vector(&self, vector: usize) {
+        if vector >= self.vectors {
+            panic!("Interrupt vector table has {} vectors, but trying to access vector {vector}", self.vectors);
+        }
+    }
+
+    pub fn handler(&self, vector: usize) -> Option<u64> {
+        self.assert_vector(vector);
+        let handler = access_memory!(self.memory).read_u64(self.base + vector * 8);
+        (handler != 0).then_some(handler)
+    }
+
+    pub fn set_handler(&self, vector: usize, handler: u64) {
+        self.assert_vector(vector);
+        access_memory!(self.memory).write_u64(self.base + vector * 8, handler);
+    }
+}
+
+// Implemented by CPUs that can take interrupts. `enter_interrupt` saves
+// whatever the CPU needs to return from the handler and jumps to it, or
+// faults without changing any state when it can't save it.
+pub trait InterruptTarget: CPUTrait {
+    fn interrupts_enabled(&self) -> bool;
+    fn enter_interrupt(&mut self, vector: usize, handler: u64) -> Result<(), CPUFault>;
+}
+
+// Connects an interrupt controller and a vector table to a CPU: pending lines
+// are delivered between steps, and faults with a handler become exceptions.
+#[derive(Debug, Clone)]
+pub struct InterruptSystem {
+    pub controller: SharedInterruptController,
+    pub table: InterruptVectorTable,
+}
+
+impl InterruptSystem {
+    // The table needs a vector for every exception and every line.
+    pub fn new(controller: SharedInterruptController, table: InterruptVectorTable) -> Self {
+        let lines = controller.lock().unwrap().len();
+        if table.len() < IRQ_BASE + lines {
+            panic!("Interrupt vector table has {} vectors, but {lines} lines need {}", table.len(), IRQ_BASE + lines);
+        }
+        Self { controller, table }
+    }
+
+    pub fn handle(&self, line: usize) -> InterruptHandle {
+        InterruptHandle::new(&self.controller, line)
+    }
+
+    // Delivers the highest priority pending line, if the CPU accepts
+    // interrupts. A line whose vector has no handler stays pending until one
+    // is installed, and so does a line the CPU faulted entering.
+    //
+    // The controller isn't locked while guest memory is, a device may raise
+    // its line with the memory locked.
+    pub fn poll<C: InterruptTarget>(&self, cpu: &mut C) -> Result<bool, CPUFault> {
+        if !cpu.interrupts_enabled() {
+            return Ok(false);
+        }
+        let pending = self.controller.lock().unwrap().pending();
+        let Some(line) = pending else {
+            return Ok(false);
+        };
+        let vector = IRQ_BASE + line;
+        let Some(handler) = self.table.handler(vector) else {
+            return Ok(false);
+        };
+        cpu.enter_interrupt(vector, handler)?;
+        self.controller.lock().unwrap().clear(line);
+        Ok(true)
+    }
+
+    // Returns false when the vector of the fault has no handler.
+    pub fn raise_exception<C: InterruptTarget>(&self, cpu: &mut C, fault: &CPUFault) -> Result<bool, CPUFault> {
+        let vector = exception_vector(fault);
+        match self.table.handler(vector) {
+            Some(handler) => cpu.enter_interrupt(vector, handler).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    // Unhandled faults are returned to the caller, and so are faults entering
+    // a handler. A CPU that returned `WaitingForInterrupt` should resume once
+    // `enter_interrupt` is called.
+    pub fn step<C: InterruptTarget>(&self, cpu: &mut C) -> StepResult {
+        if let Err(fault) = self.poll(cpu) {
+            return StepResult::Fault(fault);
+        }
+        match cpu.step() {
+            StepResult::Fault(fault) => match self.raise_exception(cpu, &fault) {
+                Ok(true) => StepResult::Continue,
+                Ok(false) => StepResult::Fault(fault),
+                Err(fault) => StepResult::Fault(fault),
+            },
+            result => result,
+        }
+    }
+
+    pub fn run_for<C: InterruptTarget>(&self, cpu: &mut C, cycles: u64) -> StepResult {
+        for _ in 0..cycles {
+            let result = self.step(cpu);
+            if !result.is_continue() {
+                return result;
+            }
+        }
+        StepResult::Continue
+    }
+}
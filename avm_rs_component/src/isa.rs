@@ -0,0 +1,316 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    Register,
+    FloatRegister,
+    Immediate,
+    SignedImmediate,
+}
+
+// A typed view of an instruction field. `from_field` receives the raw `width`
+// bits, `to_field` must return a value that fits in them.
+pub trait Operand: Copy + Display {
+    const KIND: OperandKind;
+    fn from_field(bits: u64, width: u32) -> Self;
+    fn to_field(self, width: u32) -> u64;
+}
+
+fn field_mask(width: u32) -> u64 {
+    if width >= 64 { u64::MAX } else { (1u64 << width) - 1 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reg(pub u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FReg(pub u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Imm(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SImm(pub i64);
+
+impl Operand for Reg {
+    const KIND: OperandKind = OperandKind::Register;
+    fn from_field(bits: u64, _: u32) -> Self {
+        Self(bits as u8)
+    }
+    fn to_field(self, width: u32) -> u64 {
+        self.0 as u64 & field_mask(width)
+    }
+}
+
+impl Operand for FReg {
+    const KIND: OperandKind = OperandKind::FloatRegister;
+    fn from_field(bits: u64, _: u32) -> Self {
+        Self(bits as u8)
+    }
+    fn to_field(self, width: u32) -> u64 {
+        self.0 as u64 & field_mask(width)
+    }
+}
+
+impl Operand for Imm {
+    const KIND: OperandKind = OperandKind::Immediate;
+    fn from_field(bits: u64, _: u32) -> Self {
+        Self(bits)
+    }
+    fn to_field(self, width: u32) -> u64 {
+        self.0 & field_mask(width)
+    }
+}
+
+impl Operand for SImm {
+    const KIND: OperandKind = OperandKind::SignedImmediate;
+    fn from_field(bits: u64, width: u32) -> Self {
+        if width == 0 {
+            return Self(0);
+        }
+        let shift = 64 - width;
+        Self(((bits << shift) as i64) >> shift)
+    }
+    fn to_field(self, width: u32) -> u64 {
+        self.0 as u64 & field_mask(width)
+    }
+}
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "r{}", self.0)
+    }
+}
+
+impl Display for FReg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "f{}", self.0)
+    }
+}
+
+impl Display for Imm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#0x{:X}", self.0)
+    }
+}
+
+impl Display for SImm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IsaError {
+    UnknownMnemonic(String),
+    OperandCount { mnemonic: &'static str, expected: usize, found: usize },
+    OperandKind { mnemonic: &'static str, operand: &'static str, expected: OperandKind },
+    OperandRange { mnemonic: &'static str, operand: &'static str, value: i64, width: u32 },
+}
+
+impl Display for IsaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IsaError::UnknownMnemonic(mnemonic) => write!(f, "Unknown instruction: {mnemonic}"),
+            IsaError::OperandCount { mnemonic, expected, found } => write!(f, "{mnemonic} takes {expected} operands, but {found} were given"),
+            IsaError::OperandKind { mnemonic, operand, expected } => write!(f, "Operand {operand} of {mnemonic} must be {expected:?}"),
+            IsaError::OperandRange { mnemonic, operand, value, width } => write!(f, "Operand {operand} of {mnemonic} is {width} bits wide, but value is {value}"),
+        }
+    }
+}
+
+impl std::error::Error for IsaError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub kind: OperandKind,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl FieldInfo {
+    pub fn width(&self) -> u32 {
+        self.end - self.start
+    }
+
+    pub fn fits(&self, value: i64) -> bool {
+        let width = self.width();
+        if width >= 64 {
+            return true;
+        }
+        if width == 0 {
+            return value == 0;
+        }
+        match self.kind {
+            OperandKind::SignedImmediate => value >= -(1i64 << (width - 1)) && value < (1i64 << (width - 1)),
+            _ => value >= 0 && value < (1i64 << width),
+        }
+    }
+}
+
+// One row of an instruction set: a word `w` encodes this instruction when
+// `w & mask == pattern`, its operands live in `fields`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionInfo {
+    pub mnemonic: &'static str,
+    pub mask: u64,
+    pub pattern: u64,
+    pub fields: &'static [FieldInfo],
+}
+
+impl InstructionInfo {
+    pub fn matches(&self, word: u64) -> bool {
+        word & self.mask == self.pattern
+    }
+
+    // Encodes untyped operands, as produced by an assembler, in field order.
+    pub fn encode(&self, operands: &[(OperandKind, i64)]) -> Result<u64, IsaError> {
+        if operands.len() != self.fields.len() {
+            return Err(IsaError::OperandCount { mnemonic: self.mnemonic, expected: self.fields.len(), found: operands.len() });
+        }
+        let mut word = self.pattern;
+        for (field, &(kind, value)) in self.fields.iter().zip(operands) {
+            let compatible = kind == field.kind
+                || (kind == OperandKind::Immediate && field.kind == OperandKind::SignedImmediate)
+                || (kind == OperandKind::SignedImmediate && field.kind == OperandKind::Immediate);
+            if !compatible {
+                return Err(IsaError::OperandKind { mnemonic: self.mnemonic, operand: field.name, expected: field.kind });
+            }
+            if !field.fits(value) {
+                return Err(IsaError::OperandRange { mnemonic: self.mnemonic, operand: field.name, value, width: field.width() });
+            }
+            word |= (value as u64 & field_mask(field.width())) << field.start;
+        }
+        Ok(word)
+    }
+
+    // The operands of a matching word in field order, the inverse of `encode`.
+    pub fn decode(&self, word: u64) -> Vec<(OperandKind, i64)> {
+        self.fields.iter().map(|field| {
+            let bits = (word >> field.start) & field_mask(field.width());
+            let value = match field.kind {
+                OperandKind::SignedImmediate => SImm::from_field(bits, field.width()).0,
+                _ => bits as i64,
+            };
+            (field.kind, value)
+        }).collect()
+    }
+}
+
+// All the instructions with a given mnemonic, in definition order.
+pub fn find_instructions<'a>(instructions: &'a [InstructionInfo], mnemonic: &'a str) -> impl Iterator<Item = &'a InstructionInfo> + 'a {
+    instructions.iter().filter(move |x| x.mnemonic.eq_ignore_ascii_case(mnemonic))
+}
+
+// Encodes with the first instruction of that mnemonic that accepts the operands.
+pub fn encode_instruction(instructions: &[InstructionInfo], mnemonic: &str, operands: &[(OperandKind, i64)]) -> Result<u64, IsaError> {
+    let mut error = IsaError::UnknownMnemonic(mnemonic.to_string());
+    for instruction in find_instructions(instructions, mnemonic) {
+        match instruction.encode(operands) {
+            Ok(word) => return Ok(word),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+// Generates, from one table, an enum with a variant per instruction and
+//   - `decode(word)`, the first matching entry wins,
+//   - `encode(&self)`,
+//   - `Display`, the disassembly `mnemonic op, op`,
+//   - `execute(self, cpu)`, which runs the semantics block of the entry,
+//   - `INSTRUCTIONS`, the untyped table used by assemblers.
+//
+// define_isa!(MiniIsa, u32, MiniCPU, {
+//     MovRR = "mov" [0xFFFF_0000, 0x0100_0000] (rd: Reg = 8..16, rs: Reg = 0..8) => |cpu| { ... },
+//     Hlt = "hlt" [0xFF00_0000, 0xFF00_0000] () => |cpu| { ... },
+// });
+#[macro_export]
+macro_rules! define_isa {
+    ( $name:ident, $word:ty, $cpu:ty, { $(
+        $variant:ident = $mnemonic:literal [ $mask:literal, $pattern:literal ]
+            ( $( $field:ident : $kind:ident = $start:literal .. $end:literal ),* $(,)? )
+            => | $target:ident | $body:block
+    ),* $(,)? } ) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $( $variant { $( $field: $crate::isa::$kind ),* } ),*
+        }
+        const _: () = { $( $(
+            assert!(
+                $start < $end && $end <= <$word>::BITS,
+                concat!("Field ", stringify!($field), " of ", stringify!($variant), " has to be at least one bit wide and inside the word"),
+            );
+        )* )* };
+        impl $name {
+            pub const INSTRUCTIONS: &'static [$crate::isa::InstructionInfo] = &[ $(
+                $crate::isa::InstructionInfo {
+                    mnemonic: $mnemonic,
+                    mask: $mask,
+                    pattern: $pattern,
+                    fields: &[ $(
+                        $crate::isa::FieldInfo {
+                            name: stringify!($field),
+                            kind: <$crate::isa::$kind as $crate::isa::Operand>::KIND,
+                            start: $start,
+                            end: $end,
+                        }
+                    ),* ],
+                }
+            ),* ];
+
+            pub fn decode(word: $word) -> Option<Self> {
+                let word = word as u64;
+                $(
+                    if word & $mask == $pattern {
+                        return Some(Self::$variant { $(
+                            $field: <$crate::isa::$kind as $crate::isa::Operand>::from_field(
+                                (word >> $start) & (u64::MAX >> (64 - ($end - $start))),
+                                $end - $start,
+                            )
+                        ),* });
+                    }
+                )*
+                None
+            }
+
+            pub fn encode(&self) -> $word {
+                match *self { $(
+                    Self::$variant { $( $field ),* } => {
+                        let word: u64 = $pattern $( | ($crate::isa::Operand::to_field($field, $end - $start) << $start) )*;
+                        word as $word
+                    }
+                ),* }
+            }
+
+            pub fn mnemonic(&self) -> &'static str {
+                match self { $( Self::$variant { .. } => $mnemonic ),* }
+            }
+
+            #[allow(unused_variables)]
+            pub fn execute(self, cpu: &mut $cpu) -> $crate::cpu::StepResult {
+                match self { $(
+                    Self::$variant { $( $field ),* } => {
+                        let $target = &mut *cpu;
+                        $body
+                    }
+                ),* }
+            }
+        }
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self { $(
+                    Self::$variant { $( $field ),* } => {
+                        let operands: Vec<String> = vec![ $( $field.to_string() ),* ];
+                        if operands.is_empty() {
+                            write!(f, "{}", $mnemonic)
+                        } else {
+                            write!(f, "{} {}", $mnemonic, operands.join(", "))
+                        }
+                    }
+                ),* }
+            }
+        }
+    };
+}
@@ -1,2 +1,11 @@
 pub mod cpu;
-pub mod register;
\ No newline at end of file
+pub mod debug;
+pub mod decode;
+pub mod flags;
+pub mod float;
+pub mod interrupt;
+pub mod isa;
+pub mod register;
+pub mod register_machine;
+pub mod stack_machine;
+pub mod vector;
\ No newline at end of file
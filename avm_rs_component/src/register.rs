@@ -1,4 +1,4 @@
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign, BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Shl, ShlAssign, Shr, ShrAssign, Not};
+use std::{fmt::Display, ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign, BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Shl, ShlAssign, Shr, ShrAssign, Not}};
 
 pub trait RegisterTrait<T> {
     fn get(&self) -> T;
@@ -7,16 +7,264 @@ pub trait RegisterTrait<T> {
 
 pub type RegisterType<T> = dyn RegisterTrait<T>;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterValue {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    U128(u128),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    I128(i128),
+    F32(f32),
+    F64(f64),
+}
+
+impl RegisterValue {
+    // Picks the narrowest unsigned variant able to hold `width` bits.
+    pub fn from_bits(width: u32, bits: u128) -> Self {
+        match width {
+            0..=8 => RegisterValue::U8(bits as u8),
+            9..=16 => RegisterValue::U16(bits as u16),
+            17..=32 => RegisterValue::U32(bits as u32),
+            33..=64 => RegisterValue::U64(bits as u64),
+            _ => RegisterValue::U128(bits),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        match self {
+            RegisterValue::U8(_) | RegisterValue::I8(_) => 8,
+            RegisterValue::U16(_) | RegisterValue::I16(_) => 16,
+            RegisterValue::U32(_) | RegisterValue::I32(_) | RegisterValue::F32(_) => 32,
+            RegisterValue::U64(_) | RegisterValue::I64(_) | RegisterValue::F64(_) => 64,
+            RegisterValue::U128(_) | RegisterValue::I128(_) => 128,
+        }
+    }
+
+    pub fn to_bits(&self) -> u128 {
+        match *self {
+            RegisterValue::U8(x) => x.to_bits(),
+            RegisterValue::U16(x) => x.to_bits(),
+            RegisterValue::U32(x) => x.to_bits(),
+            RegisterValue::U64(x) => x.to_bits(),
+            RegisterValue::U128(x) => x.to_bits(),
+            RegisterValue::I8(x) => x.to_bits(),
+            RegisterValue::I16(x) => x.to_bits(),
+            RegisterValue::I32(x) => x.to_bits(),
+            RegisterValue::I64(x) => x.to_bits(),
+            RegisterValue::I128(x) => x.to_bits(),
+            RegisterValue::F32(x) => RegisterPrimitive::to_bits(x),
+            RegisterValue::F64(x) => RegisterPrimitive::to_bits(x),
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, RegisterValue::F32(_) | RegisterValue::F64(_))
+    }
+}
+
+impl Display for RegisterValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterValue::U8(x) => write!(f, "{x}"),
+            RegisterValue::U16(x) => write!(f, "{x}"),
+            RegisterValue::U32(x) => write!(f, "{x}"),
+            RegisterValue::U64(x) => write!(f, "{x}"),
+            RegisterValue::U128(x) => write!(f, "{x}"),
+            RegisterValue::I8(x) => write!(f, "{x}"),
+            RegisterValue::I16(x) => write!(f, "{x}"),
+            RegisterValue::I32(x) => write!(f, "{x}"),
+            RegisterValue::I64(x) => write!(f, "{x}"),
+            RegisterValue::I128(x) => write!(f, "{x}"),
+            RegisterValue::F32(x) => write!(f, "{x}"),
+            RegisterValue::F64(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+pub trait RegisterPrimitive: Copy {
+    const BITS: u32;
+    const SIGNED: bool = false;
+    fn to_value(self) -> RegisterValue;
+    fn to_bits(self) -> u128;
+    fn from_bits(bits: u128) -> Self;
+}
+
+// The same-width unsigned and signed types of an integer, for the carry and
+// overflow of register arithmetic.
+pub trait IntegerPrimitive {
+    type Unsigned;
+    type Signed;
+}
+
+macro_rules! impl_register_primitive {
+    ( $type:ty, $variant:ident, $unsigned:ty, $signed:ty ) => {
+        impl IntegerPrimitive for $type {
+            type Unsigned = $unsigned;
+            type Signed = $signed;
+        }
+        impl RegisterPrimitive for $type {
+            const BITS: u32 = <$type>::BITS;
+            const SIGNED: bool = <$type>::MIN != 0;
+            fn to_value(self) -> RegisterValue {
+                RegisterValue::$variant(self)
+            }
+            fn to_bits(self) -> u128 {
+                self as $unsigned as u128
+            }
+            fn from_bits(bits: u128) -> Self {
+                bits as $unsigned as $type
+            }
+        }
+    };
+}
+
+impl_register_primitive!(u8, U8, u8, i8);
+impl_register_primitive!(u16, U16, u16, i16);
+impl_register_primitive!(u32, U32, u32, i32);
+impl_register_primitive!(u64, U64, u64, i64);
+impl_register_primitive!(u128, U128, u128, i128);
+impl_register_primitive!(i8, I8, u8, i8);
+impl_register_primitive!(i16, I16, u16, i16);
+impl_register_primitive!(i32, I32, u32, i32);
+impl_register_primitive!(i64, I64, u64, i64);
+impl_register_primitive!(i128, I128, u128, i128);
+
+impl RegisterPrimitive for bool {
+    const BITS: u32 = 1;
+    fn to_value(self) -> RegisterValue {
+        RegisterValue::U8(self as u8)
+    }
+    fn to_bits(self) -> u128 {
+        self as u128
+    }
+    fn from_bits(bits: u128) -> Self {
+        bits & 1 != 0
+    }
+}
+
+impl RegisterPrimitive for f32 {
+    const BITS: u32 = 32;
+    fn to_value(self) -> RegisterValue {
+        RegisterValue::F32(self)
+    }
+    fn to_bits(self) -> u128 {
+        f32::to_bits(self) as u128
+    }
+    fn from_bits(bits: u128) -> Self {
+        f32::from_bits(bits as u32)
+    }
+}
+
+impl RegisterPrimitive for f64 {
+    const BITS: u32 = 64;
+    fn to_value(self) -> RegisterValue {
+        RegisterValue::F64(self)
+    }
+    fn to_bits(self) -> u128 {
+        f64::to_bits(self) as u128
+    }
+    fn from_bits(bits: u128) -> Self {
+        f64::from_bits(bits as u64)
+    }
+}
+
+// Width-erased access to a register, so debuggers and serializers can work
+// with registers of any type through `&dyn DynRegisterTrait`.
+pub trait DynRegisterTrait {
+    fn width(&self) -> u32;
+    fn value(&self) -> RegisterValue;
+    fn bits(&self) -> u128;
+    fn set_bits(&mut self, bits: u128);
+
+    fn set_value(&mut self, value: RegisterValue) -> Result<(), RegisterError> {
+        let expected = RegisterValue::from_bits(self.width(), 0).width();
+        if value.width() != expected {
+            return Err(RegisterError::WidthMismatch { expected, found: value.width() });
+        }
+        if value.is_float() != self.value().is_float() {
+            return Err(RegisterError::KindMismatch { float: self.value().is_float() });
+        }
+        self.set_bits(value.to_bits());
+        Ok(())
+    }
+}
+
+pub type DynRegister = dyn DynRegisterTrait;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticError {
+    Overflow,
+    DivideByZero,
+}
+
+impl Display for ArithmeticError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArithmeticError::Overflow => write!(f, "Arithmetic overflow"),
+            ArithmeticError::DivideByZero => write!(f, "Divide by zero"),
+        }
+    }
+}
+
+impl std::error::Error for ArithmeticError {}
+
+// `carry` is the carry (or borrow) out of the unsigned interpretation of the
+// operands and `overflow` the overflow of the signed one, whatever the
+// signedness of the register itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArithmeticResult<T> {
+    pub value: T,
+    pub carry: bool,
+    pub overflow: bool,
+}
+
+// Full product of two u128s as (low, high) halves.
+pub fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+    let (a_low, a_high) = (a as u64 as u128, a >> 64);
+    let (b_low, b_high) = (b as u64 as u128, b >> 64);
+    let (middle, middle_carry) = (a_high * b_low).overflowing_add(a_low * b_high);
+    let (low, low_carry) = (a_low * b_low).overflowing_add(middle << 64);
+    let high = a_high * b_high + (middle >> 64) + ((middle_carry as u128) << 64) + low_carry as u128;
+    (low, high)
+}
+
+pub trait IntegerRegisterTrait {
+    fn is_zero(&self) -> bool;
+    fn is_negative(&self) -> bool;
+    fn has_even_parity(&self) -> bool;
+}
+
 #[macro_export]
 macro_rules! define_register {
-    ( $name:ident, $type:ty ) => {
-        pub struct $name(pub $type);
-        impl RegisterTrait<$type> for $name {
-            fn get(&self) -> $type {
-                self.0
+    ( $name:ident, f32 ) => {
+        $crate::define_register!(@float $name, f32);
+    };
+    ( $name:ident, f64 ) => {
+        $crate::define_register!(@float $name, f64);
+    };
+    // Operators round like the host, `*_with` through a `FloatControl`.
+    ( @float $name:ident, $type:ty ) => {
+        $crate::define_register!(@common $name, $type);
+        impl $name {
+            pub fn add_with(self, other: Self, control: &mut $crate::float::FloatControl) -> Self {
+                Self(control.add(self.0, other.0))
             }
-            fn set(&mut self, value: $type) {
-                self.0 = value;
+            pub fn sub_with(self, other: Self, control: &mut $crate::float::FloatControl) -> Self {
+                Self(control.sub(self.0, other.0))
+            }
+            pub fn mul_with(self, other: Self, control: &mut $crate::float::FloatControl) -> Self {
+                Self(control.mul(self.0, other.0))
+            }
+            pub fn div_with(self, other: Self, control: &mut $crate::float::FloatControl) -> Self {
+                Self(control.div(self.0, other.0))
+            }
+            pub fn sqrt_with(self, control: &mut $crate::float::FloatControl) -> Self {
+                Self(control.sqrt(self.0))
             }
         }
         impl Add for $name {
@@ -27,7 +275,7 @@ macro_rules! define_register {
         }
         impl AddAssign for $name {
             fn add_assign(&mut self, other: Self) {
-                *self = Self(self.0 + other.0)
+                *self = *self + other
             }
         }
         impl Sub for $name {
@@ -38,7 +286,7 @@ macro_rules! define_register {
         }
         impl SubAssign for $name {
             fn sub_assign(&mut self, other: Self) {
-                *self = Self(self.0 - other.0)
+                *self = *self - other
             }
         }
         impl Mul for $name {
@@ -49,7 +297,7 @@ macro_rules! define_register {
         }
         impl MulAssign for $name {
             fn mul_assign(&mut self, other: Self) {
-                *self = Self(self.0 * other.0)
+                *self = *self * other
             }
         }
         impl Div for $name {
@@ -60,105 +308,854 @@ macro_rules! define_register {
         }
         impl DivAssign for $name {
             fn div_assign(&mut self, other: Self) {
-                *self = Self(self.0 / other.0)
+                *self = *self / other
             }
         }
     };
-}
+    ( @common $name:ident, $type:ty ) => {
+        #[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+        pub struct $name(pub $type);
+        impl RegisterTrait<$type> for $name {
+            fn get(&self) -> $type {
+                self.0
+            }
+            fn set(&mut self, value: $type) {
+                self.0 = value;
+            }
+        }
+        impl $crate::register::DynRegisterTrait for $name {
+            fn width(&self) -> u32 {
+                <$type as $crate::register::RegisterPrimitive>::BITS
+            }
+            fn value(&self) -> $crate::register::RegisterValue {
+                $crate::register::RegisterPrimitive::to_value(self.0)
+            }
+            fn bits(&self) -> u128 {
+                $crate::register::RegisterPrimitive::to_bits(self.0)
+            }
+            fn set_bits(&mut self, bits: u128) {
+                self.0 = <$type as $crate::register::RegisterPrimitive>::from_bits(bits);
+            }
+        }
+    };
+    // Integer register. The operators wrap, and dividing by zero gives all
+    // ones, as on RISC-V, so guest arithmetic never panics the host. Shifts
+    // by the width or more shift everything out.
+    ( $name:ident, $type:ty ) => {
+        $crate::define_register!(@common $name, $type);
+        impl $crate::register::IntegerRegisterTrait for $name {
+            fn is_zero(&self) -> bool {
+                self.0 == 0
+            }
+            fn is_negative(&self) -> bool {
+                (self.0 as <$type as $crate::register::IntegerPrimitive>::Signed) < 0
+            }
+            fn has_even_parity(&self) -> bool {
+                (self.0 as u8).count_ones() % 2 == 0
+            }
+        }
+        impl $name {
+            pub fn wrapping_add(self, other: Self) -> Self {
+                Self(self.0.wrapping_add(other.0))
+            }
+            pub fn wrapping_sub(self, other: Self) -> Self {
+                Self(self.0.wrapping_sub(other.0))
+            }
+            pub fn wrapping_mul(self, other: Self) -> Self {
+                Self(self.0.wrapping_mul(other.0))
+            }
+            pub fn wrapping_neg(self) -> Self {
+                Self(self.0.wrapping_neg())
+            }
+            pub fn wrapping_div(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                if other.0 == 0 {
+                    return Err($crate::register::ArithmeticError::DivideByZero);
+                }
+                Ok(Self(self.0.wrapping_div(other.0)))
+            }
+            pub fn wrapping_rem(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                if other.0 == 0 {
+                    return Err($crate::register::ArithmeticError::DivideByZero);
+                }
+                Ok(Self(self.0.wrapping_rem(other.0)))
+            }
 
-#[macro_export]
-macro_rules! register_bitwise_extension {
-    ( $strukt:ident ) => {
-        impl BitAnd for $strukt {
-            type Output = $strukt;
+            pub fn checked_add(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                self.0.checked_add(other.0).map(Self).ok_or($crate::register::ArithmeticError::Overflow)
+            }
+            pub fn checked_sub(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                self.0.checked_sub(other.0).map(Self).ok_or($crate::register::ArithmeticError::Overflow)
+            }
+            pub fn checked_mul(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                self.0.checked_mul(other.0).map(Self).ok_or($crate::register::ArithmeticError::Overflow)
+            }
+            pub fn checked_neg(self) -> Result<Self, $crate::register::ArithmeticError> {
+                self.0.checked_neg().map(Self).ok_or($crate::register::ArithmeticError::Overflow)
+            }
+            pub fn checked_div(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                if other.0 == 0 {
+                    return Err($crate::register::ArithmeticError::DivideByZero);
+                }
+                self.0.checked_div(other.0).map(Self).ok_or($crate::register::ArithmeticError::Overflow)
+            }
+            pub fn checked_rem(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                if other.0 == 0 {
+                    return Err($crate::register::ArithmeticError::DivideByZero);
+                }
+                self.0.checked_rem(other.0).map(Self).ok_or($crate::register::ArithmeticError::Overflow)
+            }
+
+            pub fn saturating_add(self, other: Self) -> Self {
+                Self(self.0.saturating_add(other.0))
+            }
+            pub fn saturating_sub(self, other: Self) -> Self {
+                Self(self.0.saturating_sub(other.0))
+            }
+            pub fn saturating_mul(self, other: Self) -> Self {
+                Self(self.0.saturating_mul(other.0))
+            }
+            pub fn saturating_div(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                if other.0 == 0 {
+                    return Err($crate::register::ArithmeticError::DivideByZero);
+                }
+                Ok(Self(self.0.saturating_div(other.0)))
+            }
+
+            pub fn overflowing_add(self, other: Self) -> $crate::register::ArithmeticResult<Self> {
+                self.carrying_add(other, false)
+            }
+            pub fn overflowing_sub(self, other: Self) -> $crate::register::ArithmeticResult<Self> {
+                self.borrowing_sub(other, false)
+            }
+            pub fn overflowing_mul(self, other: Self) -> $crate::register::ArithmeticResult<Self> {
+                type Unsigned = <$type as $crate::register::IntegerPrimitive>::Unsigned;
+                type Signed = <$type as $crate::register::IntegerPrimitive>::Signed;
+                $crate::register::ArithmeticResult {
+                    value: Self(self.0.wrapping_mul(other.0)),
+                    carry: (self.0 as Unsigned).overflowing_mul(other.0 as Unsigned).1,
+                    overflow: (self.0 as Signed).overflowing_mul(other.0 as Signed).1,
+                }
+            }
+            pub fn carrying_add(self, other: Self, carry: bool) -> $crate::register::ArithmeticResult<Self> {
+                type Unsigned = <$type as $crate::register::IntegerPrimitive>::Unsigned;
+                type Signed = <$type as $crate::register::IntegerPrimitive>::Signed;
+                let (partial, carry_a) = (self.0 as Unsigned).overflowing_add(other.0 as Unsigned);
+                let (value, carry_b) = partial.overflowing_add(carry as Unsigned);
+                let (partial, overflow_a) = (self.0 as Signed).overflowing_add(other.0 as Signed);
+                let (_, overflow_b) = partial.overflowing_add(carry as Signed);
+                $crate::register::ArithmeticResult {
+                    value: Self(value as $type),
+                    carry: carry_a || carry_b,
+                    overflow: overflow_a != overflow_b,
+                }
+            }
+            pub fn borrowing_sub(self, other: Self, borrow: bool) -> $crate::register::ArithmeticResult<Self> {
+                type Unsigned = <$type as $crate::register::IntegerPrimitive>::Unsigned;
+                type Signed = <$type as $crate::register::IntegerPrimitive>::Signed;
+                let (partial, borrow_a) = (self.0 as Unsigned).overflowing_sub(other.0 as Unsigned);
+                let (value, borrow_b) = partial.overflowing_sub(borrow as Unsigned);
+                let (partial, overflow_a) = (self.0 as Signed).overflowing_sub(other.0 as Signed);
+                let (_, overflow_b) = partial.overflowing_sub(borrow as Signed);
+                $crate::register::ArithmeticResult {
+                    value: Self(value as $type),
+                    carry: borrow_a || borrow_b,
+                    overflow: overflow_a != overflow_b,
+                }
+            }
+        }
+        impl Add for $name {
+            type Output = $name;
+            fn add(self, other: Self) -> Self::Output {
+                self.wrapping_add(other)
+            }
+        }
+        impl AddAssign for $name {
+            fn add_assign(&mut self, other: Self) {
+                *self = self.wrapping_add(other)
+            }
+        }
+        impl Sub for $name {
+            type Output = $name;
+            fn sub(self, other: Self) -> Self::Output {
+                self.wrapping_sub(other)
+            }
+        }
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, other: Self) {
+                *self = self.wrapping_sub(other)
+            }
+        }
+        impl Mul for $name {
+            type Output = $name;
+            fn mul(self, other: Self) -> Self::Output {
+                self.wrapping_mul(other)
+            }
+        }
+        impl MulAssign for $name {
+            fn mul_assign(&mut self, other: Self) {
+                *self = self.wrapping_mul(other)
+            }
+        }
+        impl Div for $name {
+            type Output = $name;
+            fn div(self, other: Self) -> Self::Output {
+                self.wrapping_div(other).unwrap_or(Self(!0))
+            }
+        }
+        impl DivAssign for $name {
+            fn div_assign(&mut self, other: Self) {
+                *self = *self / other
+            }
+        }
+        impl BitAnd for $name {
+            type Output = $name;
             fn bitand(self, other: Self) -> Self::Output {
                 Self(self.0 & other.0)
             }
         }
-        impl BitAndAssign for $strukt {
+        impl BitAndAssign for $name {
             fn bitand_assign(&mut self, other: Self) {
-                *self = Self(self.0 & other.0)
+                *self = *self & other
             }
         }
-
-        impl BitOr for $strukt {
-            type Output = $strukt;
+        impl BitOr for $name {
+            type Output = $name;
             fn bitor(self, other: Self) -> Self::Output {
                 Self(self.0 | other.0)
             }
         }
-        impl BitOrAssign for $strukt {
+        impl BitOrAssign for $name {
             fn bitor_assign(&mut self, other: Self) {
-                *self = Self(self.0 | other.0)
+                *self = *self | other
             }
         }
-
-        impl BitXor for $strukt {
-            type Output = $strukt;
+        impl BitXor for $name {
+            type Output = $name;
             fn bitxor(self, other: Self) -> Self::Output {
                 Self(self.0 ^ other.0)
             }
         }
-        impl BitXorAssign for $strukt {
+        impl BitXorAssign for $name {
             fn bitxor_assign(&mut self, other: Self) {
-                *self = Self(self.0 ^ other.0)
+                *self = *self ^ other
             }
         }
-
-        impl Not for $strukt {
-            type Output = $strukt;
+        impl Not for $name {
+            type Output = $name;
             fn not(self) -> Self::Output {
                 Self(!self.0)
             }
         }
-
-        impl Shl for $strukt {
-            type Output = $strukt;
+        impl Shl for $name {
+            type Output = $name;
             fn shl(self, other: Self) -> Self::Output {
-                Self(self.0 << other.0)
+                Self(u32::try_from(other.0).ok().and_then(|shift| self.0.checked_shl(shift)).unwrap_or(0))
             }
         }
-        impl ShlAssign for $strukt {
+        impl ShlAssign for $name {
             fn shl_assign(&mut self, other: Self) {
-                *self = Self(self.0 << other.0)
+                *self = *self << other
+            }
+        }
+        // Signed registers shift in copies of the sign bit.
+        impl Shr for $name {
+            type Output = $name;
+            fn shr(self, other: Self) -> Self::Output {
+                Self(u32::try_from(other.0).ok().and_then(|shift| self.0.checked_shr(shift)).unwrap_or(self.0 >> (<$type>::BITS - 1) >> 1))
+            }
+        }
+        impl ShrAssign for $name {
+            fn shr_assign(&mut self, other: Self) {
+                *self = *self >> other
             }
         }
+    };
+    // Unsigned register narrower than its storage type, every write and
+    // arithmetic result is masked to the low `$bits` bits. The field is
+    // private so values go through `new`. Otherwise it behaves like the
+    // integer registers above.
+    ( $name:ident, $type:ty, $bits:literal ) => {
+        const _: () = assert!($bits > 0 && $bits < <$type>::BITS, concat!(stringify!($name), " has to be narrower than ", stringify!($type), " and at least one bit wide"));
+        #[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+        pub struct $name($type);
+        impl $name {
+            pub const BITS: u32 = $bits;
+            pub const MASK: $type = ((1u128 << $bits) - 1) as $type;
+            const SIGN: u128 = 1u128 << ($bits - 1);
 
-        impl Shr for $strukt {
-            type Output = $strukt;
+            pub fn new(value: $type) -> Self {
+                Self(value & Self::MASK)
+            }
+            pub fn wrapping_add(self, other: Self) -> Self {
+                Self::new(self.0.wrapping_add(other.0))
+            }
+            pub fn wrapping_sub(self, other: Self) -> Self {
+                Self::new(self.0.wrapping_sub(other.0))
+            }
+            pub fn wrapping_mul(self, other: Self) -> Self {
+                Self::new(self.0.wrapping_mul(other.0))
+            }
+            pub fn wrapping_neg(self) -> Self {
+                Self::new(self.0.wrapping_neg())
+            }
+            pub fn wrapping_div(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                self.checked_div(other)
+            }
+            pub fn wrapping_rem(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                self.checked_rem(other)
+            }
+            pub fn checked_add(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                let result = self.overflowing_add(other);
+                if result.carry { Err($crate::register::ArithmeticError::Overflow) } else { Ok(result.value) }
+            }
+            pub fn checked_sub(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                let result = self.overflowing_sub(other);
+                if result.carry { Err($crate::register::ArithmeticError::Overflow) } else { Ok(result.value) }
+            }
+            pub fn checked_mul(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                let result = self.overflowing_mul(other);
+                if result.carry { Err($crate::register::ArithmeticError::Overflow) } else { Ok(result.value) }
+            }
+            pub fn checked_div(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                if other.0 == 0 {
+                    return Err($crate::register::ArithmeticError::DivideByZero);
+                }
+                Ok(Self(self.0 / other.0))
+            }
+            pub fn checked_rem(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                if other.0 == 0 {
+                    return Err($crate::register::ArithmeticError::DivideByZero);
+                }
+                Ok(Self(self.0 % other.0))
+            }
+            pub fn checked_neg(self) -> Result<Self, $crate::register::ArithmeticError> {
+                if self.0 != 0 { Err($crate::register::ArithmeticError::Overflow) } else { Ok(self) }
+            }
+            pub fn saturating_add(self, other: Self) -> Self {
+                self.checked_add(other).unwrap_or(Self(Self::MASK))
+            }
+            pub fn saturating_sub(self, other: Self) -> Self {
+                Self(self.0.saturating_sub(other.0))
+            }
+            pub fn saturating_mul(self, other: Self) -> Self {
+                self.checked_mul(other).unwrap_or(Self(Self::MASK))
+            }
+            pub fn saturating_div(self, other: Self) -> Result<Self, $crate::register::ArithmeticError> {
+                self.checked_div(other)
+            }
+            pub fn overflowing_add(self, other: Self) -> $crate::register::ArithmeticResult<Self> {
+                self.carrying_add(other, false)
+            }
+            pub fn overflowing_sub(self, other: Self) -> $crate::register::ArithmeticResult<Self> {
+                self.borrowing_sub(other, false)
+            }
+            // Products are computed in 256 bits, so registers wider than 64
+            // bits can't overflow the intermediate result.
+            pub fn overflowing_mul(self, other: Self) -> $crate::register::ArithmeticResult<Self> {
+                let (a, b) = (self.0 as u128, other.0 as u128);
+                let (low, high) = $crate::register::widening_mul(a, b);
+                let extend = |x: u128| if x & Self::SIGN != 0 { x as i128 - (1i128 << $bits) } else { x as i128 };
+                let (a, b) = (extend(a), extend(b));
+                let (magnitude, magnitude_high) = $crate::register::widening_mul(a.unsigned_abs(), b.unsigned_abs());
+                let limit = if (a < 0) != (b < 0) { Self::SIGN } else { Self::SIGN - 1 };
+                $crate::register::ArithmeticResult {
+                    value: Self::new(low as $type),
+                    carry: high != 0 || low > Self::MASK as u128,
+                    overflow: magnitude_high != 0 || magnitude > limit,
+                }
+            }
+            pub fn carrying_add(self, other: Self, carry: bool) -> $crate::register::ArithmeticResult<Self> {
+                let (a, b) = (self.0 as u128, other.0 as u128);
+                let sum = a + b + carry as u128;
+                let value = sum & Self::MASK as u128;
+                $crate::register::ArithmeticResult {
+                    value: Self(value as $type),
+                    carry: sum > Self::MASK as u128,
+                    overflow: (a ^ value) & (b ^ value) & Self::SIGN != 0,
+                }
+            }
+            pub fn borrowing_sub(self, other: Self, borrow: bool) -> $crate::register::ArithmeticResult<Self> {
+                let (a, b) = (self.0 as u128, other.0 as u128);
+                let value = a.wrapping_sub(b).wrapping_sub(borrow as u128) & Self::MASK as u128;
+                $crate::register::ArithmeticResult {
+                    value: Self(value as $type),
+                    carry: a < b + borrow as u128,
+                    overflow: (a ^ b) & (a ^ value) & Self::SIGN != 0,
+                }
+            }
+        }
+        impl RegisterTrait<$type> for $name {
+            fn get(&self) -> $type {
+                self.0
+            }
+            fn set(&mut self, value: $type) {
+                self.0 = value & Self::MASK;
+            }
+        }
+        impl $crate::register::DynRegisterTrait for $name {
+            fn width(&self) -> u32 {
+                $bits
+            }
+            fn value(&self) -> $crate::register::RegisterValue {
+                $crate::register::RegisterValue::from_bits($bits, self.0 as u128)
+            }
+            fn bits(&self) -> u128 {
+                self.0 as u128
+            }
+            fn set_bits(&mut self, bits: u128) {
+                self.0 = (bits & Self::MASK as u128) as $type;
+            }
+        }
+        impl $crate::register::IntegerRegisterTrait for $name {
+            fn is_zero(&self) -> bool {
+                self.0 == 0
+            }
+            fn is_negative(&self) -> bool {
+                self.0 as u128 & Self::SIGN != 0
+            }
+            fn has_even_parity(&self) -> bool {
+                (self.0 as u8).count_ones() % 2 == 0
+            }
+        }
+        impl Add for $name {
+            type Output = $name;
+            fn add(self, other: Self) -> Self::Output {
+                self.wrapping_add(other)
+            }
+        }
+        impl AddAssign for $name {
+            fn add_assign(&mut self, other: Self) {
+                *self = self.wrapping_add(other)
+            }
+        }
+        impl Sub for $name {
+            type Output = $name;
+            fn sub(self, other: Self) -> Self::Output {
+                self.wrapping_sub(other)
+            }
+        }
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, other: Self) {
+                *self = self.wrapping_sub(other)
+            }
+        }
+        impl Mul for $name {
+            type Output = $name;
+            fn mul(self, other: Self) -> Self::Output {
+                self.wrapping_mul(other)
+            }
+        }
+        impl MulAssign for $name {
+            fn mul_assign(&mut self, other: Self) {
+                *self = self.wrapping_mul(other)
+            }
+        }
+        impl Div for $name {
+            type Output = $name;
+            fn div(self, other: Self) -> Self::Output {
+                self.wrapping_div(other).unwrap_or(Self(Self::MASK))
+            }
+        }
+        impl DivAssign for $name {
+            fn div_assign(&mut self, other: Self) {
+                *self = *self / other
+            }
+        }
+        impl BitAnd for $name {
+            type Output = $name;
+            fn bitand(self, other: Self) -> Self::Output {
+                Self(self.0 & other.0)
+            }
+        }
+        impl BitAndAssign for $name {
+            fn bitand_assign(&mut self, other: Self) {
+                *self = *self & other
+            }
+        }
+        impl BitOr for $name {
+            type Output = $name;
+            fn bitor(self, other: Self) -> Self::Output {
+                Self(self.0 | other.0)
+            }
+        }
+        impl BitOrAssign for $name {
+            fn bitor_assign(&mut self, other: Self) {
+                *self = *self | other
+            }
+        }
+        impl BitXor for $name {
+            type Output = $name;
+            fn bitxor(self, other: Self) -> Self::Output {
+                Self(self.0 ^ other.0)
+            }
+        }
+        impl BitXorAssign for $name {
+            fn bitxor_assign(&mut self, other: Self) {
+                *self = *self ^ other
+            }
+        }
+        impl Not for $name {
+            type Output = $name;
+            fn not(self) -> Self::Output {
+                Self::new(!self.0)
+            }
+        }
+        impl Shl for $name {
+            type Output = $name;
+            fn shl(self, other: Self) -> Self::Output {
+                Self::new(u32::try_from(other.0).ok().and_then(|shift| self.0.checked_shl(shift)).unwrap_or(0))
+            }
+        }
+        impl ShlAssign for $name {
+            fn shl_assign(&mut self, other: Self) {
+                *self = *self << other
+            }
+        }
+        impl Shr for $name {
+            type Output = $name;
             fn shr(self, other: Self) -> Self::Output {
-                Self(self.0 >> other.0)
+                Self(u32::try_from(other.0).ok().and_then(|shift| self.0.checked_shr(shift)).unwrap_or(0))
             }
         }
-        impl ShrAssign for $strukt {
+        impl ShrAssign for $name {
             fn shr_assign(&mut self, other: Self) {
-                *self = Self(self.0 >> other.0)
+                *self = *self >> other
             }
         }
     };
 }
 
 define_register!(RegisterU8, u8);
-register_bitwise_extension!(RegisterU8);
 define_register!(RegisterU16, u16);
-register_bitwise_extension!(RegisterU16);
 define_register!(RegisterU32, u32);
-register_bitwise_extension!(RegisterU32);
 define_register!(RegisterU64, u64);
-register_bitwise_extension!(RegisterU64);
 define_register!(RegisterU128, u128);
-register_bitwise_extension!(RegisterU128);
 
 define_register!(RegisterI8, i8);
-register_bitwise_extension!(RegisterI8);
 define_register!(RegisterI16, i16);
-register_bitwise_extension!(RegisterI16);
 define_register!(RegisterI32, i32);
-register_bitwise_extension!(RegisterI32);
 define_register!(RegisterI64, i64);
-register_bitwise_extension!(RegisterI64);
 define_register!(RegisterI128, i128);
-register_bitwise_extension!(RegisterI128);
+
+define_register!(RegisterU12, u16, 12);
+define_register!(RegisterU24, u32, 24);
+define_register!(RegisterU48, u64, 48);
 
 define_register!(RegisterF32, f32);
-define_register!(RegisterF64, f64);
\ No newline at end of file
+define_register!(RegisterF64, f64);
+#[macro_export]
+macro_rules! define_bitfield {
+    ( $name:ident, $type:ty, { $( $getter:ident, $setter:ident : $field:ty = $start:literal .. $end:literal ),* $(,)? } ) => {
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        pub struct $name(pub $type);
+        impl $name {
+            pub const FIELDS: &'static [(&'static str, u32, u32)] = &[ $( (stringify!($getter), $start, $end) ),* ];
+
+            $(
+                // Signed fields are sign-extended from their top bit.
+                pub fn $getter(&self) -> $field {
+                    let mask = (1u128 << ($end - $start)) - 1;
+                    let mut bits = (self.0 as u128 >> $start) & mask;
+                    if <$field as $crate::register::RegisterPrimitive>::SIGNED && bits >> ($end - $start - 1) != 0 {
+                        bits |= !mask;
+                    }
+                    <$field as $crate::register::RegisterPrimitive>::from_bits(bits)
+                }
+                pub fn $setter(&mut self, value: $field) {
+                    let mask = ((1u128 << ($end - $start)) - 1) << $start;
+                    let bits = $crate::register::RegisterPrimitive::to_bits(value) << $start;
+                    self.0 = ((self.0 as u128 & !mask) | (bits & mask)) as $type;
+                }
+            )*
+        }
+        impl $crate::register::RegisterTrait<$type> for $name {
+            fn get(&self) -> $type {
+                self.0
+            }
+            fn set(&mut self, value: $type) {
+                self.0 = value;
+            }
+        }
+        impl $crate::register::DynRegisterTrait for $name {
+            fn width(&self) -> u32 {
+                <$type as $crate::register::RegisterPrimitive>::BITS
+            }
+            fn value(&self) -> $crate::register::RegisterValue {
+                $crate::register::RegisterPrimitive::to_value(self.0)
+            }
+            fn bits(&self) -> u128 {
+                $crate::register::RegisterPrimitive::to_bits(self.0)
+            }
+            fn set_bits(&mut self, bits: u128) {
+                self.0 = <$type as $crate::register::RegisterPrimitive>::from_bits(bits);
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    InvalidIndex(usize),
+    UnknownName(String),
+    WidthMismatch { expected: u32, found: u32 },
+    InvalidBank(usize),
+    OutOfRange(u128),
+    BankSizeMismatch { expected: usize, found: usize },
+    // A float for an integer register or the other way around, `float` is
+    // what the register holds.
+    KindMismatch { float: bool },
+}
+
+impl Display for RegisterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterError::InvalidIndex(index) => write!(f, "Invalid register index: {index}"),
+            RegisterError::UnknownName(name) => write!(f, "Unknown register: {name}"),
+            RegisterError::WidthMismatch { expected, found } => write!(f, "Register is {expected} bits wide, but value is {found} bits wide"),
+            RegisterError::InvalidBank(bank) => write!(f, "Invalid register bank: {bank}"),
+            RegisterError::OutOfRange(value) => write!(f, "Value 0x{value:X} is out of range for this register"),
+            RegisterError::BankSizeMismatch { expected, found } => write!(f, "Register file banks {expected} slots, but saved bank has {found} slots"),
+            RegisterError::KindMismatch { float: true } => write!(f, "Register holds a float, but value is an integer"),
+            RegisterError::KindMismatch { float: false } => write!(f, "Register holds an integer, but value is a float"),
+        }
+    }
+}
+
+impl std::error::Error for RegisterError {}
+
+// Describes one named view of a register file slot: `width` bits starting at
+// bit `offset`. Aliases such as x86 EAX/AX/AL/AH share the slot of RAX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterDescriptor {
+    pub name: &'static str,
+    pub slot: usize,
+    pub offset: u32,
+    pub width: u32,
+}
+
+impl RegisterDescriptor {
+    pub fn mask(&self) -> u128 {
+        if self.width >= 128 { u128::MAX } else { (1u128 << self.width) - 1 }
+    }
+}
+
+// Saved contents of the banked slots of one register bank.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterBank(Vec<u128>);
+
+// Banked slots have one copy per bank, the others are shared by every bank.
+// `slots` always holds the live values of the current bank, the inactive
+// banks are kept in `banks` and swapped in by `select_bank`.
+#[derive(Debug, Clone)]
+pub struct RegisterFile {
+    descriptors: &'static [RegisterDescriptor],
+    slots: Vec<u128>,
+    banked: Vec<usize>,
+    banks: Vec<Vec<u128>>,
+    current_bank: usize,
+}
+
+impl RegisterFile {
+    pub fn new(descriptors: &'static [RegisterDescriptor]) -> Self {
+        Self::with_banks(descriptors, 1, &[])
+    }
+
+    // Every slot of a register in `banked` is banked, aliases included.
+    pub fn with_banks(descriptors: &'static [RegisterDescriptor], banks: usize, banked: &[usize]) -> Self {
+        if banks == 0 {
+            panic!("Register file needs at least one bank");
+        }
+        let slots = descriptors.iter().map(|x| x.slot + 1).max().unwrap_or(0);
+        let mut banked: Vec<usize> = banked.iter()
+            .map(|&index| descriptors.get(index).unwrap_or_else(|| panic!("Invalid register index: {index}")).slot)
+            .collect();
+        banked.sort_unstable();
+        banked.dedup();
+        Self {
+            descriptors,
+            slots: vec![0; slots],
+            banks: vec![vec![0; banked.len()]; banks],
+            banked,
+            current_bank: 0,
+        }
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.banks.len()
+    }
+
+    pub fn current_bank(&self) -> usize {
+        self.current_bank
+    }
+
+    pub fn is_banked(&self, index: impl Into<usize>) -> Result<bool, RegisterError> {
+        let descriptor = self.descriptor(index.into())?;
+        Ok(self.banked.binary_search(&descriptor.slot).is_ok())
+    }
+
+    fn assert_bank(&self, bank: usize) -> Result<(), RegisterError> {
+        if bank >= self.banks.len() {
+            return Err(RegisterError::InvalidBank(bank));
+        }
+        Ok(())
+    }
+
+    pub fn select_bank(&mut self, bank: usize) -> Result<(), RegisterError> {
+        self.assert_bank(bank)?;
+        if bank == self.current_bank {
+            return Ok(());
+        }
+        let current = self.current_bank;
+        for (i, &slot) in self.banked.iter().enumerate() {
+            self.banks[current][i] = self.slots[slot];
+            self.slots[slot] = self.banks[bank][i];
+        }
+        self.current_bank = bank;
+        Ok(())
+    }
+
+    pub fn save_bank(&self, bank: usize) -> Result<RegisterBank, RegisterError> {
+        self.assert_bank(bank)?;
+        if bank == self.current_bank {
+            return Ok(RegisterBank(self.banked.iter().map(|&slot| self.slots[slot]).collect()));
+        }
+        Ok(RegisterBank(self.banks[bank].clone()))
+    }
+
+    pub fn restore_bank(&mut self, bank: usize, saved: &RegisterBank) -> Result<(), RegisterError> {
+        self.assert_bank(bank)?;
+        if saved.0.len() != self.banked.len() {
+            return Err(RegisterError::BankSizeMismatch { expected: self.banked.len(), found: saved.0.len() });
+        }
+        if bank == self.current_bank {
+            for (&slot, &value) in self.banked.iter().zip(&saved.0) {
+                self.slots[slot] = value;
+            }
+        } else {
+            self.banks[bank].copy_from_slice(&saved.0);
+        }
+        Ok(())
+    }
+
+    // Exchanges the slot of one banked register with its copy in another bank
+    // without switching banks, as Z80 `EX AF, AF'` does.
+    pub fn swap_with_bank(&mut self, index: impl Into<usize>, bank: usize) -> Result<(), RegisterError> {
+        let index = index.into();
+        self.assert_bank(bank)?;
+        let slot = self.descriptor(index)?.slot;
+        let i = self.banked.binary_search(&slot).map_err(|_| RegisterError::InvalidIndex(index))?;
+        if bank != self.current_bank {
+            std::mem::swap(&mut self.slots[slot], &mut self.banks[bank][i]);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descriptors.is_empty()
+    }
+
+    pub fn descriptors(&self) -> &'static [RegisterDescriptor] {
+        self.descriptors
+    }
+
+    pub fn descriptor(&self, index: usize) -> Result<&'static RegisterDescriptor, RegisterError> {
+        self.descriptors.get(index).ok_or(RegisterError::InvalidIndex(index))
+    }
+
+    pub fn index_of(&self, name: &str) -> Result<usize, RegisterError> {
+        self.descriptors.iter()
+            .position(|x| x.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| RegisterError::UnknownName(name.to_string()))
+    }
+
+    pub fn get(&self, index: impl Into<usize>) -> Result<u128, RegisterError> {
+        let descriptor = self.descriptor(index.into())?;
+        Ok((self.slots[descriptor.slot] >> descriptor.offset) & descriptor.mask())
+    }
+
+    // Only the bits covered by the register are written, the rest of the slot
+    // is left untouched. Values wider than the register are rejected.
+    pub fn set(&mut self, index: impl Into<usize>, value: u128) -> Result<(), RegisterError> {
+        let descriptor = self.descriptor(index.into())?;
+        if value & !descriptor.mask() != 0 {
+            return Err(RegisterError::OutOfRange(value));
+        }
+        let mask = descriptor.mask() << descriptor.offset;
+        let slot = &mut self.slots[descriptor.slot];
+        *slot = (*slot & !mask) | ((value << descriptor.offset) & mask);
+        Ok(())
+    }
+
+    pub fn get_value(&self, index: impl Into<usize>) -> Result<RegisterValue, RegisterError> {
+        let index = index.into();
+        Ok(RegisterValue::from_bits(self.descriptor(index)?.width, self.get(index)?))
+    }
+
+    pub fn set_value(&mut self, index: impl Into<usize>, value: RegisterValue) -> Result<(), RegisterError> {
+        self.set(index, value.to_bits())
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Result<u128, RegisterError> {
+        self.get(self.index_of(name)?)
+    }
+
+    pub fn set_by_name(&mut self, name: &str, value: u128) -> Result<(), RegisterError> {
+        self.set(self.index_of(name)?, value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static RegisterDescriptor, u128)> + '_ {
+        self.descriptors.iter().enumerate().map(|(index, descriptor)| (descriptor, self.get(index).unwrap()))
+    }
+
+    pub fn reset(&mut self) {
+        self.slots.fill(0);
+        self.banks.iter_mut().for_each(|bank| bank.fill(0));
+        self.current_bank = 0;
+    }
+}
+
+#[macro_export]
+macro_rules! define_register_file {
+    ( $name:ident, { $( $register:ident : $width:literal $( { $( $alias:ident : $alias_width:literal $( @ $offset:literal )? ),* $(,)? } )? ),* $(,)? } ) => {
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(usize)]
+        pub enum $name {
+            $( $register, $( $( $alias, )* )? )*
+        }
+        impl $name {
+            pub const DESCRIPTORS: &'static [$crate::register::RegisterDescriptor] = {
+                #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+                enum Slot {
+                    $( $register ),*
+                }
+                &[
+                    $(
+                        $crate::register::RegisterDescriptor { name: stringify!($register), slot: Slot::$register as usize, offset: 0, width: $width },
+                        $( $(
+                            $crate::register::RegisterDescriptor { name: stringify!($alias), slot: Slot::$register as usize, offset: 0 $( + $offset )?, width: $alias_width },
+                        )* )?
+                    )*
+                ]
+            };
+
+            pub fn register_file() -> $crate::register::RegisterFile {
+                $crate::register::RegisterFile::new(Self::DESCRIPTORS)
+            }
+
+            pub fn banked_register_file(banks: usize, banked: &[$name]) -> $crate::register::RegisterFile {
+                let banked: Vec<usize> = banked.iter().map(|&register| register as usize).collect();
+                $crate::register::RegisterFile::with_banks(Self::DESCRIPTORS, banks, &banked)
+            }
+        }
+        impl From<$name> for usize {
+            fn from(register: $name) -> usize {
+                register as usize
+            }
+        }
+    };
+}
@@ -0,0 +1,1136 @@
+use avm_rs_memory::{access_memory, mem::{Memory, MemorySliceTrait}};
+
+use crate::{cpu::{CPUFault, CPUTrait, StepResult}, debug::{DebugTarget, InstructionKind}, define_isa, define_register_file, flags::FlagsRegister, float::FloatControl, interrupt::InterruptTarget, isa::{FReg, Reg, SImm}, register::{ArithmeticResult, DynRegisterTrait, RegisterError, RegisterF64, RegisterFile, RegisterU64, RegisterValue}};
+
+// Register machine with 16 integer registers r0-r15, 16 float registers
+// f0-f15, a flags register and fixed 32-bit big-endian instruction words.
+// By convention r14 is the stack pointer and r15 the link register.
+//
+// Word layout, bit 31 first:
+//
+//   R  | opcode:8 | rd:4 | rs1:4 | rs2:4 | 0:12         |
+//   I  | opcode:8 | rd:4 | rs1:4 | imm:16              |
+//   K  | opcode:8 | rd:4 | 0:2 | shift:2 | imm:16      |
+//   B  | opcode:12       | offset:20                   |
+//   J  | opcode:8 | offset:24                          |
+//
+// Branch, jump and call offsets are signed and counted in words from the
+// address of the instruction itself. Integer ALU instructions set zero, sign
+// and parity; add, sub, mul and cmp also set carry and overflow like x86,
+// carry being the borrow for sub and cmp. `fcmp` sets zero for equal, carry
+// for less and parity for unordered. Loads and stores address
+// `rs1 + sign-extended imm` and use the memory's byte order.
+//
+// Interrupt entry pushes the program counter, then the flags zero-extended
+// to 64 bits, on the descending r14 stack and clears the interrupt enable
+// flag, `iret` pops both. A fault leaves the program counter on the faulting
+// instruction, so its handler returns past it by adding 4 to the saved
+// program counter at `r14 + 8`. Entries nest, each on its own stack frame.
+#[derive(Debug)]
+pub struct RegisterCPU {
+    memory: Memory,
+    pub registers: RegisterFile,
+    pub flags: FlagsRegister,
+    pub float_control: FloatControl,
+    pub entry: u64,
+    pub pc: u64,
+    next_pc: u64,
+    halted: bool,
+    waiting: bool,
+    // Whether the link register holds a return address. Writing it makes it
+    // one, `ret` leaves it stale.
+    link_live: bool,
+}
+
+pub const STACK_POINTER: Reg = Reg(14);
+pub const LINK_REGISTER: Reg = Reg(15);
+
+// Integer registers come first, so `Reg(n)` is register file index n and
+// `FReg(n)` index 16 + n.
+define_register_file!(RegisterName, {
+    r0: 64, r1: 64, r2: 64, r3: 64, r4: 64, r5: 64, r6: 64, r7: 64,
+    r8: 64, r9: 64, r10: 64, r11: 64, r12: 64, r13: 64, r14: 64, r15: 64,
+    f0: 64, f1: 64, f2: 64, f3: 64, f4: 64, f5: 64, f6: 64, f7: 64,
+    f8: 64, f9: 64, f10: 64, f11: 64, f12: 64, f13: 64, f14: 64, f15: 64,
+});
+
+define_isa!(RegisterInstruction, u32, RegisterCPU, {
+    Nop = "nop" [0xFF00_0000, 0x0000_0000] () => |cpu| { StepResult::Continue },
+    Halt = "halt" [0xFF00_0000, 0x0100_0000] () => |cpu| {
+        cpu.halted = true;
+        StepResult::Halted
+    },
+    Break = "brk" [0xFF00_0000, 0x0200_0000] () => |cpu| { StepResult::Breakpoint },
+    Wait = "wait" [0xFF00_0000, 0x0300_0000] () => |cpu| {
+        cpu.waiting = true;
+        StepResult::WaitingForInterrupt
+    },
+
+    Add = "add" [0xFF00_0000, 0x1000_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.arithmetic(rd, cpu.operand(rs1).overflowing_add(cpu.operand(rs2)))
+    },
+    Sub = "sub" [0xFF00_0000, 0x1100_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.arithmetic(rd, cpu.operand(rs1).overflowing_sub(cpu.operand(rs2)))
+    },
+    Mul = "mul" [0xFF00_0000, 0x1200_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.arithmetic(rd, cpu.operand(rs1).overflowing_mul(cpu.operand(rs2)))
+    },
+    DivU = "divu" [0xFF00_0000, 0x1300_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.divide(rd, rs1, rs2, |a, b| a / b)
+    },
+    DivS = "divs" [0xFF00_0000, 0x1400_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.divide(rd, rs1, rs2, |a, b| (a as i64).wrapping_div(b as i64) as u64)
+    },
+    RemU = "remu" [0xFF00_0000, 0x1500_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.divide(rd, rs1, rs2, |a, b| a % b)
+    },
+    RemS = "rems" [0xFF00_0000, 0x1600_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.divide(rd, rs1, rs2, |a, b| (a as i64).wrapping_rem(b as i64) as u64)
+    },
+    And = "and" [0xFF00_0000, 0x1700_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1) & cpu.register(rs2))
+    },
+    Or = "or" [0xFF00_0000, 0x1800_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1) | cpu.register(rs2))
+    },
+    Xor = "xor" [0xFF00_0000, 0x1900_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1) ^ cpu.register(rs2))
+    },
+    Shl = "shl" [0xFF00_0000, 0x1A00_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1).wrapping_shl(cpu.register(rs2) as u32))
+    },
+    Shr = "shr" [0xFF00_0000, 0x1B00_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1).wrapping_shr(cpu.register(rs2) as u32))
+    },
+    Sar = "sar" [0xFF00_0000, 0x1C00_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        cpu.logic(rd, (cpu.register(rs1) as i64).wrapping_shr(cpu.register(rs2) as u32) as u64)
+    },
+    Cmp = "cmp" [0xFF00_0000, 0x1D00_0000] (rs1: Reg = 16..20, rs2: Reg = 12..16) => |cpu| {
+        let result = cpu.operand(rs1).overflowing_sub(cpu.operand(rs2));
+        cpu.flags.update_from_result(&result);
+        StepResult::Continue
+    },
+    Mov = "mov" [0xFF00_0000, 0x1E00_0000] (rd: Reg = 20..24, rs1: Reg = 16..20) => |cpu| {
+        cpu.set_register(rd, cpu.register(rs1));
+        StepResult::Continue
+    },
+    Not = "not" [0xFF00_0000, 0x1F00_0000] (rd: Reg = 20..24, rs1: Reg = 16..20) => |cpu| {
+        cpu.logic(rd, !cpu.register(rs1))
+    },
+
+    AddI = "addi" [0xFF00_0000, 0x2000_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.arithmetic(rd, cpu.operand(rs1).overflowing_add(RegisterU64(imm.0 as u64)))
+    },
+    AndI = "andi" [0xFF00_0000, 0x2200_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: Imm = 0..16) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1) & imm.0)
+    },
+    OrI = "ori" [0xFF00_0000, 0x2300_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: Imm = 0..16) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1) | imm.0)
+    },
+    XorI = "xori" [0xFF00_0000, 0x2400_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: Imm = 0..16) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1) ^ imm.0)
+    },
+    ShlI = "shli" [0xFF00_0000, 0x2500_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: Imm = 0..6) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1) << imm.0)
+    },
+    ShrI = "shri" [0xFF00_0000, 0x2600_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: Imm = 0..6) => |cpu| {
+        cpu.logic(rd, cpu.register(rs1) >> imm.0)
+    },
+    SarI = "sari" [0xFF00_0000, 0x2700_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: Imm = 0..6) => |cpu| {
+        cpu.logic(rd, ((cpu.register(rs1) as i64) >> imm.0) as u64)
+    },
+    CmpI = "cmpi" [0xFF00_0000, 0x2800_0000] (rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        let result = cpu.operand(rs1).overflowing_sub(RegisterU64(imm.0 as u64));
+        cpu.flags.update_from_result(&result);
+        StepResult::Continue
+    },
+    MovI = "movi" [0xFF00_0000, 0x2900_0000] (rd: Reg = 20..24, imm: SImm = 0..16) => |cpu| {
+        cpu.set_register(rd, imm.0 as u64);
+        StepResult::Continue
+    },
+    // Replaces bits `16 * shift` to `16 * shift + 15` of rd, building wide
+    // constants after `movi`.
+    MovK = "movk" [0xFF0C_0000, 0x2A00_0000] (rd: Reg = 20..24, shift: Imm = 16..18, imm: Imm = 0..16) => |cpu| {
+        let shift = shift.0 * 16;
+        cpu.set_register(rd, (cpu.register(rd) & !(0xFFFF << shift)) | (imm.0 << shift));
+        StepResult::Continue
+    },
+
+    Load8U = "ld8u" [0xFF00_0000, 0x3000_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.load(rd, rs1, imm, 1, |memory, at| memory.read_u8(at) as u64)
+    },
+    Load16U = "ld16u" [0xFF00_0000, 0x3100_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.load(rd, rs1, imm, 2, |memory, at| memory.read_u16(at) as u64)
+    },
+    Load32U = "ld32u" [0xFF00_0000, 0x3200_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.load(rd, rs1, imm, 4, |memory, at| memory.read_u32(at) as u64)
+    },
+    Load64 = "ld64" [0xFF00_0000, 0x3300_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.load(rd, rs1, imm, 8, |memory, at| memory.read_u64(at))
+    },
+    Load8S = "ld8s" [0xFF00_0000, 0x3400_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.load(rd, rs1, imm, 1, |memory, at| memory.read_i8(at) as u64)
+    },
+    Load16S = "ld16s" [0xFF00_0000, 0x3500_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.load(rd, rs1, imm, 2, |memory, at| memory.read_i16(at) as u64)
+    },
+    Load32S = "ld32s" [0xFF00_0000, 0x3600_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.load(rd, rs1, imm, 4, |memory, at| memory.read_i32(at) as u64)
+    },
+    Store8 = "st8" [0xFF00_0000, 0x3800_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.store(rd, rs1, imm, 1, |memory, at, value| memory.write_u8(at, value as u8))
+    },
+    Store16 = "st16" [0xFF00_0000, 0x3900_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.store(rd, rs1, imm, 2, |memory, at, value| memory.write_u16(at, value as u16))
+    },
+    Store32 = "st32" [0xFF00_0000, 0x3A00_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.store(rd, rs1, imm, 4, |memory, at, value| memory.write_u32(at, value as u32))
+    },
+    Store64 = "st64" [0xFF00_0000, 0x3B00_0000] (rd: Reg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        cpu.store(rd, rs1, imm, 8, |memory, at, value| memory.write_u64(at, value))
+    },
+
+    FAdd = "fadd" [0xFF00_0000, 0x4000_0000] (fd: FReg = 20..24, fs1: FReg = 16..20, fs2: FReg = 12..16) => |cpu| {
+        let value = cpu.float_register(fs1).add_with(cpu.float_register(fs2), &mut cpu.float_control);
+        cpu.set_float_register(fd, value);
+        StepResult::Continue
+    },
+    FSub = "fsub" [0xFF00_0000, 0x4100_0000] (fd: FReg = 20..24, fs1: FReg = 16..20, fs2: FReg = 12..16) => |cpu| {
+        let value = cpu.float_register(fs1).sub_with(cpu.float_register(fs2), &mut cpu.float_control);
+        cpu.set_float_register(fd, value);
+        StepResult::Continue
+    },
+    FMul = "fmul" [0xFF00_0000, 0x4200_0000] (fd: FReg = 20..24, fs1: FReg = 16..20, fs2: FReg = 12..16) => |cpu| {
+        let value = cpu.float_register(fs1).mul_with(cpu.float_register(fs2), &mut cpu.float_control);
+        cpu.set_float_register(fd, value);
+        StepResult::Continue
+    },
+    FDiv = "fdiv" [0xFF00_0000, 0x4300_0000] (fd: FReg = 20..24, fs1: FReg = 16..20, fs2: FReg = 12..16) => |cpu| {
+        let value = cpu.float_register(fs1).div_with(cpu.float_register(fs2), &mut cpu.float_control);
+        cpu.set_float_register(fd, value);
+        StepResult::Continue
+    },
+    FSqrt = "fsqrt" [0xFF00_0000, 0x4400_0000] (fd: FReg = 20..24, fs1: FReg = 16..20) => |cpu| {
+        let value = cpu.float_register(fs1).sqrt_with(&mut cpu.float_control);
+        cpu.set_float_register(fd, value);
+        StepResult::Continue
+    },
+    FMov = "fmov" [0xFF00_0000, 0x4500_0000] (fd: FReg = 20..24, fs1: FReg = 16..20) => |cpu| {
+        cpu.set_float_register(fd, cpu.float_register(fs1));
+        StepResult::Continue
+    },
+    FCmp = "fcmp" [0xFF00_0000, 0x4600_0000] (fs1: FReg = 16..20, fs2: FReg = 12..16) => |cpu| {
+        let (a, b) = (cpu.float_register(fs1).0, cpu.float_register(fs2).0);
+        let unordered = a.is_nan() || b.is_nan();
+        cpu.flags.remove(FlagsRegister::SIGN | FlagsRegister::OVERFLOW);
+        cpu.flags.set_flags(FlagsRegister::ZERO, unordered || a == b);
+        cpu.flags.set_flags(FlagsRegister::CARRY, unordered || a < b);
+        cpu.flags.set_flags(FlagsRegister::PARITY, unordered);
+        StepResult::Continue
+    },
+    // Signed integer to float, rounded to nearest.
+    FFromInt = "fcvtif" [0xFF00_0000, 0x4700_0000] (fd: FReg = 20..24, rs1: Reg = 16..20) => |cpu| {
+        cpu.set_float_register(fd, RegisterF64(cpu.register(rs1) as i64 as f64));
+        StepResult::Continue
+    },
+    // Float to signed integer, truncated and saturated, NaN becomes zero.
+    FToInt = "fcvtfi" [0xFF00_0000, 0x4800_0000] (rd: Reg = 20..24, fs1: FReg = 16..20) => |cpu| {
+        cpu.set_register(rd, cpu.float_register(fs1).0 as i64 as u64);
+        StepResult::Continue
+    },
+    FLoad = "fld" [0xFF00_0000, 0x4900_0000] (fd: FReg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        let address = cpu.address(rs1, imm);
+        match cpu.access(address, 8, |memory, at| memory.read_f64(at)) {
+            Ok(value) => {
+                cpu.set_float_register(fd, RegisterF64(value));
+                StepResult::Continue
+            }
+            Err(fault) => StepResult::Fault(fault),
+        }
+    },
+    FStore = "fst" [0xFF00_0000, 0x4A00_0000] (fd: FReg = 20..24, rs1: Reg = 16..20, imm: SImm = 0..16) => |cpu| {
+        let (address, value) = (cpu.address(rs1, imm), cpu.float_register(fd).0);
+        match cpu.access_mut(address, 8, |memory, at| memory.write_f64(at, value)) {
+            Ok(()) => StepResult::Continue,
+            Err(fault) => StepResult::Fault(fault),
+        }
+    },
+
+    Branch = "b" [0xFFF0_0000, 0x5000_0000] (offset: SImm = 0..20) => |cpu| { cpu.branch(true, offset) },
+    BranchEq = "beq" [0xFFF0_0000, 0x5010_0000] (offset: SImm = 0..20) => |cpu| { cpu.branch(cpu.flags.zero(), offset) },
+    BranchNe = "bne" [0xFFF0_0000, 0x5020_0000] (offset: SImm = 0..20) => |cpu| { cpu.branch(!cpu.flags.zero(), offset) },
+    BranchLt = "blt" [0xFFF0_0000, 0x5030_0000] (offset: SImm = 0..20) => |cpu| {
+        cpu.branch(cpu.flags.sign() != cpu.flags.overflow(), offset)
+    },
+    BranchGe = "bge" [0xFFF0_0000, 0x5040_0000] (offset: SImm = 0..20) => |cpu| {
+        cpu.branch(cpu.flags.sign() == cpu.flags.overflow(), offset)
+    },
+    BranchLe = "ble" [0xFFF0_0000, 0x5050_0000] (offset: SImm = 0..20) => |cpu| {
+        cpu.branch(cpu.flags.zero() || cpu.flags.sign() != cpu.flags.overflow(), offset)
+    },
+    BranchGt = "bgt" [0xFFF0_0000, 0x5060_0000] (offset: SImm = 0..20) => |cpu| {
+        cpu.branch(!cpu.flags.zero() && cpu.flags.sign() == cpu.flags.overflow(), offset)
+    },
+    BranchLtU = "bltu" [0xFFF0_0000, 0x5070_0000] (offset: SImm = 0..20) => |cpu| { cpu.branch(cpu.flags.carry(), offset) },
+    BranchGeU = "bgeu" [0xFFF0_0000, 0x5080_0000] (offset: SImm = 0..20) => |cpu| { cpu.branch(!cpu.flags.carry(), offset) },
+    BranchLeU = "bleu" [0xFFF0_0000, 0x5090_0000] (offset: SImm = 0..20) => |cpu| {
+        cpu.branch(cpu.flags.carry() || cpu.flags.zero(), offset)
+    },
+    BranchGtU = "bgtu" [0xFFF0_0000, 0x50A0_0000] (offset: SImm = 0..20) => |cpu| {
+        cpu.branch(!cpu.flags.carry() && !cpu.flags.zero(), offset)
+    },
+    BranchNeg = "bneg" [0xFFF0_0000, 0x50B0_0000] (offset: SImm = 0..20) => |cpu| { cpu.branch(cpu.flags.sign(), offset) },
+    BranchPos = "bpos" [0xFFF0_0000, 0x50C0_0000] (offset: SImm = 0..20) => |cpu| { cpu.branch(!cpu.flags.sign(), offset) },
+    BranchOverflow = "bvs" [0xFFF0_0000, 0x50D0_0000] (offset: SImm = 0..20) => |cpu| { cpu.branch(cpu.flags.overflow(), offset) },
+    BranchNoOverflow = "bvc" [0xFFF0_0000, 0x50E0_0000] (offset: SImm = 0..20) => |cpu| { cpu.branch(!cpu.flags.overflow(), offset) },
+
+    Call = "call" [0xFF00_0000, 0x5200_0000] (offset: SImm = 0..24) => |cpu| {
+        cpu.set_register(LINK_REGISTER, cpu.next_pc);
+        cpu.branch(true, offset)
+    },
+    Ret = "ret" [0xFF00_0000, 0x5300_0000] () => |cpu| {
+        cpu.next_pc = cpu.register(LINK_REGISTER);
+        cpu.link_live = false;
+        StepResult::Continue
+    },
+    JumpRegister = "jr" [0xFF00_0000, 0x5400_0000] (rs1: Reg = 16..20) => |cpu| {
+        cpu.next_pc = cpu.register(rs1);
+        StepResult::Continue
+    },
+    CallRegister = "callr" [0xFF00_0000, 0x5500_0000] (rs1: Reg = 16..20) => |cpu| {
+        let target = cpu.register(rs1);
+        cpu.set_register(LINK_REGISTER, cpu.next_pc);
+        cpu.next_pc = target;
+        StepResult::Continue
+    },
+
+    EnableInterrupts = "ei" [0xFF00_0000, 0x6000_0000] () => |cpu| {
+        cpu.flags.insert(FlagsRegister::INTERRUPT_ENABLE);
+        StepResult::Continue
+    },
+    DisableInterrupts = "di" [0xFF00_0000, 0x6100_0000] () => |cpu| {
+        cpu.flags.remove(FlagsRegister::INTERRUPT_ENABLE);
+        StepResult::Continue
+    },
+    InterruptReturn = "iret" [0xFF00_0000, 0x6200_0000] () => |cpu| {
+        let sp = cpu.register(STACK_POINTER);
+        match cpu.access(sp, 16, |memory, at| (memory.read_u64(at), memory.read_u64(at + 8))) {
+            Ok((flags, pc)) => {
+                cpu.flags = FlagsRegister(flags as u32);
+                cpu.set_register(STACK_POINTER, sp + 16);
+                cpu.next_pc = pc;
+                StepResult::Continue
+            }
+            Err(fault) => StepResult::Fault(fault),
+        }
+    },
+});
+
+impl RegisterCPU {
+    pub fn new(memory: Memory, entry: u64) -> Self {
+        Self {
+            memory,
+            registers: RegisterName::register_file(),
+            flags: FlagsRegister::default(),
+            float_control: FloatControl::default(),
+            entry,
+            pc: entry,
+            next_pc: entry,
+            halted: false,
+            waiting: false,
+            link_live: false,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Register numbers come from 4-bit instruction fields, so they're always
+    // in the register file.
+    pub fn register(&self, register: Reg) -> u64 {
+        self.registers.get(register.0 as usize).unwrap() as u64
+    }
+
+    pub fn set_register(&mut self, register: Reg, value: u64) {
+        self.registers.set(register.0 as usize, value as u128).unwrap();
+        self.link_live |= register == LINK_REGISTER;
+    }
+
+    pub fn float_register(&self, register: FReg) -> RegisterF64 {
+        RegisterF64(f64::from_bits(self.registers.get(16 + register.0 as usize).unwrap() as u64))
+    }
+
+    pub fn set_float_register(&mut self, register: FReg, value: RegisterF64) {
+        self.registers.set(16 + register.0 as usize, value.0.to_bits() as u128).unwrap();
+    }
+
+    fn operand(&self, register: Reg) -> RegisterU64 {
+        RegisterU64(self.register(register))
+    }
+
+    fn arithmetic(&mut self, rd: Reg, result: ArithmeticResult<RegisterU64>) -> StepResult {
+        self.flags.update_from_result(&result);
+        self.set_register(rd, result.value.0);
+        StepResult::Continue
+    }
+
+    fn logic(&mut self, rd: Reg, value: u64) -> StepResult {
+        self.flags.update_from_value(&RegisterU64(value));
+        self.set_register(rd, value);
+        StepResult::Continue
+    }
+
+    fn divide(&mut self, rd: Reg, rs1: Reg, rs2: Reg, f: impl FnOnce(u64, u64) -> u64) -> StepResult {
+        let (a, b) = (self.register(rs1), self.register(rs2));
+        if b == 0 {
+            return StepResult::Fault(CPUFault::DivideByZero { address: self.pc });
+        }
+        self.logic(rd, f(a, b))
+    }
+
+    fn branch(&mut self, condition: bool, offset: SImm) -> StepResult {
+        if condition {
+            self.next_pc = self.pc.wrapping_add((offset.0 * 4) as u64);
+        }
+        StepResult::Continue
+    }
+
+    fn address(&self, base: Reg, offset: SImm) -> u64 {
+        self.register(base).wrapping_add(offset.0 as u64)
+    }
+
+    fn access<T>(&self, address: u64, width: usize, read: impl FnOnce(&dyn MemorySliceTrait, usize) -> T) -> Result<T, CPUFault> {
+        let memory = access_memory!(self.memory);
+        if address.checked_add(width as u64).is_none_or(|end| end > memory.len() as u64) {
+            return Err(CPUFault::MemoryFault { address });
+        }
+        Ok(read(&*memory, address as usize))
+    }
+
+    fn access_mut(&self, address: u64, width: usize, write: impl FnOnce(&mut dyn MemorySliceTrait, usize)) -> Result<(), CPUFault> {
+        let mut memory = access_memory!(self.memory);
+        if address.checked_add(width as u64).is_none_or(|end| end > memory.len() as u64) {
+            return Err(CPUFault::MemoryFault { address });
+        }
+        write(&mut *memory, address as usize);
+        Ok(())
+    }
+
+    fn load(&mut self, rd: Reg, base: Reg, offset: SImm, width: usize, read: impl FnOnce(&dyn MemorySliceTrait, usize) -> u64) -> StepResult {
+        match self.access(self.address(base, offset), width, read) {
+            Ok(value) => {
+                self.set_register(rd, value);
+                StepResult::Continue
+            }
+            Err(fault) => StepResult::Fault(fault),
+        }
+    }
+
+    fn store(&mut self, source: Reg, base: Reg, offset: SImm, width: usize, write: impl FnOnce(&mut dyn MemorySliceTrait, usize, u64)) -> StepResult {
+        let value = self.register(source);
+        match self.access_mut(self.address(base, offset), width, |memory, at| write(memory, at, value)) {
+            Ok(()) => StepResult::Continue,
+            Err(fault) => StepResult::Fault(fault),
+        }
+    }
+}
+
+impl CPUTrait for RegisterCPU {
+    fn reset(&mut self) {
+        self.registers.reset();
+        self.flags = FlagsRegister::default();
+        self.float_control = FloatControl::default();
+        self.pc = self.entry;
+        self.next_pc = self.entry;
+        self.halted = false;
+        self.waiting = false;
+        self.link_live = false;
+    }
+
+    fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    fn start_at(&mut self, entry: u64) {
+        self.entry = entry;
+        self.reset();
+    }
+
+    // A faulting instruction leaves the program counter on itself.
+    fn step(&mut self) -> StepResult {
+        if self.halted {
+            return StepResult::Halted;
+        }
+        if self.waiting {
+            return StepResult::WaitingForInterrupt;
+        }
+        let word = match self.access(self.pc, 4, |memory, at| memory.fetch_u32(at)) {
+            Ok(word) => word,
+            Err(fault) => return StepResult::Fault(fault),
+        };
+        let Some(instruction) = RegisterInstruction::decode(word) else {
+            return StepResult::Fault(CPUFault::IllegalInstruction { address: self.pc, opcode: word });
+        };
+        self.next_pc = self.pc.wrapping_add(4);
+        let result = instruction.execute(self);
+        if !matches!(result, StepResult::Fault(_)) {
+            self.pc = self.next_pc;
+        }
+        result
+    }
+}
+
+impl InterruptTarget for RegisterCPU {
+    fn interrupts_enabled(&self) -> bool {
+        self.flags.interrupt_enable()
+    }
+
+    fn enter_interrupt(&mut self, _vector: usize, handler: u64) -> Result<(), CPUFault> {
+        let sp = self.register(STACK_POINTER).wrapping_sub(16);
+        let (pc, flags) = (self.pc, self.flags.0 as u64);
+        self.access_mut(sp, 16, |memory, at| {
+            memory.write_u64(at, flags);
+            memory.write_u64(at + 8, pc);
+        })?;
+        self.set_register(STACK_POINTER, sp);
+        self.flags.remove(FlagsRegister::INTERRUPT_ENABLE);
+        self.waiting = false;
+        self.pc = handler;
+        Ok(())
+    }
+}
+
+// Registers are r0-r15, f0-f15, flags and pc. Only the innermost return
+// address is known, the one in the link register, as callers save it
+// wherever they like.
+impl DebugTarget for RegisterCPU {
+    fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    fn set_pc(&mut self, pc: u64) {
+        self.pc = pc;
+        self.next_pc = pc;
+    }
+
+    fn registers(&self) -> Vec<(&'static str, RegisterValue)> {
+        let mut registers: Vec<_> = self.registers.iter()
+            .map(|(descriptor, bits)| match descriptor.slot {
+                0..=15 => (descriptor.name, RegisterValue::U64(bits as u64)),
+                _ => (descriptor.name, RegisterValue::F64(f64::from_bits(bits as u64))),
+            })
+            .collect();
+        registers.push(("flags", self.flags.value()));
+        registers.push(("pc", RegisterValue::U64(self.pc)));
+        registers
+    }
+
+    fn set_register(&mut self, index: usize, value: RegisterValue) -> Result<(), RegisterError> {
+        match index {
+            0..=31 if value.width() != 64 => Err(RegisterError::WidthMismatch { expected: 64, found: value.width() }),
+            0..=31 if value.is_float() != (index >= 16) => Err(RegisterError::KindMismatch { float: index >= 16 }),
+            0..=31 => {
+                self.registers.set_value(index, value)?;
+                self.link_live |= index == LINK_REGISTER.0 as usize;
+                Ok(())
+            }
+            32 => self.flags.set_value(value),
+            33 => {
+                let mut pc = RegisterU64(self.pc);
+                pc.set_value(value)?;
+                self.set_pc(pc.0);
+                Ok(())
+            }
+            _ => Err(RegisterError::InvalidIndex(index)),
+        }
+    }
+
+    fn instruction_kind(&self, address: u64) -> Option<InstructionKind> {
+        let word = self.access(address, 4, |memory, at| memory.fetch_u32(at)).ok()?;
+        Some(match RegisterInstruction::decode(word)? {
+            RegisterInstruction::Call { .. } | RegisterInstruction::CallRegister { .. } => InstructionKind::Call,
+            RegisterInstruction::Ret {} => InstructionKind::Return,
+            _ => InstructionKind::Other,
+        })
+    }
+
+    // Only the link register, and only until `ret` uses it. Calls further
+    // out keep their return addresses wherever their callees saved them.
+    fn return_addresses(&self) -> Vec<u64> {
+        match self.link_live {
+            true => vec![self.register(LINK_REGISTER)],
+            false => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use avm_rs_memory::mem::create_memory;
+
+    use crate::{interrupt::{create_interrupt_controller, InterruptSystem, InterruptVectorTable, DIVIDE_BY_ZERO_VECTOR, IRQ_BASE}, isa::Imm};
+
+    use super::{RegisterInstruction::*, *};
+
+    const MEMORY_SIZE: usize = 0x1000;
+    const STACK: u64 = 0x1000;
+    const DATA: i64 = 0x600;
+    const HANDLER: u64 = 0x400;
+    const VECTOR_TABLE: usize = 0x800;
+
+    fn write(cpu: &RegisterCPU, at: u64, program: &[RegisterInstruction]) {
+        let mut memory = access_memory!(cpu.memory);
+        for (index, instruction) in program.iter().enumerate() {
+            memory.write_u32(at as usize + index * 4, instruction.encode());
+        }
+    }
+
+    fn load(program: &[RegisterInstruction]) -> RegisterCPU {
+        let mut cpu = RegisterCPU::new(create_memory(MEMORY_SIZE), 0);
+        cpu.set_register(STACK_POINTER, STACK);
+        write(&cpu, 0, program);
+        cpu
+    }
+
+    // Runs `program` and expects it to halt.
+    fn run(program: &[RegisterInstruction]) -> RegisterCPU {
+        let mut cpu = load(program);
+        assert_eq!(cpu.run_for(100), StepResult::Halted);
+        cpu
+    }
+
+    fn interrupt_system(cpu: &RegisterCPU, lines: usize) -> InterruptSystem {
+        let table = InterruptVectorTable::new(cpu.memory.clone(), VECTOR_TABLE, IRQ_BASE + lines);
+        InterruptSystem::new(create_interrupt_controller(lines), table)
+    }
+
+    fn r(register: u8) -> Reg {
+        Reg(register)
+    }
+
+    fn f(register: u8) -> FReg {
+        FReg(register)
+    }
+
+    fn movi(rd: u8, imm: i64) -> RegisterInstruction {
+        MovI { rd: r(rd), imm: SImm(imm) }
+    }
+
+    #[test]
+    fn add_sets_carry_zero_and_overflow() {
+        let cpu = run(&[movi(1, -1), movi(2, 1), Add { rd: r(3), rs1: r(1), rs2: r(2) }, Halt {}]);
+        assert_eq!(cpu.register(r(3)), 0);
+        assert!(cpu.flags.carry() && cpu.flags.zero() && cpu.flags.parity());
+        assert!(!cpu.flags.overflow() && !cpu.flags.sign());
+
+        let cpu = run(&[movi(1, -1), ShrI { rd: r(1), rs1: r(1), imm: Imm(1) }, movi(2, 1), Add { rd: r(3), rs1: r(1), rs2: r(2) }, Halt {}]);
+        assert_eq!(cpu.register(r(3)), 1 << 63);
+        assert!(cpu.flags.overflow() && cpu.flags.sign());
+        assert!(!cpu.flags.carry() && !cpu.flags.zero());
+    }
+
+    #[test]
+    fn sub_and_cmp_set_borrow() {
+        let cpu = run(&[movi(1, 1), movi(2, 2), Sub { rd: r(3), rs1: r(1), rs2: r(2) }, Halt {}]);
+        assert_eq!(cpu.register(r(3)), u64::MAX);
+        assert!(cpu.flags.carry() && cpu.flags.sign() && !cpu.flags.overflow());
+
+        let cpu = run(&[movi(1, 5), movi(2, 9), Cmp { rs1: r(1), rs2: r(1) }, Halt {}]);
+        assert!(cpu.flags.zero() && !cpu.flags.carry());
+        assert_eq!(cpu.register(r(1)), 5);
+
+        let cpu = run(&[movi(1, 5), CmpI { rs1: r(1), imm: SImm(6) }, Halt {}]);
+        assert!(cpu.flags.carry() && cpu.flags.sign() && !cpu.flags.zero());
+    }
+
+    #[test]
+    fn mul_sets_carry_and_overflow() {
+        let cpu = run(&[movi(1, -3), movi(2, 7), Mul { rd: r(3), rs1: r(1), rs2: r(2) }, Halt {}]);
+        assert_eq!(cpu.register(r(3)) as i64, -21);
+        assert!(cpu.flags.carry() && !cpu.flags.overflow());
+
+        let cpu = run(&[movi(1, 1), ShlI { rd: r(1), rs1: r(1), imm: Imm(62) }, movi(2, 2), Mul { rd: r(3), rs1: r(1), rs2: r(2) }, Halt {}]);
+        assert_eq!(cpu.register(r(3)), 1 << 63);
+        assert!(cpu.flags.overflow() && !cpu.flags.carry());
+    }
+
+    #[test]
+    fn divide_and_remainder() {
+        let cpu = run(&[
+            movi(1, -7),
+            movi(2, 2),
+            DivU { rd: r(3), rs1: r(1), rs2: r(2) },
+            DivS { rd: r(4), rs1: r(1), rs2: r(2) },
+            RemU { rd: r(5), rs1: r(1), rs2: r(2) },
+            RemS { rd: r(6), rs1: r(1), rs2: r(2) },
+            Halt {},
+        ]);
+        assert_eq!(cpu.register(r(3)), (-7i64 as u64) / 2);
+        assert_eq!(cpu.register(r(4)) as i64, -3);
+        assert_eq!(cpu.register(r(5)), 1);
+        assert_eq!(cpu.register(r(6)) as i64, -1);
+
+        let cpu = run(&[movi(1, 1), ShlI { rd: r(1), rs1: r(1), imm: Imm(63) }, movi(2, -1), DivS { rd: r(3), rs1: r(1), rs2: r(2) }, Halt {}]);
+        assert_eq!(cpu.register(r(3)), 1 << 63);
+    }
+
+    #[test]
+    fn divide_by_zero_faults_on_the_instruction() {
+        let mut cpu = load(&[movi(1, 1), movi(2, 0), movi(3, 5), DivU { rd: r(3), rs1: r(1), rs2: r(2) }, Halt {}]);
+        assert_eq!(cpu.run_for(100), StepResult::Fault(CPUFault::DivideByZero { address: 12 }));
+        assert_eq!(cpu.pc, 12);
+        assert_eq!(cpu.register(r(3)), 5);
+        assert_eq!(cpu.step(), StepResult::Fault(CPUFault::DivideByZero { address: 12 }));
+    }
+
+    #[test]
+    fn logic_sets_zero_sign_and_parity_and_clears_carry() {
+        let cpu = run(&[
+            movi(1, 0b1100),
+            movi(2, 0b1010),
+            movi(9, -1),
+            Add { rd: r(9), rs1: r(9), rs2: r(9) },
+            And { rd: r(3), rs1: r(1), rs2: r(2) },
+            Or { rd: r(4), rs1: r(1), rs2: r(2) },
+            Xor { rd: r(5), rs1: r(1), rs2: r(2) },
+            Not { rd: r(6), rs1: r(1) },
+            Halt {},
+        ]);
+        assert_eq!(cpu.register(r(3)), 0b1000);
+        assert_eq!(cpu.register(r(4)), 0b1110);
+        assert_eq!(cpu.register(r(5)), 0b0110);
+        assert_eq!(cpu.register(r(6)), !0b1100);
+        assert!(cpu.flags.sign() && cpu.flags.parity() && !cpu.flags.carry() && !cpu.flags.zero());
+
+        let cpu = run(&[movi(1, 0b1100), AndI { rd: r(2), rs1: r(1), imm: Imm(0b0011) }, Halt {}]);
+        assert!(cpu.flags.zero() && cpu.flags.parity() && !cpu.flags.sign());
+    }
+
+    #[test]
+    fn immediate_logic() {
+        let cpu = run(&[
+            movi(1, 0b1100),
+            OrI { rd: r(2), rs1: r(1), imm: Imm(0xFFFF) },
+            XorI { rd: r(3), rs1: r(1), imm: Imm(0b0110) },
+            AndI { rd: r(4), rs1: r(1), imm: Imm(0b0100) },
+            Halt {},
+        ]);
+        assert_eq!(cpu.register(r(2)), 0xFFFF);
+        assert_eq!(cpu.register(r(3)), 0b1010);
+        assert_eq!(cpu.register(r(4)), 0b0100);
+    }
+
+    #[test]
+    fn shifts() {
+        let cpu = run(&[
+            movi(1, -16),
+            movi(2, 2),
+            Shl { rd: r(3), rs1: r(1), rs2: r(2) },
+            Shr { rd: r(4), rs1: r(1), rs2: r(2) },
+            Sar { rd: r(5), rs1: r(1), rs2: r(2) },
+            ShlI { rd: r(6), rs1: r(1), imm: Imm(4) },
+            ShrI { rd: r(7), rs1: r(1), imm: Imm(60) },
+            SarI { rd: r(8), rs1: r(1), imm: Imm(63) },
+            Halt {},
+        ]);
+        assert_eq!(cpu.register(r(3)) as i64, -64);
+        assert_eq!(cpu.register(r(4)), (-16i64 as u64) >> 2);
+        assert_eq!(cpu.register(r(5)) as i64, -4);
+        assert_eq!(cpu.register(r(6)) as i64, -256);
+        assert_eq!(cpu.register(r(7)), 0xF);
+        assert_eq!(cpu.register(r(8)), u64::MAX);
+    }
+
+    #[test]
+    fn moves_and_wide_constants() {
+        let cpu = run(&[
+            movi(1, -2),
+            AddI { rd: r(2), rs1: r(1), imm: SImm(-3) },
+            MovI { rd: r(3), imm: SImm(0x5678) },
+            MovK { rd: r(3), shift: Imm(1), imm: Imm(0x1234) },
+            MovK { rd: r(3), shift: Imm(3), imm: Imm(0xDEAD) },
+            Mov { rd: r(4), rs1: r(3) },
+            Halt {},
+        ]);
+        assert_eq!(cpu.register(r(1)), -2i64 as u64);
+        assert_eq!(cpu.register(r(2)) as i64, -5);
+        assert!(cpu.flags.carry());
+        assert_eq!(cpu.register(r(3)), 0xDEAD_0000_1234_5678);
+        assert_eq!(cpu.register(r(4)), 0xDEAD_0000_1234_5678);
+    }
+
+    #[test]
+    fn loads_and_stores() {
+        let cpu = run(&[
+            movi(1, DATA),
+            movi(2, 0x1234),
+            Store16 { rd: r(2), rs1: r(1), imm: SImm(0) },
+            Load8U { rd: r(3), rs1: r(1), imm: SImm(0) },
+            Load8U { rd: r(4), rs1: r(1), imm: SImm(1) },
+            movi(2, -128),
+            Store8 { rd: r(2), rs1: r(1), imm: SImm(8) },
+            Load8S { rd: r(5), rs1: r(1), imm: SImm(8) },
+            Store32 { rd: r(2), rs1: r(1), imm: SImm(12) },
+            Load32S { rd: r(6), rs1: r(1), imm: SImm(12) },
+            Load32U { rd: r(7), rs1: r(1), imm: SImm(12) },
+            Store16 { rd: r(2), rs1: r(1), imm: SImm(16) },
+            Load16S { rd: r(8), rs1: r(1), imm: SImm(16) },
+            Load16U { rd: r(9), rs1: r(1), imm: SImm(16) },
+            MovK { rd: r(2), shift: Imm(2), imm: Imm(0x1234) },
+            Store64 { rd: r(2), rs1: r(1), imm: SImm(24) },
+            AddI { rd: r(10), rs1: r(1), imm: SImm(32) },
+            Load64 { rd: r(11), rs1: r(10), imm: SImm(-8) },
+            Halt {},
+        ]);
+        assert_eq!((cpu.register(r(3)), cpu.register(r(4))), (0x12, 0x34));
+        assert_eq!(cpu.register(r(5)) as i64, -128);
+        assert_eq!(cpu.register(r(6)) as i64, -128);
+        assert_eq!(cpu.register(r(7)), 0xFFFF_FF80);
+        assert_eq!(cpu.register(r(8)) as i64, -128);
+        assert_eq!(cpu.register(r(9)), 0xFF80);
+        assert_eq!(cpu.register(r(11)), 0xFFFF_1234_FFFF_FF80);
+        assert_eq!(access_memory!(cpu.memory).read_u64(DATA as usize + 24), 0xFFFF_1234_FFFF_FF80);
+    }
+
+    #[test]
+    fn memory_faults_leave_registers_and_pc() {
+        let mut cpu = load(&[movi(1, -1), movi(2, 7), Load64 { rd: r(2), rs1: r(1), imm: SImm(0) }, Halt {}]);
+        assert_eq!(cpu.run_for(100), StepResult::Fault(CPUFault::MemoryFault { address: u64::MAX }));
+        assert_eq!((cpu.pc, cpu.register(r(2))), (8, 7));
+
+        let address = MEMORY_SIZE as i64 - 4;
+        let mut cpu = load(&[movi(1, address), Store64 { rd: r(2), rs1: r(1), imm: SImm(0) }, Halt {}]);
+        assert_eq!(cpu.run_for(100), StepResult::Fault(CPUFault::MemoryFault { address: address as u64 }));
+        assert_eq!(cpu.pc, 4);
+
+        let mut cpu = load(&[movi(1, 0), FLoad { fd: f(0), rs1: r(1), imm: SImm(-8) }, Halt {}]);
+        assert_eq!(cpu.run_for(100), StepResult::Fault(CPUFault::MemoryFault { address: -8i64 as u64 }));
+    }
+
+    #[test]
+    fn float_arithmetic_and_conversions() {
+        let cpu = run(&[
+            movi(1, 9),
+            movi(2, 2),
+            FFromInt { fd: f(0), rs1: r(1) },
+            FFromInt { fd: f(1), rs1: r(2) },
+            FSqrt { fd: f(2), fs1: f(0) },
+            FAdd { fd: f(3), fs1: f(2), fs2: f(1) },
+            FSub { fd: f(4), fs1: f(2), fs2: f(1) },
+            FMul { fd: f(5), fs1: f(2), fs2: f(1) },
+            FDiv { fd: f(6), fs1: f(0), fs2: f(1) },
+            FMov { fd: f(7), fs1: f(6) },
+            FToInt { rd: r(3), fs1: f(7) },
+            FSub { fd: f(8), fs1: f(4), fs2: f(0) },
+            FToInt { rd: r(4), fs1: f(8) },
+            movi(5, DATA),
+            FStore { fd: f(6), rs1: r(5), imm: SImm(0) },
+            FLoad { fd: f(9), rs1: r(5), imm: SImm(0) },
+            Halt {},
+        ]);
+        let float = |n| cpu.float_register(f(n)).0;
+        assert_eq!([float(2), float(3), float(4), float(5), float(6), float(7)], [3.0, 5.0, 1.0, 6.0, 4.5, 4.5]);
+        assert_eq!(cpu.register(r(3)), 4);
+        assert_eq!(cpu.register(r(4)) as i64, -8);
+        assert_eq!(float(9), 4.5);
+        assert_eq!(access_memory!(cpu.memory).read_f64(DATA as usize), 4.5);
+    }
+
+    #[test]
+    fn float_to_int_saturates_and_maps_nan_to_zero() {
+        let cpu = run(&[
+            movi(1, 0),
+            FFromInt { fd: f(0), rs1: r(1) },
+            FDiv { fd: f(1), fs1: f(0), fs2: f(0) },
+            FToInt { rd: r(2), fs1: f(1) },
+            movi(3, 1),
+            FFromInt { fd: f(2), rs1: r(3) },
+            FDiv { fd: f(3), fs1: f(2), fs2: f(0) },
+            FToInt { rd: r(4), fs1: f(3) },
+            Halt {},
+        ]);
+        assert!(cpu.float_register(f(1)).0.is_nan());
+        assert_eq!(cpu.register(r(2)), 0);
+        assert_eq!(cpu.register(r(4)), i64::MAX as u64);
+    }
+
+    #[test]
+    fn float_compare_flags() {
+        let compare = |a: i64, b: i64, nan: bool| {
+            let cpu = run(&[
+                movi(1, a),
+                movi(2, b),
+                FFromInt { fd: f(0), rs1: r(1) },
+                FFromInt { fd: f(1), rs1: r(2) },
+                FSub { fd: f(2), fs1: f(0), fs2: f(0) },
+                FDiv { fd: f(3), fs1: f(2), fs2: f(2) },
+                FCmp { fs1: f(0), fs2: if nan { f(3) } else { f(1) } },
+                Halt {},
+            ]);
+            (cpu.flags.zero(), cpu.flags.carry(), cpu.flags.parity())
+        };
+        assert_eq!(compare(1, 2, false), (false, true, false));
+        assert_eq!(compare(2, 1, false), (false, false, false));
+        assert_eq!(compare(2, 2, false), (true, false, false));
+        assert_eq!(compare(2, 2, true), (true, true, true));
+    }
+
+    #[test]
+    fn branches_follow_the_flags() {
+        type MakeBranch = fn(SImm) -> RegisterInstruction;
+        let taken = |branch: MakeBranch, a: i64, b: i64| {
+            let cpu = run(&[movi(1, a), movi(2, b), Cmp { rs1: r(1), rs2: r(2) }, branch(SImm(2)), movi(3, 1), Halt {}]);
+            cpu.register(r(3)) == 0
+        };
+        let cases: [(MakeBranch, i64, i64, bool); 28] = [
+            (|offset| Branch { offset }, 1, 1, true),
+            (|offset| BranchEq { offset }, 1, 1, true),
+            (|offset| BranchEq { offset }, 1, 2, false),
+            (|offset| BranchNe { offset }, 1, 2, true),
+            (|offset| BranchNe { offset }, 2, 2, false),
+            (|offset| BranchLt { offset }, -1, 1, true),
+            (|offset| BranchLt { offset }, 1, -1, false),
+            (|offset| BranchGe { offset }, 1, 1, true),
+            (|offset| BranchGe { offset }, -1, 1, false),
+            (|offset| BranchLe { offset }, 1, 1, true),
+            (|offset| BranchLe { offset }, 2, 1, false),
+            (|offset| BranchGt { offset }, 2, -1, true),
+            (|offset| BranchGt { offset }, 1, 1, false),
+            (|offset| BranchLtU { offset }, 1, -1, true),
+            (|offset| BranchLtU { offset }, -1, 1, false),
+            (|offset| BranchGeU { offset }, -1, 1, true),
+            (|offset| BranchGeU { offset }, 1, -1, false),
+            (|offset| BranchLeU { offset }, 1, 1, true),
+            (|offset| BranchLeU { offset }, -1, 1, false),
+            (|offset| BranchGtU { offset }, -1, 1, true),
+            (|offset| BranchGtU { offset }, 1, 1, false),
+            (|offset| BranchNeg { offset }, 1, 2, true),
+            (|offset| BranchNeg { offset }, 2, 1, false),
+            (|offset| BranchPos { offset }, 2, 1, true),
+            (|offset| BranchPos { offset }, 1, 2, false),
+            (|offset| BranchOverflow { offset }, i16::MIN as i64, 1, false),
+            (|offset| BranchNoOverflow { offset }, 1, 2, true),
+            (|offset| BranchNoOverflow { offset }, 2, 1, true),
+        ];
+        for (index, (branch, a, b, expected)) in cases.into_iter().enumerate() {
+            assert_eq!(taken(branch, a, b), expected, "case {index}");
+        }
+
+        // 0x8000_0000_0000_0000 - 1 overflows.
+        let cpu = run(&[movi(1, 1), ShlI { rd: r(1), rs1: r(1), imm: Imm(63) }, movi(2, 1), Cmp { rs1: r(1), rs2: r(2) }, BranchOverflow { offset: SImm(2) }, movi(3, 1), Halt {}]);
+        assert_eq!(cpu.register(r(3)), 0);
+    }
+
+    #[test]
+    fn backward_branch_loops() {
+        let cpu = run(&[movi(1, 5), movi(2, 0), AddI { rd: r(2), rs1: r(2), imm: SImm(3) }, AddI { rd: r(1), rs1: r(1), imm: SImm(-1) }, BranchNe { offset: SImm(-2) }, Halt {}]);
+        assert_eq!(cpu.register(r(2)), 15);
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        let cpu = run(&[
+            movi(1, 5),
+            Call { offset: SImm(3) },
+            AddI { rd: r(1), rs1: r(1), imm: SImm(1) },
+            Halt {},
+            AddI { rd: r(1), rs1: r(1), imm: SImm(10) },
+            Ret {},
+        ]);
+        assert_eq!(cpu.register(r(1)), 16);
+        assert_eq!(cpu.register(LINK_REGISTER), 8);
+        assert_eq!(cpu.return_addresses(), Vec::<u64>::new());
+
+        let cpu = run(&[
+            movi(2, 20),
+            CallRegister { rs1: r(2) },
+            movi(3, 28),
+            JumpRegister { rs1: r(3) },
+            Halt {},
+            movi(1, 7),
+            Ret {},
+            Halt {},
+        ]);
+        assert_eq!(cpu.register(r(1)), 7);
+        assert_eq!(cpu.register(LINK_REGISTER), 8);
+        assert_eq!(cpu.pc, 32);
+    }
+
+    #[test]
+    fn return_addresses_follow_the_link_register() {
+        let mut cpu = load(&[Call { offset: SImm(2) }, Halt {}, Ret {}]);
+        assert!(cpu.return_addresses().is_empty());
+        cpu.step();
+        assert_eq!(cpu.return_addresses(), vec![4]);
+        cpu.step();
+        assert!(cpu.return_addresses().is_empty());
+    }
+
+    #[test]
+    fn halt_break_and_illegal_instructions() {
+        let mut cpu = load(&[Nop {}, Break {}, Halt {}]);
+        assert_eq!(cpu.step(), StepResult::Continue);
+        assert_eq!(cpu.step(), StepResult::Breakpoint);
+        assert_eq!(cpu.pc, 8);
+        assert_eq!(cpu.step(), StepResult::Halted);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.step(), StepResult::Halted);
+
+        let mut cpu = load(&[Nop {}]);
+        access_memory!(cpu.memory).write_u32(4, 0xFFFF_FFFF);
+        assert_eq!(cpu.run_for(10), StepResult::Fault(CPUFault::IllegalInstruction { address: 4, opcode: 0xFFFF_FFFF }));
+        assert_eq!(cpu.pc, 4);
+
+        let mut cpu = load(&[movi(1, MEMORY_SIZE as i64 - 8), JumpRegister { rs1: r(1) }]);
+        access_memory!(cpu.memory).write_u32(MEMORY_SIZE - 8, Nop {}.encode());
+        assert_eq!(cpu.run_for(10), StepResult::Fault(CPUFault::MemoryFault { address: MEMORY_SIZE as u64 }));
+    }
+
+    #[test]
+    fn interrupt_entry_pushes_and_iret_pops_the_return_state() {
+        let mut cpu = load(&[EnableInterrupts {}, movi(1, 1), movi(1, 2), Halt {}]);
+        write(&cpu, HANDLER, &[movi(5, 7), InterruptReturn {}]);
+        let system = interrupt_system(&cpu, 4);
+        system.table.set_handler(IRQ_BASE, HANDLER);
+        system.controller.lock().unwrap().raise(0);
+
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert!(cpu.interrupts_enabled());
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert_eq!(cpu.register(r(5)), 7);
+        assert_eq!(cpu.register(STACK_POINTER), STACK - 16);
+        assert!(!cpu.interrupts_enabled());
+        {
+            let memory = access_memory!(cpu.memory);
+            assert_eq!(memory.read_u64(STACK as usize - 8), 4);
+            assert_eq!(memory.read_u64(STACK as usize - 16), FlagsRegister::INTERRUPT_ENABLE as u64);
+        }
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert_eq!((cpu.pc, cpu.register(STACK_POINTER)), (4, STACK));
+        assert!(cpu.interrupts_enabled());
+        assert_eq!(system.run_for(&mut cpu, 10), StepResult::Halted);
+        assert_eq!(cpu.register(r(1)), 2);
+    }
+
+    #[test]
+    fn di_blocks_interrupts() {
+        let mut cpu = load(&[EnableInterrupts {}, DisableInterrupts {}, movi(1, 1), Halt {}]);
+        write(&cpu, HANDLER, &[movi(5, 7), InterruptReturn {}]);
+        let system = interrupt_system(&cpu, 4);
+        system.table.set_handler(IRQ_BASE, HANDLER);
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert!(!cpu.interrupts_enabled());
+        system.controller.lock().unwrap().raise(0);
+        assert_eq!(system.run_for(&mut cpu, 10), StepResult::Halted);
+        assert_eq!(cpu.register(r(5)), 0);
+        assert_eq!(system.controller.lock().unwrap().pending(), Some(0));
+    }
+
+    #[test]
+    fn interrupts_nest() {
+        let mut cpu = load(&[EnableInterrupts {}, movi(1, 1), movi(1, 2), Halt {}]);
+        let outer = HANDLER;
+        let inner = HANDLER + 0x80;
+        write(&cpu, outer, &[EnableInterrupts {}, movi(5, 1), movi(5, 2), InterruptReturn {}]);
+        write(&cpu, inner, &[movi(6, 1), InterruptReturn {}]);
+        let system = interrupt_system(&cpu, 4);
+        system.table.set_handler(IRQ_BASE, outer);
+        system.table.set_handler(IRQ_BASE + 1, inner);
+        system.controller.lock().unwrap().raise(0);
+        system.controller.lock().unwrap().raise(1);
+
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert_eq!(cpu.pc, outer + 4);
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert_eq!(cpu.register(r(6)), 1);
+        assert_eq!(cpu.register(STACK_POINTER), STACK - 32);
+        assert_eq!(system.run_for(&mut cpu, 20), StepResult::Halted);
+        assert_eq!((cpu.register(r(1)), cpu.register(r(5)), cpu.register(STACK_POINTER)), (2, 2, STACK));
+    }
+
+    #[test]
+    fn fault_handler_can_skip_the_faulting_instruction() {
+        let mut cpu = load(&[movi(1, 1), movi(2, 0), DivU { rd: r(3), rs1: r(1), rs2: r(2) }, movi(4, 9), Halt {}]);
+        write(&cpu, HANDLER, &[
+            Load64 { rd: r(10), rs1: STACK_POINTER, imm: SImm(8) },
+            AddI { rd: r(10), rs1: r(10), imm: SImm(4) },
+            Store64 { rd: r(10), rs1: STACK_POINTER, imm: SImm(8) },
+            movi(11, 1),
+            InterruptReturn {},
+        ]);
+        let system = interrupt_system(&cpu, 1);
+        system.table.set_handler(DIVIDE_BY_ZERO_VECTOR, HANDLER);
+        assert_eq!(system.run_for(&mut cpu, 20), StepResult::Halted);
+        assert_eq!((cpu.register(r(4)), cpu.register(r(11)), cpu.register(STACK_POINTER)), (9, 1, STACK));
+    }
+
+    #[test]
+    fn faulting_interrupt_entry_changes_nothing() {
+        let mut cpu = load(&[EnableInterrupts {}, Halt {}]);
+        assert_eq!(cpu.step(), StepResult::Continue);
+        cpu.set_register(STACK_POINTER, 8);
+        assert_eq!(cpu.enter_interrupt(IRQ_BASE, HANDLER), Err(CPUFault::MemoryFault { address: 8u64.wrapping_sub(16) }));
+        assert_eq!((cpu.pc, cpu.register(STACK_POINTER)), (4, 8));
+        assert!(cpu.interrupts_enabled());
+    }
+
+    #[test]
+    #[should_panic(expected = "Interrupt vector table has 17 vectors, but 2 lines need 18")]
+    fn vector_tables_cover_every_line() {
+        let cpu = load(&[]);
+        let table = InterruptVectorTable::new(cpu.memory.clone(), VECTOR_TABLE, IRQ_BASE + 1);
+        InterruptSystem::new(create_interrupt_controller(2), table);
+    }
+
+    #[test]
+    fn iret_with_a_bad_stack_faults() {
+        let mut cpu = load(&[movi(14, -8), InterruptReturn {}]);
+        assert_eq!(cpu.run_for(10), StepResult::Fault(CPUFault::MemoryFault { address: -8i64 as u64 }));
+        assert_eq!(cpu.pc, 4);
+        assert_eq!(cpu.register(STACK_POINTER), -8i64 as u64);
+    }
+
+    #[test]
+    fn wait_resumes_after_an_interrupt() {
+        let mut cpu = load(&[EnableInterrupts {}, Wait {}, movi(1, 1), Halt {}]);
+        write(&cpu, HANDLER, &[InterruptReturn {}]);
+        let system = interrupt_system(&cpu, 1);
+        system.table.set_handler(IRQ_BASE, HANDLER);
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert_eq!(system.step(&mut cpu), StepResult::WaitingForInterrupt);
+        assert_eq!(system.step(&mut cpu), StepResult::WaitingForInterrupt);
+        system.controller.lock().unwrap().raise(0);
+        assert_eq!(system.run_for(&mut cpu, 10), StepResult::Halted);
+        assert_eq!(cpu.register(r(1)), 1);
+    }
+
+    #[test]
+    fn reset_clears_registers_and_restarts() {
+        let mut cpu = run(&[movi(1, 3), FFromInt { fd: f(1), rs1: r(1) }, Halt {}]);
+        cpu.reset();
+        assert_eq!((cpu.pc, cpu.register(r(1)), cpu.float_register(f(1)).0), (0, 0, 0.0));
+        assert_eq!(cpu.run_for(10), StepResult::Halted);
+        assert_eq!(cpu.register(r(1)), 3);
+    }
+
+    #[test]
+    fn debug_registers() {
+        let mut cpu = load(&[Halt {}]);
+        let index = cpu.register_index("f2").unwrap();
+        DebugTarget::set_register(&mut cpu, index, RegisterValue::F64(1.5)).unwrap();
+        assert_eq!(cpu.float_register(f(2)).0, 1.5);
+        assert_eq!(DebugTarget::set_register(&mut cpu, 3, RegisterValue::U32(1)), Err(RegisterError::WidthMismatch { expected: 64, found: 32 }));
+        assert_eq!(DebugTarget::set_register(&mut cpu, 3, RegisterValue::F64(1.0)), Err(RegisterError::KindMismatch { float: false }));
+        assert_eq!(DebugTarget::set_register(&mut cpu, index, RegisterValue::U64(1)), Err(RegisterError::KindMismatch { float: true }));
+        assert_eq!(DebugTarget::set_register(&mut cpu, 33, RegisterValue::F64(1.0)), Err(RegisterError::KindMismatch { float: false }));
+        DebugTarget::set_register(&mut cpu, 33, RegisterValue::U64(0x40)).unwrap();
+        assert_eq!(cpu.pc, 0x40);
+        let registers = cpu.registers();
+        assert_eq!(registers[2].0, "r2");
+        assert_eq!(registers[18], ("f2", RegisterValue::F64(1.5)));
+        assert_eq!(registers.len(), 34);
+    }
+}
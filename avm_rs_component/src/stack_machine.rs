@@ -0,0 +1,811 @@
+use std::fmt::Display;
+
+use avm_rs_memory::{access_memory, mem::{Memory, MemorySliceTrait}, wrappers::stack::Stack};
+
+use crate::{cpu::{CPUFault, CPUTrait, StepResult}, debug::{DebugTarget, InstructionKind}, decode::{encode_sleb128, encode_uleb128, DecodeError, InstructionDecoder, InstructionReader}, interrupt::InterruptTarget, register::{RegisterError, RegisterValue}};
+
+// Operands of the stack machine are LEB128 encoded, signed for immediates and
+// unsigned for addresses, counts and indices.
+trait StackOperand: Copy + Display {
+    fn read<M: MemorySliceTrait + ?Sized>(reader: &mut InstructionReader<M>) -> Result<Self, DecodeError>;
+    fn write(self, output: &mut Vec<u8>);
+}
+
+impl StackOperand for i64 {
+    fn read<M: MemorySliceTrait + ?Sized>(reader: &mut InstructionReader<M>) -> Result<Self, DecodeError> {
+        reader.sleb128()
+    }
+    fn write(self, output: &mut Vec<u8>) {
+        encode_sleb128(self, output);
+    }
+}
+
+impl StackOperand for u64 {
+    fn read<M: MemorySliceTrait + ?Sized>(reader: &mut InstructionReader<M>) -> Result<Self, DecodeError> {
+        reader.uleb128()
+    }
+    fn write(self, output: &mut Vec<u8>) {
+        encode_uleb128(self, output);
+    }
+}
+
+macro_rules! stack_instructions {
+    ( $( $variant:ident = $opcode:literal, $mnemonic:literal $( ( $operand:ident ) )? ; )* ) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum StackInstruction {
+            $( $variant $( ( $operand ) )? ),*
+        }
+
+        impl StackInstruction {
+            pub const MNEMONICS: &'static [(&'static str, u8, bool)] = &[
+                $( ($mnemonic, $opcode, stack_instructions!(@has_operand $( $operand )?)) ),*
+            ];
+
+            pub fn opcode(&self) -> u8 {
+                match self { $( Self::$variant { .. } => $opcode ),* }
+            }
+
+            pub fn mnemonic(&self) -> &'static str {
+                match self { $( Self::$variant { .. } => $mnemonic ),* }
+            }
+
+            pub fn encode(&self, output: &mut Vec<u8>) {
+                output.push(self.opcode());
+                match *self {
+                    $( stack_instructions!(@pattern $variant value $( $operand )?) => {
+                        stack_instructions!(@write output value $( $operand )?);
+                    } )*
+                }
+            }
+
+            // The instruction for a mnemonic and its operand, as written in
+            // assembly. None when the mnemonic is unknown or the operand count
+            // doesn't match.
+            pub fn from_mnemonic(mnemonic: &str, operand: Option<i64>) -> Option<Self> {
+                $(
+                    if mnemonic.eq_ignore_ascii_case($mnemonic) {
+                        return stack_instructions!(@construct operand $variant $( $operand )?);
+                    }
+                )*
+                None
+            }
+
+            pub fn decode<M: MemorySliceTrait + ?Sized>(reader: &mut InstructionReader<M>) -> Result<Self, DecodeError> {
+                match reader.u8()? {
+                    $( $opcode => Ok(stack_instructions!(@read reader $variant $( $operand )?)), )*
+                    opcode => Err(reader.invalid(opcode as u32)),
+                }
+            }
+        }
+
+        impl Display for StackInstruction {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match *self {
+                    $( stack_instructions!(@pattern $variant value $( $operand )?) => {
+                        write!(f, "{}", $mnemonic)?;
+                        stack_instructions!(@display f value $( $operand )?);
+                        Ok(())
+                    } )*
+                }
+            }
+        }
+    };
+    ( @has_operand ) => { false };
+    ( @has_operand $operand:ident ) => { true };
+    ( @pattern $variant:ident $value:ident ) => { Self::$variant };
+    ( @pattern $variant:ident $value:ident $operand:ident ) => { Self::$variant($value) };
+    ( @write $output:ident $value:ident ) => {};
+    ( @write $output:ident $value:ident $operand:ident ) => { StackOperand::write($value, $output) };
+    ( @read $reader:ident $variant:ident ) => { Self::$variant };
+    ( @read $reader:ident $variant:ident $operand:ident ) => { Self::$variant(<$operand as StackOperand>::read($reader)?) };
+    ( @construct $value:ident $variant:ident ) => { $value.is_none().then_some(Self::$variant) };
+    ( @construct $value:ident $variant:ident $operand:ident ) => { $value.map(|value| Self::$variant(value as $operand)) };
+    ( @display $f:ident $value:ident ) => {};
+    ( @display $f:ident $value:ident $operand:ident ) => { write!($f, " {}", $value)? };
+}
+
+// Every instruction is one opcode byte, followed by a LEB128 operand for the
+// ones that take one. Values on the stack are 64 bits; comparisons push 1 or
+// 0, loads zero- or sign-extend to 64 bits and stores truncate.
+//
+// Binary operations pop `b` then `a` and push `a op b`. Stores pop the value,
+// then the address. Branch and call targets are absolute addresses.
+//
+// Calls push the return address and the caller's frame pointer, and the
+// frame pointer is set to the new top of stack. `enter n` reserves `n` zeroed
+// locals above it, `local n` and `setlocal n` address them, and `arg n` reads
+// the `n`-th argument counting back from the last one pushed by the caller.
+// `ret n` pops the return value, unwinds the frame, drops `n` arguments and
+// pushes the return value back.
+stack_instructions! {
+    Nop = 0x00, "nop";
+    Halt = 0x01, "halt";
+    Push = 0x02, "push"(i64);
+    Pop = 0x03, "pop";
+    Dup = 0x04, "dup";
+    Swap = 0x05, "swap";
+    Over = 0x06, "over";
+
+    Add = 0x10, "add";
+    Sub = 0x11, "sub";
+    Mul = 0x12, "mul";
+    DivS = 0x13, "divs";
+    DivU = 0x14, "divu";
+    RemS = 0x15, "rems";
+    RemU = 0x16, "remu";
+    Neg = 0x17, "neg";
+
+    And = 0x20, "and";
+    Or = 0x21, "or";
+    Xor = 0x22, "xor";
+    Not = 0x23, "not";
+    Shl = 0x24, "shl";
+    Shr = 0x25, "shr";
+    Sar = 0x26, "sar";
+
+    Eq = 0x30, "eq";
+    Ne = 0x31, "ne";
+    LtS = 0x32, "lts";
+    LtU = 0x33, "ltu";
+    LeS = 0x34, "les";
+    LeU = 0x35, "leu";
+    Eqz = 0x36, "eqz";
+
+    Jump = 0x40, "jmp"(u64);
+    JumpIfZero = 0x41, "jz"(u64);
+    JumpIfNotZero = 0x42, "jnz"(u64);
+    Call = 0x43, "call"(u64);
+    Ret = 0x44, "ret"(u64);
+
+    Enter = 0x48, "enter"(u64);
+    LoadLocal = 0x49, "local"(u64);
+    StoreLocal = 0x4A, "setlocal"(u64);
+    LoadArg = 0x4B, "arg"(u64);
+
+    Load8U = 0x60, "load8u";
+    Load16U = 0x61, "load16u";
+    Load32U = 0x62, "load32u";
+    Load64 = 0x63, "load64";
+    Load8S = 0x64, "load8s";
+    Load16S = 0x65, "load16s";
+    Load32S = 0x66, "load32s";
+    Store8 = 0x68, "store8";
+    Store16 = 0x69, "store16";
+    Store32 = 0x6A, "store32";
+    Store64 = 0x6B, "store64";
+
+    Wait = 0x70, "wait";
+    Break = 0x71, "brk";
+    EnableInterrupts = 0x72, "ei";
+    DisableInterrupts = 0x73, "di";
+    InterruptReturn = 0x74, "iret";
+}
+
+// Longest encoding: an opcode and a ten byte LEB128 operand.
+pub struct StackDecoder;
+
+impl InstructionDecoder for StackDecoder {
+    type Instruction = StackInstruction;
+    const MAX_LENGTH: usize = 11;
+
+    fn decode<M: MemorySliceTrait + ?Sized>(&self, reader: &mut InstructionReader<M>) -> Result<StackInstruction, DecodeError> {
+        StackInstruction::decode(reader)
+    }
+}
+
+pub fn assemble_stack_program(instructions: &[StackInstruction]) -> Vec<u8> {
+    let mut output = Vec::new();
+    for instruction in instructions {
+        instruction.encode(&mut output);
+    }
+    output
+}
+
+// Code and data share `memory`, the operand and call stack lives in `stack`.
+#[derive(Debug)]
+pub struct StackCPU {
+    memory: Memory,
+    pub stack: Stack,
+    pub entry: u64,
+    pub pc: u64,
+    pub fp: usize,
+    halted: bool,
+    waiting: bool,
+    interrupts_enabled: bool,
+}
+
+impl StackCPU {
+    pub fn new(memory: Memory, stack: Stack, entry: u64) -> Self {
+        Self {
+            memory,
+            stack,
+            entry,
+            pc: entry,
+            fp: 0,
+            halted: false,
+            waiting: false,
+            interrupts_enabled: false,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Values on the stack, bottom first.
+    pub fn values(&self) -> Vec<u64> {
+        (0..self.stack.top() / 8).map(|index| self.stack.pointer.read_u64(index * 8)).collect()
+    }
+
+    pub fn push(&mut self, value: u64) -> Result<(), CPUFault> {
+        if self.stack.top() + 8 > self.stack.pointer.size {
+            return Err(self.stack_fault());
+        }
+        self.stack.push_u64(value);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<u64, CPUFault> {
+        if self.stack.top() < 8 {
+            return Err(self.stack_fault());
+        }
+        Ok(self.stack.pop_u64())
+    }
+
+    fn stack_fault(&self) -> CPUFault {
+        CPUFault::StackFault { address: self.pc }
+    }
+
+    // `offset` counts slots from fp, locals above it and arguments below.
+    fn frame_slot(&self, offset: isize) -> Result<usize, CPUFault> {
+        let slot = offset.checked_mul(8).and_then(|x| x.checked_add(self.fp as isize)).ok_or(self.stack_fault())?;
+        if slot < 0 || slot as usize + 8 > self.stack.top() {
+            return Err(self.stack_fault());
+        }
+        Ok(slot as usize)
+    }
+
+    fn binary(&mut self, f: impl FnOnce(u64, u64) -> u64) -> Result<(), CPUFault> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        self.push(f(a, b))
+    }
+
+    fn divide(&mut self, f: impl FnOnce(u64, u64) -> u64) -> Result<(), CPUFault> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        if b == 0 {
+            return Err(CPUFault::DivideByZero { address: self.pc });
+        }
+        self.push(f(a, b))
+    }
+
+    fn load(&mut self, width: usize, read: impl FnOnce(&dyn MemorySliceTrait, usize) -> u64) -> Result<(), CPUFault> {
+        let address = self.pop()?;
+        let value = {
+            let memory = access_memory!(self.memory);
+            if address.checked_add(width as u64).is_none_or(|end| end > memory.len() as u64) {
+                return Err(CPUFault::MemoryFault { address });
+            }
+            read(&*memory, address as usize)
+        };
+        self.push(value)
+    }
+
+    fn store(&mut self, width: usize, write: impl FnOnce(&mut dyn MemorySliceTrait, usize, u64)) -> Result<(), CPUFault> {
+        let value = self.pop()?;
+        let address = self.pop()?;
+        let mut memory = access_memory!(self.memory);
+        if address.checked_add(width as u64).is_none_or(|end| end > memory.len() as u64) {
+            return Err(CPUFault::MemoryFault { address });
+        }
+        write(&mut *memory, address as usize, value);
+        Ok(())
+    }
+
+    // Runs one decoded instruction, `next` is the address following it.
+    // Returns the new program counter.
+    fn execute(&mut self, instruction: StackInstruction, next: u64) -> Result<(u64, StepResult), CPUFault> {
+        use StackInstruction::*;
+        let continue_at = |pc: u64| Ok((pc, StepResult::Continue));
+        match instruction {
+            Nop => {}
+            Halt => {
+                self.halted = true;
+                return Ok((next, StepResult::Halted));
+            }
+            Push(value) => self.push(value as u64)?,
+            Pop => {
+                self.pop()?;
+            }
+            Dup => {
+                let value = self.pop()?;
+                self.push(value)?;
+                self.push(value)?;
+            }
+            Swap => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(b)?;
+                self.push(a)?;
+            }
+            Over => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(a)?;
+                self.push(b)?;
+                self.push(a)?;
+            }
+
+            Add => self.binary(u64::wrapping_add)?,
+            Sub => self.binary(u64::wrapping_sub)?,
+            Mul => self.binary(u64::wrapping_mul)?,
+            DivS => self.divide(|a, b| (a as i64).wrapping_div(b as i64) as u64)?,
+            DivU => self.divide(|a, b| a / b)?,
+            RemS => self.divide(|a, b| (a as i64).wrapping_rem(b as i64) as u64)?,
+            RemU => self.divide(|a, b| a % b)?,
+            Neg => {
+                let value = self.pop()?;
+                self.push(value.wrapping_neg())?;
+            }
+
+            And => self.binary(|a, b| a & b)?,
+            Or => self.binary(|a, b| a | b)?,
+            Xor => self.binary(|a, b| a ^ b)?,
+            Not => {
+                let value = self.pop()?;
+                self.push(!value)?;
+            }
+            Shl => self.binary(|a, b| a.wrapping_shl(b as u32))?,
+            Shr => self.binary(|a, b| a.wrapping_shr(b as u32))?,
+            Sar => self.binary(|a, b| (a as i64).wrapping_shr(b as u32) as u64)?,
+
+            Eq => self.binary(|a, b| (a == b) as u64)?,
+            Ne => self.binary(|a, b| (a != b) as u64)?,
+            LtS => self.binary(|a, b| ((a as i64) < (b as i64)) as u64)?,
+            LtU => self.binary(|a, b| (a < b) as u64)?,
+            LeS => self.binary(|a, b| ((a as i64) <= (b as i64)) as u64)?,
+            LeU => self.binary(|a, b| (a <= b) as u64)?,
+            Eqz => {
+                let value = self.pop()?;
+                self.push((value == 0) as u64)?;
+            }
+
+            Jump(target) => return continue_at(target),
+            JumpIfZero(target) => {
+                if self.pop()? == 0 {
+                    return continue_at(target);
+                }
+            }
+            JumpIfNotZero(target) => {
+                if self.pop()? != 0 {
+                    return continue_at(target);
+                }
+            }
+            Call(target) => {
+                self.push(next)?;
+                self.push(self.fp as u64)?;
+                self.fp = self.stack.top();
+                return continue_at(target);
+            }
+            Ret(arguments) => {
+                let value = self.pop()?;
+                if self.fp < 16 || self.fp > self.stack.top() {
+                    return Err(self.stack_fault());
+                }
+                self.stack.set_top(self.fp);
+                self.fp = self.pop()? as usize;
+                let target = self.pop()?;
+                let arguments = arguments.checked_mul(8).filter(|&x| x <= self.stack.top() as u64).ok_or(self.stack_fault())?;
+                self.stack.set_top(self.stack.top() - arguments as usize);
+                self.push(value)?;
+                return continue_at(target);
+            }
+
+            Enter(locals) => {
+                for _ in 0..locals {
+                    self.push(0)?;
+                }
+            }
+            LoadLocal(index) => {
+                let slot = self.frame_slot(isize::try_from(index).map_err(|_| self.stack_fault())?)?;
+                self.push(self.stack.pointer.read_u64(slot))?;
+            }
+            StoreLocal(index) => {
+                let value = self.pop()?;
+                let slot = self.frame_slot(isize::try_from(index).map_err(|_| self.stack_fault())?)?;
+                self.stack.pointer.write_u64(slot, value);
+            }
+            LoadArg(index) => {
+                let offset = isize::try_from(index).ok().and_then(|x| (-3isize).checked_sub(x)).ok_or(self.stack_fault())?;
+                let slot = self.frame_slot(offset)?;
+                self.push(self.stack.pointer.read_u64(slot))?;
+            }
+
+            Load8U => self.load(1, |memory, at| memory.read_u8(at) as u64)?,
+            Load16U => self.load(2, |memory, at| memory.read_u16(at) as u64)?,
+            Load32U => self.load(4, |memory, at| memory.read_u32(at) as u64)?,
+            Load64 => self.load(8, |memory, at| memory.read_u64(at))?,
+            Load8S => self.load(1, |memory, at| memory.read_i8(at) as u64)?,
+            Load16S => self.load(2, |memory, at| memory.read_i16(at) as u64)?,
+            Load32S => self.load(4, |memory, at| memory.read_i32(at) as u64)?,
+            Store8 => self.store(1, |memory, at, value| memory.write_u8(at, value as u8))?,
+            Store16 => self.store(2, |memory, at, value| memory.write_u16(at, value as u16))?,
+            Store32 => self.store(4, |memory, at, value| memory.write_u32(at, value as u32))?,
+            Store64 => self.store(8, |memory, at, value| memory.write_u64(at, value))?,
+
+            Wait => {
+                self.waiting = true;
+                return Ok((next, StepResult::WaitingForInterrupt));
+            }
+            Break => return Ok((next, StepResult::Breakpoint)),
+            EnableInterrupts => self.interrupts_enabled = true,
+            DisableInterrupts => self.interrupts_enabled = false,
+            InterruptReturn => {
+                let enabled = self.pop()?;
+                let target = self.pop()?;
+                self.interrupts_enabled = enabled != 0;
+                return continue_at(target);
+            }
+        }
+        continue_at(next)
+    }
+}
+
+impl CPUTrait for StackCPU {
+    fn reset(&mut self) {
+        self.stack.reset();
+        self.pc = self.entry;
+        self.fp = 0;
+        self.halted = false;
+        self.waiting = false;
+        self.interrupts_enabled = false;
+    }
+
+    fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    fn start_at(&mut self, entry: u64) {
+        self.entry = entry;
+        self.reset();
+    }
+
+    // A faulting instruction leaves the program counter and the stack as they
+    // were before it ran.
+    fn step(&mut self) -> StepResult {
+        if self.halted {
+            return StepResult::Halted;
+        }
+        if self.waiting {
+            return StepResult::WaitingForInterrupt;
+        }
+        let decoded = {
+            let memory = access_memory!(self.memory);
+            StackDecoder.decode_at(&*memory, self.pc as usize)
+        };
+        let decoded = match decoded {
+            Ok(decoded) => decoded,
+            Err(error) => return StepResult::Fault(error.into()),
+        };
+        let (top, fp) = (self.stack.top(), self.fp);
+        match self.execute(decoded.instruction, decoded.next_address() as u64) {
+            Ok((pc, result)) => {
+                self.pc = pc;
+                result
+            }
+            Err(fault) => {
+                self.stack.set_top(top);
+                self.fp = fp;
+                StepResult::Fault(fault)
+            }
+        }
+    }
+}
+
+// Interrupt entry pushes the return address and the interrupt enable state,
+// and disables interrupts until `iret`.
+impl InterruptTarget for StackCPU {
+    fn interrupts_enabled(&self) -> bool {
+        self.interrupts_enabled
+    }
+
+    fn enter_interrupt(&mut self, _vector: usize, handler: u64) -> Result<(), CPUFault> {
+        let top = self.stack.top();
+        if let Err(fault) = self.push(self.pc).and_then(|_| self.push(self.interrupts_enabled as u64)) {
+            self.stack.set_top(top);
+            return Err(fault);
+        }
+        self.interrupts_enabled = false;
+        self.waiting = false;
+        self.pc = handler;
+        Ok(())
+    }
+}
+
+// Registers are pc, fp and sp, the top of the stack, fp and sp being offsets
+// into the stack rather than addresses. Return addresses are found by
+// following the frame pointers saved by each call.
+impl DebugTarget for StackCPU {
+    fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    fn set_pc(&mut self, pc: u64) {
+        self.pc = pc;
+    }
+
+    fn registers(&self) -> Vec<(&'static str, RegisterValue)> {
+        vec![
+            ("pc", RegisterValue::U64(self.pc)),
+            ("fp", RegisterValue::U64(self.fp as u64)),
+            ("sp", RegisterValue::U64(self.stack.top() as u64)),
+        ]
+    }
+
+    fn set_register(&mut self, index: usize, value: RegisterValue) -> Result<(), RegisterError> {
+        let RegisterValue::U64(value) = value else {
+            return Err(RegisterError::WidthMismatch { expected: 64, found: value.width() });
+        };
+        match index {
+            0 => self.pc = value,
+            1 => self.fp = value as usize,
+            2 if value as usize <= self.stack.pointer.size => self.stack.set_top(value as usize),
+            2 => return Err(RegisterError::OutOfRange(value as u128)),
+            _ => return Err(RegisterError::InvalidIndex(index)),
+        }
+        Ok(())
+    }
+
+    fn instruction_kind(&self, address: u64) -> Option<InstructionKind> {
+        let memory = access_memory!(self.memory);
+        Some(match StackDecoder.decode_at(&*memory, address as usize).ok()?.instruction {
+            StackInstruction::Call(_) => InstructionKind::Call,
+            StackInstruction::Ret(_) => InstructionKind::Return,
+            _ => InstructionKind::Other,
+        })
+    }
+
+    fn return_addresses(&self) -> Vec<u64> {
+        let mut addresses = Vec::new();
+        let mut fp = self.fp;
+        while fp >= 16 && fp <= self.stack.top() {
+            addresses.push(self.stack.pointer.read_u64(fp - 16));
+            let caller = self.stack.pointer.read_u64(fp - 8) as usize;
+            // Frames only ever get deeper, anything else isn't a frame.
+            if caller >= fp {
+                break;
+            }
+            fp = caller;
+        }
+        addresses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use avm_rs_memory::{mem::create_memory, pointer::Pointer};
+
+    use crate::{decode::Decoded, interrupt::{create_interrupt_controller, InterruptSystem, InterruptVectorTable, IRQ_BASE}};
+
+    use super::{StackInstruction::*, *};
+
+    const MEMORY_SIZE: usize = 0x1000;
+    const STACK_SIZE: usize = 0x100;
+    const FUNCTION: u64 = 0x100;
+    const HANDLER: u64 = 0x400;
+    const VECTOR_TABLE: usize = 0x800;
+
+    fn write(cpu: &StackCPU, at: u64, program: &[StackInstruction]) {
+        access_memory!(cpu.memory).write_bytes(at as usize, &assemble_stack_program(program));
+    }
+
+    // Address of the instruction at `index` in a program written at 0.
+    fn address(program: &[StackInstruction], index: usize) -> u64 {
+        assemble_stack_program(&program[..index]).len() as u64
+    }
+
+    fn load(program: &[StackInstruction]) -> StackCPU {
+        let stack = Stack::new(Pointer::new(create_memory(STACK_SIZE), 0, STACK_SIZE));
+        let cpu = StackCPU::new(create_memory(MEMORY_SIZE), stack, 0);
+        write(&cpu, 0, program);
+        cpu
+    }
+
+    fn run(program: &[StackInstruction]) -> StackCPU {
+        let mut cpu = load(program);
+        assert_eq!(cpu.run_for(100), StepResult::Halted);
+        cpu
+    }
+
+    // Steps until the program counter reaches `pc`.
+    fn run_to(cpu: &mut StackCPU, pc: u64) {
+        for _ in 0..100 {
+            if cpu.pc == pc {
+                return;
+            }
+            assert_eq!(cpu.step(), StepResult::Continue);
+        }
+        panic!("Never reached 0x{pc:X}");
+    }
+
+    fn interrupt_system(cpu: &StackCPU, lines: usize) -> InterruptSystem {
+        let table = InterruptVectorTable::new(cpu.memory.clone(), VECTOR_TABLE, IRQ_BASE + lines);
+        InterruptSystem::new(create_interrupt_controller(lines), table)
+    }
+
+    #[test]
+    fn arithmetic_pops_b_then_a() {
+        let cpu = run(&[Push(10), Push(3), Sub, Push(-7), Push(2), DivS, Push(-1), Push(60), Shr, Push(5), Push(3), LtS, Halt]);
+        assert_eq!(cpu.values(), [7, -3i64 as u64, 0xF, 0]);
+    }
+
+    #[test]
+    fn calls_pass_arguments_and_return_values() {
+        // f(a, b) = a - b with a local in between.
+        let main = [Push(10), Push(3), Call(FUNCTION), Halt];
+        let function = [Enter(1), LoadArg(1), LoadArg(0), Sub, StoreLocal(0), LoadLocal(0), Ret(2)];
+        let mut cpu = load(&main);
+        write(&cpu, FUNCTION, &function);
+        let return_address = address(&main, 3);
+
+        run_to(&mut cpu, FUNCTION);
+        assert_eq!(cpu.fp, 32);
+        assert_eq!(cpu.values(), [10, 3, return_address, 0]);
+        assert_eq!(cpu.return_addresses(), [return_address]);
+
+        // `arg n` is slot fp-3-n: the last argument pushed is `arg 0`.
+        run_to(&mut cpu, FUNCTION + address(&function, 5));
+        assert_eq!(cpu.values(), [10, 3, return_address, 0, 7]);
+
+        assert_eq!(cpu.step(), StepResult::Continue);
+        assert_eq!(cpu.values(), [10, 3, return_address, 0, 7, 7]);
+        assert_eq!(cpu.step(), StepResult::Continue);
+        assert_eq!((cpu.pc, cpu.fp), (return_address, 0));
+        assert_eq!(cpu.values(), [7]);
+        assert_eq!(cpu.return_addresses(), []);
+        assert_eq!(cpu.run_for(10), StepResult::Halted);
+    }
+
+    #[test]
+    fn nested_frames_chain_their_frame_pointers() {
+        let inner = FUNCTION + 0x40;
+        let main = [Push(1), Call(FUNCTION), Halt];
+        let outer = [Enter(0), LoadArg(0), Push(1), Add, Call(inner), Ret(1)];
+        let mut cpu = load(&main);
+        write(&cpu, FUNCTION, &outer);
+        write(&cpu, inner, &[LoadArg(0), Dup, Mul, Ret(1)]);
+
+        run_to(&mut cpu, inner);
+        assert_eq!(cpu.fp, 48);
+        assert_eq!(cpu.return_addresses(), [FUNCTION + address(&outer, 5), address(&main, 2)]);
+        assert_eq!(cpu.run_for(20), StepResult::Halted);
+        assert_eq!((cpu.values(), cpu.fp), (vec![4], 0));
+    }
+
+    #[test]
+    fn faults_leave_the_stack_and_frame_as_they_were() {
+        let mut cpu = load(&[Push(1), Push(0), DivU]);
+        assert_eq!(cpu.run_for(2), StepResult::Continue);
+        assert_eq!(cpu.step(), StepResult::Fault(CPUFault::DivideByZero { address: 4 }));
+        assert_eq!((cpu.pc, cpu.values()), (4, vec![1, 0]));
+
+        // `ret 3` has unwound the frame by the time it finds only one
+        // argument to drop.
+        let main = [Push(5), Call(FUNCTION), Halt];
+        let mut cpu = load(&main);
+        write(&cpu, FUNCTION, &[Enter(1), Push(9), Ret(3)]);
+        run_to(&mut cpu, FUNCTION + 4);
+        let before = cpu.values();
+        let fault = StepResult::Fault(CPUFault::StackFault { address: FUNCTION + 4 });
+        assert_eq!(cpu.step(), fault);
+        assert_eq!((cpu.pc, cpu.fp, cpu.values()), (FUNCTION + 4, 24, before));
+
+        // Locals and arguments outside the frame.
+        for instruction in [LoadLocal(2), StoreLocal(5), LoadArg(3), Pop] {
+            let mut cpu = load(&[instruction]);
+            assert_eq!(cpu.step(), StepResult::Fault(CPUFault::StackFault { address: 0 }));
+            assert_eq!((cpu.pc, cpu.fp, cpu.stack.top()), (0, 0, 0));
+        }
+
+        // Overflowing the stack midway through `enter`.
+        let mut cpu = load(&[Enter(STACK_SIZE as u64 / 8 + 1)]);
+        assert_eq!(cpu.step(), StepResult::Fault(CPUFault::StackFault { address: 0 }));
+        assert_eq!(cpu.stack.top(), 0);
+    }
+
+    #[test]
+    fn interrupt_entry_pushes_and_iret_pops_the_return_state() {
+        let mut cpu = load(&[EnableInterrupts, Push(1), Push(2), Halt]);
+        write(&cpu, HANDLER, &[Push(7), Pop, InterruptReturn]);
+        let system = interrupt_system(&cpu, 4);
+        system.table.set_handler(IRQ_BASE, HANDLER);
+        system.controller.lock().unwrap().raise(0);
+
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert!(cpu.interrupts_enabled());
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert_eq!(cpu.values(), [1, 1, 7]);
+        assert!(!cpu.interrupts_enabled());
+        assert_eq!(system.run_for(&mut cpu, 2), StepResult::Continue);
+        assert_eq!((cpu.pc, cpu.values()), (1, vec![]));
+        assert!(cpu.interrupts_enabled());
+        assert_eq!(system.run_for(&mut cpu, 10), StepResult::Halted);
+        assert_eq!(cpu.values(), [1, 2]);
+    }
+
+    #[test]
+    fn interrupts_wake_wait() {
+        let mut cpu = load(&[EnableInterrupts, Wait, Halt]);
+        write(&cpu, HANDLER, &[InterruptReturn]);
+        let system = interrupt_system(&cpu, 4);
+        system.table.set_handler(IRQ_BASE + 2, HANDLER);
+        assert_eq!(system.run_for(&mut cpu, 10), StepResult::WaitingForInterrupt);
+        assert_eq!(system.step(&mut cpu), StepResult::WaitingForInterrupt);
+
+        // The handler returns right away, to the instruction after `wait`.
+        system.handle(2).raise();
+        assert_eq!(system.step(&mut cpu), StepResult::Continue);
+        assert_eq!((cpu.pc, cpu.values()), (2, vec![]));
+        assert_eq!(system.run_for(&mut cpu, 10), StepResult::Halted);
+    }
+
+    #[test]
+    fn faulting_interrupt_entry_changes_nothing() {
+        let mut cpu = load(&[EnableInterrupts, Halt]);
+        assert_eq!(cpu.step(), StepResult::Continue);
+        cpu.stack.set_top(STACK_SIZE - 8);
+        assert_eq!(cpu.enter_interrupt(IRQ_BASE, HANDLER), Err(CPUFault::StackFault { address: 1 }));
+        assert_eq!((cpu.pc, cpu.stack.top()), (1, STACK_SIZE - 8));
+        assert!(cpu.interrupts_enabled());
+    }
+
+    #[test]
+    fn leb128_round_trips() {
+        let memory = create_memory(0x20);
+        let mut memory = access_memory!(memory);
+        for value in [0, 1, 63, 64, 127, 128, 0x3FFF, 0x4000, u32::MAX as u64, 1 << 62, 1 << 63, u64::MAX] {
+            let mut bytes = Vec::new();
+            encode_uleb128(value, &mut bytes);
+            memory.write_bytes(0, &bytes);
+            let mut reader = InstructionReader::new(&*memory, 0);
+            assert_eq!(reader.uleb128(), Ok(value));
+            assert_eq!(reader.length(), bytes.len());
+
+            for value in [value as i64, (value as i64).wrapping_neg()] {
+                let mut bytes = Vec::new();
+                encode_sleb128(value, &mut bytes);
+                memory.write_bytes(0, &bytes);
+                let mut reader = InstructionReader::new(&*memory, 0);
+                assert_eq!(reader.sleb128(), Ok(value));
+                assert_eq!(reader.length(), bytes.len());
+            }
+        }
+
+        // A signed value only needs its sign bit in the last byte.
+        let mut bytes = Vec::new();
+        encode_sleb128(-64, &mut bytes);
+        encode_sleb128(64, &mut bytes);
+        assert_eq!(bytes, [0x40, 0xC0, 0x00]);
+    }
+
+    #[test]
+    fn instructions_round_trip() {
+        let program = [Push(i64::MIN), Push(i64::MAX), Push(-1), Jump(u64::MAX), Call(0), Ret(300), Enter(1), Add, Halt];
+        let memory = create_memory(0x100);
+        let mut memory = access_memory!(memory);
+        memory.write_bytes(0, &assemble_stack_program(&program));
+        let mut at = 0;
+        for instruction in program {
+            let decoded = StackDecoder.decode_at(&*memory, at).unwrap();
+            let length = assemble_stack_program(&[instruction]).len();
+            assert_eq!(decoded, Decoded { instruction, address: at, length });
+            // The disassembly reads back as the same instruction.
+            let text = instruction.to_string();
+            let operand = text.split_once(' ').map(|(_, x)| x.parse::<i128>().unwrap() as i64);
+            assert_eq!(StackInstruction::from_mnemonic(instruction.mnemonic(), operand), Some(instruction));
+            at += length;
+        }
+        assert_eq!(assemble_stack_program(&[Push(i64::MIN)]).len(), StackDecoder::MAX_LENGTH);
+    }
+}
@@ -0,0 +1,301 @@
+pub trait Lane: Copy + PartialOrd {
+    const SIZE: usize;
+    fn read(bytes: &[u8]) -> Self;
+    fn write(self, bytes: &mut [u8]);
+    fn lane_add(self, other: Self) -> Self;
+    fn lane_sub(self, other: Self) -> Self;
+    fn lane_mul(self, other: Self) -> Self;
+}
+
+pub trait IntegerLane: Lane {
+    fn lane_saturating_add(self, other: Self) -> Self;
+    fn lane_saturating_sub(self, other: Self) -> Self;
+}
+
+macro_rules! impl_integer_lane {
+    ( $type:ty ) => {
+        impl Lane for $type {
+            const SIZE: usize = std::mem::size_of::<$type>();
+            fn read(bytes: &[u8]) -> Self {
+                <$type>::from_le_bytes(bytes.try_into().unwrap())
+            }
+            fn write(self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_le_bytes());
+            }
+            fn lane_add(self, other: Self) -> Self {
+                self.wrapping_add(other)
+            }
+            fn lane_sub(self, other: Self) -> Self {
+                self.wrapping_sub(other)
+            }
+            fn lane_mul(self, other: Self) -> Self {
+                self.wrapping_mul(other)
+            }
+        }
+        impl IntegerLane for $type {
+            fn lane_saturating_add(self, other: Self) -> Self {
+                self.saturating_add(other)
+            }
+            fn lane_saturating_sub(self, other: Self) -> Self {
+                self.saturating_sub(other)
+            }
+        }
+    };
+}
+
+macro_rules! impl_float_lane {
+    ( $type:ty ) => {
+        impl Lane for $type {
+            const SIZE: usize = std::mem::size_of::<$type>();
+            fn read(bytes: &[u8]) -> Self {
+                <$type>::from_le_bytes(bytes.try_into().unwrap())
+            }
+            fn write(self, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&self.to_le_bytes());
+            }
+            fn lane_add(self, other: Self) -> Self {
+                self + other
+            }
+            fn lane_sub(self, other: Self) -> Self {
+                self - other
+            }
+            fn lane_mul(self, other: Self) -> Self {
+                self * other
+            }
+        }
+    };
+}
+
+impl_integer_lane!(u8);
+impl_integer_lane!(u16);
+impl_integer_lane!(u32);
+impl_integer_lane!(u64);
+impl_integer_lane!(i8);
+impl_integer_lane!(i16);
+impl_integer_lane!(i32);
+impl_integer_lane!(i64);
+impl_float_lane!(f32);
+impl_float_lane!(f64);
+
+// Lanes are stored little-endian, lane 0 in the lowest bytes. Comparisons
+// produce a mask with every bit of a lane set when the predicate holds.
+pub trait VectorRegisterTrait: Sized + Copy {
+    const BYTES: usize;
+
+    fn zeroed() -> Self;
+    fn bytes(&self) -> &[u8];
+    fn bytes_mut(&mut self) -> &mut [u8];
+
+    fn lane_count<T: Lane>() -> usize {
+        Self::BYTES / T::SIZE
+    }
+
+    fn lane<T: Lane>(&self, index: usize) -> T {
+        if index >= Self::lane_count::<T>() {
+            panic!("Lane {index} is out of range, register has {} lanes of {} bytes", Self::lane_count::<T>(), T::SIZE);
+        }
+        T::read(&self.bytes()[index * T::SIZE..(index + 1) * T::SIZE])
+    }
+
+    fn set_lane<T: Lane>(&mut self, index: usize, value: T) {
+        if index >= Self::lane_count::<T>() {
+            panic!("Lane {index} is out of range, register has {} lanes of {} bytes", Self::lane_count::<T>(), T::SIZE);
+        }
+        value.write(&mut self.bytes_mut()[index * T::SIZE..(index + 1) * T::SIZE]);
+    }
+
+    fn lanes<T: Lane>(&self) -> Vec<T> {
+        self.bytes().chunks_exact(T::SIZE).map(T::read).collect()
+    }
+
+    fn to_array<T: Lane, const L: usize>(&self) -> [T; L] {
+        if L != Self::lane_count::<T>() {
+            panic!("Register has {} lanes of {} bytes, but trying to view it as {L} lanes", Self::lane_count::<T>(), T::SIZE);
+        }
+        std::array::from_fn(|index| self.lane(index))
+    }
+
+    fn from_lanes<T: Lane>(lanes: &[T]) -> Self {
+        if lanes.len() != Self::lane_count::<T>() {
+            panic!("Register has {} lanes of {} bytes, but {} lanes were given", Self::lane_count::<T>(), T::SIZE, lanes.len());
+        }
+        let mut register = Self::zeroed();
+        for (index, lane) in lanes.iter().enumerate() {
+            register.set_lane(index, *lane);
+        }
+        register
+    }
+
+    fn splat<T: Lane>(value: T) -> Self {
+        let mut register = Self::zeroed();
+        for index in 0..Self::lane_count::<T>() {
+            register.set_lane(index, value);
+        }
+        register
+    }
+
+    fn map_lanes<T: Lane>(self, f: impl Fn(T) -> T) -> Self {
+        let mut register = self;
+        for index in 0..Self::lane_count::<T>() {
+            register.set_lane(index, f(self.lane(index)));
+        }
+        register
+    }
+
+    fn zip_lanes<T: Lane>(self, other: Self, f: impl Fn(T, T) -> T) -> Self {
+        let mut register = self;
+        for index in 0..Self::lane_count::<T>() {
+            register.set_lane(index, f(self.lane(index), other.lane(index)));
+        }
+        register
+    }
+
+    fn add_lanes<T: Lane>(self, other: Self) -> Self {
+        self.zip_lanes(other, T::lane_add)
+    }
+
+    fn sub_lanes<T: Lane>(self, other: Self) -> Self {
+        self.zip_lanes(other, T::lane_sub)
+    }
+
+    fn mul_lanes<T: Lane>(self, other: Self) -> Self {
+        self.zip_lanes(other, T::lane_mul)
+    }
+
+    fn saturating_add_lanes<T: IntegerLane>(self, other: Self) -> Self {
+        self.zip_lanes(other, T::lane_saturating_add)
+    }
+
+    fn saturating_sub_lanes<T: IntegerLane>(self, other: Self) -> Self {
+        self.zip_lanes(other, T::lane_saturating_sub)
+    }
+
+    fn min_lanes<T: Lane>(self, other: Self) -> Self {
+        self.zip_lanes(other, |a: T, b: T| if b < a { b } else { a })
+    }
+
+    fn max_lanes<T: Lane>(self, other: Self) -> Self {
+        self.zip_lanes(other, |a: T, b: T| if b > a { b } else { a })
+    }
+
+    fn compare_lanes<T: Lane>(self, other: Self, predicate: impl Fn(T, T) -> bool) -> Self {
+        let mut register = Self::zeroed();
+        for index in 0..Self::lane_count::<T>() {
+            if predicate(self.lane(index), other.lane(index)) {
+                register.bytes_mut()[index * T::SIZE..(index + 1) * T::SIZE].fill(0xFF);
+            }
+        }
+        register
+    }
+
+    fn cmp_eq<T: Lane>(self, other: Self) -> Self {
+        self.compare_lanes(other, |a: T, b: T| a == b)
+    }
+
+    fn cmp_lt<T: Lane>(self, other: Self) -> Self {
+        self.compare_lanes(other, |a: T, b: T| a < b)
+    }
+
+    fn cmp_gt<T: Lane>(self, other: Self) -> Self {
+        self.compare_lanes(other, |a: T, b: T| a > b)
+    }
+
+    // Bit `i` of the result is the top bit of lane `i`.
+    fn move_mask<T: Lane>(&self) -> u64 {
+        (0..Self::lane_count::<T>())
+            .filter(|index| self.bytes()[(index + 1) * T::SIZE - 1] & 0x80 != 0)
+            .fold(0, |mask, index| mask | (1 << index))
+    }
+
+    // Lane `i` of the result is lane `indices[i]` of `self`.
+    fn shuffle<T: Lane>(self, indices: &[usize]) -> Self {
+        let mut register = Self::zeroed();
+        for (index, source) in indices.iter().enumerate() {
+            register.set_lane(index, self.lane::<T>(*source));
+        }
+        register
+    }
+
+    // Lane `i` of the result is lane `indices[i]` of `self` followed by `other`.
+    fn shuffle2<T: Lane>(self, other: Self, indices: &[usize]) -> Self {
+        let count = Self::lane_count::<T>();
+        let mut register = Self::zeroed();
+        for (index, source) in indices.iter().enumerate() {
+            let lane = if *source < count { self.lane::<T>(*source) } else { other.lane::<T>(*source - count) };
+            register.set_lane(index, lane);
+        }
+        register
+    }
+
+    // Byte `i` of the result is the byte of `self` selected by byte `i` of
+    // `indices`, or zero when its top bit is set.
+    fn shuffle_bytes(self, indices: Self) -> Self {
+        let mut register = Self::zeroed();
+        for (index, selector) in indices.bytes().iter().enumerate() {
+            if selector & 0x80 == 0 {
+                register.bytes_mut()[index] = self.bytes()[*selector as usize % Self::BYTES];
+            }
+        }
+        register
+    }
+}
+
+#[macro_export]
+macro_rules! define_vector_register {
+    ( $name:ident, $bytes:literal ) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct $name(pub [u8; $bytes]);
+        impl Default for $name {
+            fn default() -> Self {
+                Self([0; $bytes])
+            }
+        }
+        impl $crate::vector::VectorRegisterTrait for $name {
+            const BYTES: usize = $bytes;
+            fn zeroed() -> Self {
+                Self([0; $bytes])
+            }
+            fn bytes(&self) -> &[u8] {
+                &self.0
+            }
+            fn bytes_mut(&mut self) -> &mut [u8] {
+                &mut self.0
+            }
+        }
+        impl $crate::register::RegisterTrait<[u8; $bytes]> for $name {
+            fn get(&self) -> [u8; $bytes] {
+                self.0
+            }
+            fn set(&mut self, value: [u8; $bytes]) {
+                self.0 = value;
+            }
+        }
+        impl std::ops::BitAnd for $name {
+            type Output = $name;
+            fn bitand(self, other: Self) -> Self::Output {
+                Self(std::array::from_fn(|i| self.0[i] & other.0[i]))
+            }
+        }
+        impl std::ops::BitOr for $name {
+            type Output = $name;
+            fn bitor(self, other: Self) -> Self::Output {
+                Self(std::array::from_fn(|i| self.0[i] | other.0[i]))
+            }
+        }
+        impl std::ops::BitXor for $name {
+            type Output = $name;
+            fn bitxor(self, other: Self) -> Self::Output {
+                Self(std::array::from_fn(|i| self.0[i] ^ other.0[i]))
+            }
+        }
+        impl std::ops::Not for $name {
+            type Output = $name;
+            fn not(self) -> Self::Output {
+                Self(self.0.map(|x| !x))
+            }
+        }
+    };
+}
+
+define_vector_register!(RegisterV128, 16);
+define_vector_register!(RegisterV256, 32);
@@ -0,0 +1,265 @@
+use std::{fs::{File, OpenOptions}, io::{self, Read, Seek, SeekFrom, Write}, path::Path, sync::{Arc, Mutex}};
+
+use memmap2::{Mmap, MmapMut};
+
+use crate::mem::{Memory, MemorySliceTrait};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAccess {
+    ReadOnly,
+    ReadWrite,
+}
+
+fn open_file(path: &Path, access: FileAccess) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(access == FileAccess::ReadWrite)
+        .open(path)
+}
+
+fn create_file(path: &Path, size: usize) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.set_len(size as u64)?;
+    Ok(file)
+}
+
+fn panic_read_only(at: usize, length: usize) -> ! {
+    panic!("Memory is read-only, but trying to write at 0x{at:04X}({at}) with 0x{length:04X}({length}) bytes");
+}
+
+// Every access goes straight to the file, so nothing is held in host memory
+// besides the OS page cache. Writes are made durable by `flush`.
+#[derive(Debug)]
+pub struct FileMemory {
+    file: File,
+    size: usize,
+    access: FileAccess,
+}
+
+impl FileMemory {
+    pub fn open<P: AsRef<Path>>(path: P, access: FileAccess) -> io::Result<Self> {
+        let file = open_file(path.as_ref(), access)?;
+        let size = file.metadata()?.len() as usize;
+        Ok(Self { file, size, access })
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P, size: usize) -> io::Result<Self> {
+        let file = create_file(path.as_ref(), size)?;
+        Ok(Self { file, size, access: FileAccess::ReadWrite })
+    }
+
+    pub fn access(&self) -> FileAccess {
+        self.access
+    }
+
+    fn load(&self, at: usize, buffer: &mut [u8]) {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(at as u64))
+            .and_then(|_| file.read_exact(buffer))
+            .unwrap_or_else(|error| panic!("Failed to read 0x{:04X}({}) bytes at 0x{at:04X}({at}): {error}", buffer.len(), buffer.len()));
+    }
+
+    fn store(&mut self, at: usize, bytes: &[u8]) {
+        if self.access == FileAccess::ReadOnly {
+            panic_read_only(at, bytes.len());
+        }
+        self.file.seek(SeekFrom::Start(at as u64))
+            .and_then(|_| self.file.write_all(bytes))
+            .unwrap_or_else(|error| panic!("Failed to write 0x{:04X}({}) bytes at 0x{at:04X}({at}): {error}", bytes.len(), bytes.len()));
+    }
+}
+
+impl MemorySliceTrait for FileMemory {
+    fn len(&self) -> usize {
+        self.size
+    }
+
+    fn assert_access_range(&self, at: usize, length: usize) {
+        if at.checked_add(length).is_none_or(|end| end > self.size) {
+            panic!("Memory size is 0x{:04X}({}), but trying to access at 0x{at:04X}({at}) with 0x{length:04X}({length}) bytes", self.size, self.size);
+        }
+    }
+
+    fn read_u8(&self, at: usize) -> u8 {
+        self.assert_access_range(at, 1);
+        let mut buffer = [0u8; 1];
+        self.load(at, &mut buffer);
+        buffer[0]
+    }
+
+    fn write_u8(&mut self, at: usize, value: u8) {
+        self.assert_access_range(at, 1);
+        self.store(at, &[value]);
+    }
+
+    fn read_u16(&self, at: usize) -> u16 {
+        self.assert_access_range(at, 2);
+        let mut buffer = [0u8; 2];
+        self.load(at, &mut buffer);
+        u16::from_be_bytes(buffer)
+    }
+
+    fn write_u16(&mut self, at: usize, value: u16) {
+        self.assert_access_range(at, 2);
+        self.store(at, &value.to_be_bytes());
+    }
+
+    fn read_u32(&self, at: usize) -> u32 {
+        self.assert_access_range(at, 4);
+        let mut buffer = [0u8; 4];
+        self.load(at, &mut buffer);
+        u32::from_be_bytes(buffer)
+    }
+
+    fn write_u32(&mut self, at: usize, value: u32) {
+        self.assert_access_range(at, 4);
+        self.store(at, &value.to_be_bytes());
+    }
+
+    fn read_bytes(&self, at: usize, length: usize) -> Vec<u8> {
+        self.assert_access_range(at, length);
+        let mut bytes = vec![0u8; length];
+        self.load(at, &mut bytes);
+        bytes
+    }
+
+    fn read_into(&self, at: usize, buffer: &mut [u8]) {
+        self.assert_access_range(at, buffer.len());
+        self.load(at, buffer);
+    }
+
+    fn write_bytes(&mut self, at: usize, bytes: &[u8]) {
+        self.assert_access_range(at, bytes.len());
+        self.store(at, bytes);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.access {
+            FileAccess::ReadOnly => Ok(()),
+            FileAccess::ReadWrite => self.file.sync_data(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Mapping {
+    ReadOnly(Mmap),
+    ReadWrite(MmapMut),
+}
+
+#[derive(Debug)]
+pub struct MmapMemory {
+    mapping: Mapping,
+}
+
+impl MmapMemory {
+    pub fn open<P: AsRef<Path>>(path: P, access: FileAccess) -> io::Result<Self> {
+        let file = open_file(path.as_ref(), access)?;
+        // SAFETY: the mapping is only sound while no other process truncates or
+        // rewrites the file, which is the caller's responsibility for guest images.
+        let mapping = unsafe {
+            match access {
+                FileAccess::ReadOnly => Mapping::ReadOnly(Mmap::map(&file)?),
+                FileAccess::ReadWrite => Mapping::ReadWrite(MmapMut::map_mut(&file)?),
+            }
+        };
+        Ok(Self { mapping })
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P, size: usize) -> io::Result<Self> {
+        let file = create_file(path.as_ref(), size)?;
+        // SAFETY: see `MmapMemory::open`.
+        let mapping = unsafe { Mapping::ReadWrite(MmapMut::map_mut(&file)?) };
+        Ok(Self { mapping })
+    }
+
+    pub fn access(&self) -> FileAccess {
+        match self.mapping {
+            Mapping::ReadOnly(_) => FileAccess::ReadOnly,
+            Mapping::ReadWrite(_) => FileAccess::ReadWrite,
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match &self.mapping {
+            Mapping::ReadOnly(map) => map,
+            Mapping::ReadWrite(map) => map,
+        }
+    }
+
+    fn writable_bytes(&mut self) -> Option<&mut [u8]> {
+        match &mut self.mapping {
+            Mapping::ReadOnly(_) => None,
+            Mapping::ReadWrite(map) => Some(map),
+        }
+    }
+
+    fn bytes_mut(&mut self, at: usize, length: usize) -> &mut [u8] {
+        match &mut self.mapping {
+            Mapping::ReadOnly(_) => panic_read_only(at, length),
+            Mapping::ReadWrite(map) => &mut map[at..at + length],
+        }
+    }
+}
+
+impl MemorySliceTrait for MmapMemory {
+    fn len(&self) -> usize {
+        self.bytes().len()
+    }
+
+    fn assert_access_range(&self, at: usize, length: usize) {
+        if at.checked_add(length).is_none_or(|end| end > self.len()) {
+            panic!("Memory size is 0x{:04X}({}), but trying to access at 0x{at:04X}({at}) with 0x{length:04X}({length}) bytes", self.len(), self.len());
+        }
+    }
+
+    fn read_u8(&self, at: usize) -> u8 {
+        self.assert_access_range(at, 1);
+        self.bytes()[at]
+    }
+
+    fn write_u8(&mut self, at: usize, value: u8) {
+        self.assert_access_range(at, 1);
+        self.bytes_mut(at, 1)[0] = value;
+    }
+
+    fn read_bytes(&self, at: usize, length: usize) -> Vec<u8> {
+        self.assert_access_range(at, length);
+        self.bytes()[at..at + length].to_vec()
+    }
+
+    fn write_bytes(&mut self, at: usize, bytes: &[u8]) {
+        self.assert_access_range(at, bytes.len());
+        self.bytes_mut(at, bytes.len()).copy_from_slice(bytes);
+    }
+
+    fn as_slice(&self, at: usize, length: usize) -> Option<&[u8]> {
+        self.assert_access_range(at, length);
+        Some(&self.bytes()[at..at + length])
+    }
+
+    fn as_mut_slice(&mut self, at: usize, length: usize) -> Option<&mut [u8]> {
+        self.assert_access_range(at, length);
+        self.writable_bytes().map(|bytes| &mut bytes[at..at + length])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &self.mapping {
+            Mapping::ReadOnly(_) => Ok(()),
+            Mapping::ReadWrite(map) => map.flush(),
+        }
+    }
+}
+
+pub fn create_file_memory<P: AsRef<Path>>(path: P, access: FileAccess) -> io::Result<Memory> {
+    Ok(Arc::new(Mutex::new(FileMemory::open(path, access)?)))
+}
+
+pub fn create_mmap_memory<P: AsRef<Path>>(path: P, access: FileAccess) -> io::Result<Memory> {
+    Ok(Arc::new(Mutex::new(MmapMemory::open(path, access)?)))
+}
@@ -1,4 +1,7 @@
 pub mod wrappers;
 pub mod mem;
 pub mod vmem;
-pub mod pointer;
\ No newline at end of file
+pub mod pointer;
+pub mod sparse;
+pub mod file;
+pub mod stats;
\ No newline at end of file
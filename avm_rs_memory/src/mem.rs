@@ -1,84 +1,292 @@
-use std::{fmt::Display, sync::{Arc, Mutex}};
+use std::{cmp::Ordering, fmt::{Debug, Display}, ops::{Deref, DerefMut}, sync::{Arc, Mutex, MutexGuard}};
+
+// Size of the buffer the default fill and copies go through.
+const CHUNK: usize = 0x1000;
 
 pub trait MemorySliceTrait {
     fn len(&self) -> usize;
     fn assert_access_range(&self, at: usize, length: usize);
 
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     fn read_u8(&self, at: usize) -> u8;
-    fn read_i8(&self, at: usize) -> i8;
     fn write_u8(&mut self, at: usize, value: u8);
-    fn write_i8(&mut self, at: usize, value: i8);
-
-    fn read_u16(&self, at: usize) -> u16;
-    fn read_i16(&self, at: usize) -> i16;
-    fn write_u16(&mut self, at: usize, value: u16);
-    fn write_i16(&mut self, at: usize, value: i16);
-
-    fn read_u32(&self, at: usize) -> u32;
-    fn read_i32(&self, at: usize) -> i32;
-    fn write_u32(&mut self, at: usize, value: u32);
-    fn write_i32(&mut self, at: usize, value: i32);
-
-    fn read_u64(&self, at: usize) -> u64;
-    fn read_i64(&self, at: usize) -> i64;
-    fn write_u64(&mut self, at: usize, value: u64);
-    fn write_i64(&mut self, at: usize, value: i64);
-
-    fn read_u128(&self, at: usize) -> u128;
-    fn read_i128(&self, at: usize) -> i128;
-    fn write_u128(&mut self, at: usize, value: u128);
-    fn write_i128(&mut self, at: usize, value: i128);
-
-    fn read_f32(&self, at: usize) -> f32;
-    fn write_f32(&mut self, at: usize, value: f32);
-
-    fn read_f64(&self, at: usize) -> f64;
-    fn write_f64(&mut self, at: usize, value: f64);
-
-    fn read_bytes(&self, at: usize, length: usize) -> Vec<u8>;
-    fn write_bytes(&mut self, at: usize, bytes: &[u8]);
-    fn read_string(&self, at: usize, length: usize) -> String;
-    fn write_string(&mut self, at: usize, value: &str);
-}
 
-#[derive(Debug)]
-pub struct _Memory(Vec<u8>);
+    fn read_i8(&self, at: usize) -> i8 {
+        self.read_u8(at) as i8
+    }
 
-impl _Memory {
-    pub fn new(size: usize) -> Self {
-        Self(vec![0u8; size])
+    fn write_i8(&mut self, at: usize, value: i8) {
+        self.write_u8(at, value as u8);
     }
 
-    pub fn display(&self, at: usize, length: usize) -> String {
-        self.assert_access_range(at, length);
+    fn read_u16(&self, at: usize) -> u16 {
+        self.assert_access_range(at, 2);
+        ((self.read_u8(at) as u16) << 8) | (self.read_u8(at + 1) as u16)
+    }
 
-        let mut buf = String::new();
+    fn read_i16(&self, at: usize) -> i16 {
+        self.read_u16(at) as i16
+    }
 
-        if at > 0 {
-            buf += &format!("\n... (- {} bytes)\n", at);
+    fn write_u16(&mut self, at: usize, value: u16) {
+        self.assert_access_range(at, 2);
+        self.write_u8(at, (value >> 8) as u8);
+        self.write_u8(at + 1, value as u8);
+    }
+
+    fn write_i16(&mut self, at: usize, value: i16) {
+        self.write_u16(at, value as u16);
+    }
+
+    fn read_u32(&self, at: usize) -> u32 {
+        self.assert_access_range(at, 4);
+        ((self.read_u16(at) as u32) << 16) | (self.read_u16(at + 2) as u32)
+    }
+
+    fn read_i32(&self, at: usize) -> i32 {
+        self.read_u32(at) as i32
+    }
+
+    fn write_u32(&mut self, at: usize, value: u32) {
+        self.assert_access_range(at, 4);
+        self.write_u16(at, (value >> 16) as u16);
+        self.write_u16(at + 2, value as u16);
+    }
+
+    fn write_i32(&mut self, at: usize, value: i32) {
+        self.write_u32(at, value as u32);
+    }
+
+    fn read_u64(&self, at: usize) -> u64 {
+        self.assert_access_range(at, 8);
+        self.read_u32(at) as u64 | (self.read_u32(at + 4) as u64) << 32
+    }
+
+    fn read_i64(&self, at: usize) -> i64 {
+        self.read_u64(at) as i64
+    }
+
+    fn write_u64(&mut self, at: usize, value: u64) {
+        self.assert_access_range(at, 8);
+        self.write_u32(at, value as u32);
+        self.write_u32(at + 4, (value >> 32) as u32);
+    }
+
+    fn write_i64(&mut self, at: usize, value: i64) {
+        self.write_u64(at, value as u64);
+    }
+
+    fn read_u128(&self, at: usize) -> u128 {
+        self.assert_access_range(at, 16);
+        self.read_u64(at) as u128 | (self.read_u64(at + 8) as u128) << 64
+    }
+
+    fn read_i128(&self, at: usize) -> i128 {
+        self.read_u128(at) as i128
+    }
+
+    fn write_u128(&mut self, at: usize, value: u128) {
+        self.assert_access_range(at, 16);
+        self.write_u64(at, value as u64);
+        self.write_u64(at + 8, (value >> 64) as u64);
+    }
+
+    fn write_i128(&mut self, at: usize, value: i128) {
+        self.write_u128(at, value as u128);
+    }
+
+    fn read_f32(&self, at: usize) -> f32 {
+        self.assert_access_range(at, 4);
+        f32::from_le_bytes(self.read_bytes(at, 4).try_into().unwrap())
+    }
+
+    fn write_f32(&mut self, at: usize, value: f32) {
+        self.write_bytes(at, &value.to_le_bytes());
+    }
+
+    fn read_f64(&self, at: usize) -> f64 {
+        self.assert_access_range(at, 8);
+        f64::from_le_bytes(self.read_bytes(at, 8).try_into().unwrap())
+    }
+
+    fn write_f64(&mut self, at: usize, value: f64) {
+        self.write_bytes(at, &value.to_le_bytes());
+    }
+
+    fn read_bytes(&self, at: usize, length: usize) -> Vec<u8> {
+        self.assert_access_range(at, length);
+        (at..at + length).map(|i| self.read_u8(i)).collect()
+    }
+
+    fn write_bytes(&mut self, at: usize, bytes: &[u8]) {
+        self.assert_access_range(at, bytes.len());
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_u8(at + i, *byte);
         }
+    }
 
-        for i in 0..length {
-            buf += &format!("{:02x} ", self.0[at + i]);
-            if i % 16 == 15 {
-                buf += "| ";
-                for j in (0..16).rev() {
-                    buf += &format!("{}", if self.0[at + i - j] < 32 || self.0[at + i - j] > 126 { '.' } else { self.0[at + i - j] as char });
+    // Reads `buffer.len()` bytes at `at` into `buffer`.
+    fn read_into(&self, at: usize, buffer: &mut [u8]) {
+        self.assert_access_range(at, buffer.len());
+        match self.as_slice(at, buffer.len()) {
+            Some(bytes) => buffer.copy_from_slice(bytes),
+            None => {
+                for (i, byte) in buffer.iter_mut().enumerate() {
+                    *byte = self.read_u8(at + i);
                 }
-                if i < length - 1 {
-                    buf += "\n";
+            }
+        }
+    }
+
+    fn read_string(&self, at: usize, length: usize) -> String {
+        String::from_utf8_lossy(&self.read_bytes(at, length)).into_owned()
+    }
+
+    fn write_string(&mut self, at: usize, value: &str) {
+        self.write_bytes(at, value.as_bytes());
+    }
+
+    fn fetch_u8(&self, at: usize) -> u8 {
+        self.read_u8(at)
+    }
+
+    fn fetch_u16(&self, at: usize) -> u16 {
+        self.read_u16(at)
+    }
+
+    fn fetch_u32(&self, at: usize) -> u32 {
+        self.read_u32(at)
+    }
+
+    fn fetch_u64(&self, at: usize) -> u64 {
+        self.read_u64(at)
+    }
+
+    fn fetch_bytes(&self, at: usize, length: usize) -> Vec<u8> {
+        self.read_bytes(at, length)
+    }
+
+    fn as_slice(&self, _at: usize, _length: usize) -> Option<&[u8]> {
+        None
+    }
+
+    fn as_mut_slice(&mut self, _at: usize, _length: usize) -> Option<&mut [u8]> {
+        None
+    }
+
+    fn fill(&mut self, at: usize, length: usize, value: u8) {
+        self.assert_access_range(at, length);
+        if let Some(slice) = self.as_mut_slice(at, length) {
+            slice.fill(value);
+            return;
+        }
+        // Written through a fixed buffer so filling a large range doesn't
+        // allocate all of it at once.
+        let chunk = [value; CHUNK];
+        let mut offset = 0;
+        while offset < length {
+            let size = CHUNK.min(length - offset);
+            self.write_bytes(at + offset, &chunk[..size]);
+            offset += size;
+        }
+    }
+
+    fn copy_within(&mut self, from: usize, to: usize, length: usize) {
+        self.assert_access_range(from, length);
+        self.assert_access_range(to, length);
+        let start = from.min(to);
+        match self.as_mut_slice(start, from.max(to) - start + length) {
+            Some(slice) => slice.copy_within(from - start..from - start + length, to - start),
+            None => {
+                // Copying forwards would overwrite the source before it's
+                // read when the destination overlaps its end, so that case
+                // walks backwards.
+                let mut buffer = [0u8; CHUNK];
+                let backwards = to > from && to < from + length;
+                let mut offset = 0;
+                while offset < length {
+                    let size = CHUNK.min(length - offset);
+                    let position = if backwards { length - offset - size } else { offset };
+                    self.read_into(from + position, &mut buffer[..size]);
+                    self.write_bytes(to + position, &buffer[..size]);
+                    offset += size;
                 }
             }
         }
+    }
 
-        if length < self.0.len() - at {
-            buf += &format!("\n... (+ {} more bytes)", self.0.len() - at - length);
+    fn compare(&self, at: usize, other: usize, length: usize) -> Ordering {
+        self.assert_access_range(at, length);
+        self.assert_access_range(other, length);
+        match (self.as_slice(at, length), self.as_slice(other, length)) {
+            (Some(left), Some(right)) => left.cmp(right),
+            _ => self.read_bytes(at, length).cmp(&self.read_bytes(other, length)),
+        }
+    }
+
+    fn compare_bytes(&self, at: usize, bytes: &[u8]) -> Ordering {
+        self.assert_access_range(at, bytes.len());
+        match self.as_slice(at, bytes.len()) {
+            Some(slice) => slice.cmp(bytes),
+            None => self.read_bytes(at, bytes.len()).as_slice().cmp(bytes),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn display(&self, at: usize, length: usize) -> String {
+        self.assert_access_range(at, length);
+
+        let bytes = self.read_bytes(at, length);
+        let mut buf = String::new();
+
+        if at > 0 {
+            buf += &format!("\n... (- {} bytes)\n", at);
+        }
+
+        buf += &hexdump(&bytes, |c| if !(32..=126).contains(&c) { '.' } else { c as char });
+
+        if length < self.len() - at {
+            buf += &format!("\n... (+ {} more bytes)", self.len() - at - length);
         }
 
         buf
     }
 }
 
+pub fn hexdump(bytes: &[u8], glyph: impl Fn(u8) -> char) -> String {
+    let mut buf = String::new();
+
+    for (i, byte) in bytes.iter().enumerate() {
+        buf += &format!("{:02x} ", byte);
+        if i % 16 == 15 {
+            buf += "| ";
+            for &c in &bytes[i - 15..=i] {
+                buf.push(glyph(c));
+            }
+            if i < bytes.len() - 1 {
+                buf += "\n";
+            }
+        }
+    }
+
+    buf
+}
+
+#[derive(Debug)]
+pub struct _Memory(Vec<u8>);
+
+impl _Memory {
+    pub fn new(size: usize) -> Self {
+        Self(vec![0u8; size])
+    }
+}
+
 impl MemorySliceTrait for _Memory {
     fn len(&self) -> usize {
         self.0.len()
@@ -235,6 +443,16 @@ impl MemorySliceTrait for _Memory {
         self.assert_access_range(at, value.len());
         self.0[at..at + value.len()].copy_from_slice(value.as_bytes());
     }
+
+    fn as_slice(&self, at: usize, length: usize) -> Option<&[u8]> {
+        self.assert_access_range(at, length);
+        Some(&self.0[at..at + length])
+    }
+
+    fn as_mut_slice(&mut self, at: usize, length: usize) -> Option<&mut [u8]> {
+        self.assert_access_range(at, length);
+        Some(&mut self.0[at..at + length])
+    }
 }
 
 impl Display for _Memory {
@@ -243,12 +461,109 @@ impl Display for _Memory {
     }
 }
 
-pub type Memory = Arc<Mutex<_Memory>>;
+impl Display for dyn MemorySliceTrait + Send {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display(0, self.len().min(128)))
+    }
+}
+
+impl Debug for dyn MemorySliceTrait + Send {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Memory(0x{:04X})", self.len())
+    }
+}
+
+pub type Memory = Arc<Mutex<dyn MemorySliceTrait + Send>>;
+
+pub struct MemoryView<'a> {
+    guard: MutexGuard<'a, dyn MemorySliceTrait + Send + 'static>,
+    at: usize,
+    length: usize,
+}
+
+impl<'a> MemoryView<'a> {
+    pub fn new(memory: &'a Memory, at: usize, length: usize) -> Option<Self> {
+        let guard = memory.lock().unwrap();
+        guard.as_slice(at, length)?;
+        Some(Self { guard, at, length })
+    }
+}
+
+impl Deref for MemoryView<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.guard.as_slice(self.at, self.length).unwrap()
+    }
+}
+
+pub struct MemoryViewMut<'a> {
+    guard: MutexGuard<'a, dyn MemorySliceTrait + Send + 'static>,
+    at: usize,
+    length: usize,
+}
+
+impl<'a> MemoryViewMut<'a> {
+    pub fn new(memory: &'a Memory, at: usize, length: usize) -> Option<Self> {
+        let mut guard = memory.lock().unwrap();
+        guard.as_mut_slice(at, length)?;
+        Some(Self { guard, at, length })
+    }
+}
+
+impl Deref for MemoryViewMut<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.guard.as_slice(self.at, self.length).unwrap()
+    }
+}
+
+impl DerefMut for MemoryViewMut<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.guard.as_mut_slice(self.at, self.length).unwrap()
+    }
+}
 
 pub fn create_memory(size: usize) -> Memory {
     Arc::new(Mutex::new(_Memory::new(size)))
 }
 
+// Locks are always taken in address order, so two threads copying in
+// opposite directions between the same memories can't deadlock.
+fn lock_pair<'a>(first: &'a Memory, second: &'a Memory) -> (MutexGuard<'a, dyn MemorySliceTrait + Send + 'static>, MutexGuard<'a, dyn MemorySliceTrait + Send + 'static>) {
+    if Arc::as_ptr(first) as *const () <= Arc::as_ptr(second) as *const () {
+        let first = first.lock().unwrap();
+        (first, second.lock().unwrap())
+    } else {
+        let second = second.lock().unwrap();
+        (first.lock().unwrap(), second)
+    }
+}
+
+pub fn copy_memory(source: &Memory, from: usize, destination: &Memory, to: usize, length: usize) {
+    if Arc::ptr_eq(source, destination) {
+        destination.lock().unwrap().copy_within(from, to, length);
+        return;
+    }
+
+    let (source, mut destination) = lock_pair(source, destination);
+    source.assert_access_range(from, length);
+    destination.assert_access_range(to, length);
+    if let Some(bytes) = source.as_slice(from, length) {
+        destination.write_bytes(to, bytes);
+        return;
+    }
+    let mut buffer = [0u8; CHUNK];
+    let mut offset = 0;
+    while offset < length {
+        let size = CHUNK.min(length - offset);
+        source.read_into(from + offset, &mut buffer[..size]);
+        destination.write_bytes(to + offset, &buffer[..size]);
+        offset += size;
+    }
+}
+
 #[macro_export]
 macro_rules! access_memory {
     ( $memory:expr ) => {
@@ -1,6 +1,6 @@
-use std::ops::{AddAssign, SubAssign};
+use std::cmp::Ordering;
 
-use crate::{access_memory, mem::{Memory, MemorySliceTrait}};
+use crate::{access_memory, mem::{copy_memory, Memory, MemorySliceTrait, MemoryView, MemoryViewMut}};
 
 #[derive(Debug)]
 pub struct Pointer {
@@ -12,11 +12,21 @@ pub struct Pointer {
 impl Pointer {
     pub fn new(memory: Memory, address: usize, size: usize) -> Self {
         Self {
-            memory: memory,
-            address: address,
-            size: size,
+            memory,
+            address,
+            size,
         }
     }
+
+    pub fn view(&self, at: usize, length: usize) -> Option<MemoryView<'_>> {
+        self.assert_access_range(at, length);
+        MemoryView::new(&self.memory, self.address + at, length)
+    }
+
+    pub fn view_mut(&mut self, at: usize, length: usize) -> Option<MemoryViewMut<'_>> {
+        self.assert_access_range(at, length);
+        MemoryViewMut::new(&self.memory, self.address + at, length)
+    }
 }
 
 impl MemorySliceTrait for Pointer {
@@ -153,7 +163,12 @@ impl MemorySliceTrait for Pointer {
 
     fn read_bytes(&self, at: usize, length: usize) -> Vec<u8> {
         self.assert_access_range(at, length);
-        access_memory!(self.memory).read_bytes(self.address + at, length).to_vec()
+        access_memory!(self.memory).read_bytes(self.address + at, length)
+    }
+
+    fn read_into(&self, at: usize, buffer: &mut [u8]) {
+        self.assert_access_range(at, buffer.len());
+        access_memory!(self.memory).read_into(self.address + at, buffer);
     }
 
     fn write_bytes(&mut self, at: usize, value: &[u8]) {
@@ -170,4 +185,62 @@ impl MemorySliceTrait for Pointer {
         self.assert_access_range(at, value.len());
         access_memory!(self.memory).write_string(self.address + at, value);
     }
-}
\ No newline at end of file
+
+    fn fetch_u8(&self, at: usize) -> u8 {
+        self.assert_access_range(at, 1);
+        access_memory!(self.memory).fetch_u8(self.address + at)
+    }
+
+    fn fetch_u16(&self, at: usize) -> u16 {
+        self.assert_access_range(at, 2);
+        access_memory!(self.memory).fetch_u16(self.address + at)
+    }
+
+    fn fetch_u32(&self, at: usize) -> u32 {
+        self.assert_access_range(at, 4);
+        access_memory!(self.memory).fetch_u32(self.address + at)
+    }
+
+    fn fetch_u64(&self, at: usize) -> u64 {
+        self.assert_access_range(at, 8);
+        access_memory!(self.memory).fetch_u64(self.address + at)
+    }
+
+    fn fetch_bytes(&self, at: usize, length: usize) -> Vec<u8> {
+        self.assert_access_range(at, length);
+        access_memory!(self.memory).fetch_bytes(self.address + at, length)
+    }
+
+    fn fill(&mut self, at: usize, length: usize, value: u8) {
+        self.assert_access_range(at, length);
+        access_memory!(self.memory).fill(self.address + at, length, value);
+    }
+
+    fn copy_within(&mut self, from: usize, to: usize, length: usize) {
+        self.assert_access_range(from, length);
+        self.assert_access_range(to, length);
+        access_memory!(self.memory).copy_within(self.address + from, self.address + to, length);
+    }
+
+    fn compare(&self, at: usize, other: usize, length: usize) -> Ordering {
+        self.assert_access_range(at, length);
+        self.assert_access_range(other, length);
+        access_memory!(self.memory).compare(self.address + at, self.address + other, length)
+    }
+
+    fn compare_bytes(&self, at: usize, bytes: &[u8]) -> Ordering {
+        self.assert_access_range(at, bytes.len());
+        access_memory!(self.memory).compare_bytes(self.address + at, bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        access_memory!(self.memory).flush()
+    }
+}
+
+// Source and destination may overlap when they point into the same memory,
+// the destination then ends up with the source's bytes from before the copy.
+pub fn copy(source: &Pointer, destination: &mut Pointer) {
+    destination.assert_access_range(0, source.size);
+    copy_memory(&source.memory, source.address, &destination.memory, destination.address, source.size);
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, fmt::Debug, sync::{Arc, Mutex}};
+
+use crate::mem::{Memory, MemorySliceTrait};
+
+pub const PAGE_SIZE: usize = 0x1000;
+
+type Page = Box<[u8; PAGE_SIZE]>;
+
+static ZERO_PAGE: [u8; PAGE_SIZE] = [0u8; PAGE_SIZE];
+
+// Pages are allocated on the first write that makes them non-zero,
+// untouched pages read back as zeros.
+pub struct SparseMemory {
+    size: usize,
+    pages: HashMap<usize, Page>,
+}
+
+impl SparseMemory {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            pages: HashMap::new(),
+        }
+    }
+
+    pub fn resident_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn resident_size(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+
+    pub fn release(&mut self) {
+        self.pages.clear();
+    }
+
+    fn page_range(at: usize, length: usize) -> Option<(usize, usize)> {
+        let offset = at % PAGE_SIZE;
+        if offset + length > PAGE_SIZE {
+            return None;
+        }
+        Some((at / PAGE_SIZE, offset))
+    }
+
+    fn page(&self, index: usize) -> Option<&Page> {
+        self.pages.get(&index)
+    }
+
+    fn page_mut(&mut self, index: usize) -> &mut Page {
+        self.pages.entry(index).or_insert_with(|| Box::new([0u8; PAGE_SIZE]))
+    }
+
+    fn chunks(at: usize, length: usize) -> impl Iterator<Item = (usize, usize, usize)> {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            if offset >= length {
+                return None;
+            }
+            let address = at + offset;
+            let page_offset = address % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - page_offset).min(length - offset);
+            let item = (address / PAGE_SIZE, page_offset, chunk);
+            offset += chunk;
+            Some(item)
+        })
+    }
+}
+
+impl MemorySliceTrait for SparseMemory {
+    fn len(&self) -> usize {
+        self.size
+    }
+
+    fn assert_access_range(&self, at: usize, length: usize) {
+        if at.checked_add(length).is_none_or(|end| end > self.size) {
+            panic!("Memory size is 0x{:04X}({}), but trying to access at 0x{at:04X}({at}) with 0x{length:04X}({length}) bytes", self.size, self.size);
+        }
+    }
+
+    fn read_u8(&self, at: usize) -> u8 {
+        self.assert_access_range(at, 1);
+        self.page(at / PAGE_SIZE).map_or(0, |page| page[at % PAGE_SIZE])
+    }
+
+    fn write_u8(&mut self, at: usize, value: u8) {
+        self.assert_access_range(at, 1);
+        if value == 0 && self.page(at / PAGE_SIZE).is_none() {
+            return;
+        }
+        self.page_mut(at / PAGE_SIZE)[at % PAGE_SIZE] = value;
+    }
+
+    fn read_bytes(&self, at: usize, length: usize) -> Vec<u8> {
+        self.assert_access_range(at, length);
+        let mut bytes = vec![0u8; length];
+        let mut offset = 0;
+        for (index, page_offset, chunk) in Self::chunks(at, length) {
+            if let Some(page) = self.page(index) {
+                bytes[offset..offset + chunk].copy_from_slice(&page[page_offset..page_offset + chunk]);
+            }
+            offset += chunk;
+        }
+        bytes
+    }
+
+    fn read_into(&self, at: usize, buffer: &mut [u8]) {
+        self.assert_access_range(at, buffer.len());
+        let mut offset = 0;
+        for (index, page_offset, chunk) in Self::chunks(at, buffer.len()) {
+            let target = &mut buffer[offset..offset + chunk];
+            match self.page(index) {
+                Some(page) => target.copy_from_slice(&page[page_offset..page_offset + chunk]),
+                None => target.fill(0),
+            }
+            offset += chunk;
+        }
+    }
+
+    fn write_bytes(&mut self, at: usize, bytes: &[u8]) {
+        self.assert_access_range(at, bytes.len());
+        let mut offset = 0;
+        for (index, page_offset, chunk) in Self::chunks(at, bytes.len()) {
+            let source = &bytes[offset..offset + chunk];
+            if self.page(index).is_some() || source.iter().any(|&byte| byte != 0) {
+                self.page_mut(index)[page_offset..page_offset + chunk].copy_from_slice(source);
+            }
+            offset += chunk;
+        }
+    }
+
+    // Zero fills leave absent pages alone and release the ones they cover
+    // completely.
+    fn fill(&mut self, at: usize, length: usize, value: u8) {
+        self.assert_access_range(at, length);
+        for (index, page_offset, chunk) in Self::chunks(at, length) {
+            if value == 0 && (chunk == PAGE_SIZE || self.page(index).is_none()) {
+                self.pages.remove(&index);
+                continue;
+            }
+            self.page_mut(index)[page_offset..page_offset + chunk].fill(value);
+        }
+    }
+
+    fn as_slice(&self, at: usize, length: usize) -> Option<&[u8]> {
+        self.assert_access_range(at, length);
+        let (index, offset) = Self::page_range(at, length)?;
+        let page = self.page(index).map_or(&ZERO_PAGE, |page| &**page);
+        Some(&page[offset..offset + length])
+    }
+
+    fn as_mut_slice(&mut self, at: usize, length: usize) -> Option<&mut [u8]> {
+        self.assert_access_range(at, length);
+        let (index, offset) = Self::page_range(at, length)?;
+        Some(&mut self.page_mut(index)[offset..offset + length])
+    }
+}
+
+impl Debug for SparseMemory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SparseMemory")
+            .field("size", &self.size)
+            .field("resident_pages", &self.pages.len())
+            .finish()
+    }
+}
+
+pub fn create_sparse_memory(size: usize) -> Memory {
+    Arc::new(Mutex::new(SparseMemory::new(size)))
+}
@@ -0,0 +1,360 @@
+use std::{cmp::Ordering, collections::BTreeMap, fmt::Write, sync::{Arc, Mutex}};
+
+use crate::mem::{hexdump, Memory, MemorySliceTrait};
+
+pub const ACCESS_WIDTHS: [usize; 5] = [1, 2, 4, 8, 16];
+pub const BULK_ACCESS: usize = 0;
+// Bytes per heatmap region of `create_instrumented_memory`.
+pub const DEFAULT_REGION_SIZE: usize = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Fetch,
+}
+
+impl AccessKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+            AccessKind::Fetch => "fetch",
+        }
+    }
+}
+
+// One slot per entry of `ACCESS_WIDTHS`, the last slot counts `BULK_ACCESS`
+// operations (`read_bytes`, `fill`, ...) of any length.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccessCounters {
+    pub reads: [u64; 6],
+    pub writes: [u64; 6],
+    pub fetches: [u64; 6],
+}
+
+impl AccessCounters {
+    fn slot(width: usize) -> usize {
+        ACCESS_WIDTHS.iter().position(|&x| x == width).unwrap_or(ACCESS_WIDTHS.len())
+    }
+
+    pub fn counts(&self, kind: AccessKind) -> &[u64; 6] {
+        match kind {
+            AccessKind::Read => &self.reads,
+            AccessKind::Write => &self.writes,
+            AccessKind::Fetch => &self.fetches,
+        }
+    }
+
+    fn counts_mut(&mut self, kind: AccessKind) -> &mut [u64; 6] {
+        match kind {
+            AccessKind::Read => &mut self.reads,
+            AccessKind::Write => &mut self.writes,
+            AccessKind::Fetch => &mut self.fetches,
+        }
+    }
+
+    pub fn count(&self, kind: AccessKind, width: usize) -> u64 {
+        self.counts(kind)[Self::slot(width)]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.reads.iter().chain(&self.writes).chain(&self.fetches).sum()
+    }
+}
+
+fn width_name(slot: usize) -> String {
+    match ACCESS_WIDTHS.get(slot) {
+        Some(width) => width.to_string(),
+        None => "bulk".to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryStats {
+    region_size: usize,
+    regions: BTreeMap<usize, AccessCounters>,
+}
+
+impl MemoryStats {
+    pub fn new(region_size: usize) -> Self {
+        if region_size == 0 {
+            panic!("Region size must be greater than zero");
+        }
+        Self {
+            region_size,
+            regions: BTreeMap::new(),
+        }
+    }
+
+    pub fn region_size(&self) -> usize {
+        self.region_size
+    }
+
+    pub fn record(&mut self, kind: AccessKind, at: usize, width: usize) {
+        let region = at - at % self.region_size;
+        self.regions.entry(region).or_default().counts_mut(kind)[AccessCounters::slot(width)] += 1;
+    }
+
+    // A bulk access counts once in every region it touches.
+    pub fn record_range(&mut self, kind: AccessKind, at: usize, length: usize) {
+        let first = at - at % self.region_size;
+        let last = at + length.max(1) - 1;
+        for region in (first..=last).step_by(self.region_size) {
+            self.regions.entry(region).or_default().counts_mut(kind)[AccessCounters::slot(BULK_ACCESS)] += 1;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.regions.clear();
+    }
+
+    pub fn region(&self, address: usize) -> Option<&AccessCounters> {
+        self.regions.get(&(address - address % self.region_size))
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = (usize, &AccessCounters)> {
+        self.regions.iter().map(|(address, counters)| (*address, counters))
+    }
+
+    pub fn total(&self) -> AccessCounters {
+        let mut total = AccessCounters::default();
+        for counters in self.regions.values() {
+            for kind in [AccessKind::Read, AccessKind::Write, AccessKind::Fetch] {
+                for (sum, count) in total.counts_mut(kind).iter_mut().zip(counters.counts(kind)) {
+                    *sum += count;
+                }
+            }
+        }
+        total
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut buf = String::from("address,kind,width,count\n");
+        for (address, counters) in self.regions() {
+            for kind in [AccessKind::Read, AccessKind::Write, AccessKind::Fetch] {
+                for (slot, count) in counters.counts(kind).iter().enumerate().filter(|(_, &count)| count > 0) {
+                    writeln!(buf, "0x{address:04X},{},{},{count}", kind.name(), width_name(slot)).unwrap();
+                }
+            }
+        }
+        buf
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut buf = format!("{{\"region_size\":{},\"regions\":[", self.region_size);
+        for (i, (address, counters)) in self.regions().enumerate() {
+            if i > 0 {
+                buf += ",";
+            }
+            write!(buf, "{{\"address\":{address}").unwrap();
+            for kind in [AccessKind::Read, AccessKind::Write, AccessKind::Fetch] {
+                let counts = counters.counts(kind).iter().enumerate()
+                    .map(|(slot, count)| format!("\"{}\":{count}", width_name(slot)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                write!(buf, ",\"{}\":{{{counts}}}", kind.name()).unwrap();
+            }
+            buf += "}";
+        }
+        buf += "]}";
+        buf
+    }
+
+    // Renders `length` bytes of address space starting at `at` in the hexdump
+    // layout, one cell per region, scaled so the hottest region reads `ff`.
+    pub fn heatmap(&self, at: usize, length: usize) -> String {
+        const RAMP: [char; 10] = [' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+        let first = at - at % self.region_size;
+        let totals = (first..at + length).step_by(self.region_size)
+            .map(|region| self.regions.get(&region).map_or(0, AccessCounters::total))
+            .collect::<Vec<_>>();
+        let hottest = totals.iter().copied().max().unwrap_or(0).max(1);
+        let cells = totals.iter()
+            .map(|&total| if total == 0 { 0 } else { (total * 254 / hottest + 1) as u8 })
+            .collect::<Vec<_>>();
+
+        let mut buf = format!("heatmap 0x{first:04X}..0x{:04X}, 0x{:04X} bytes per cell, hottest {hottest}\n", at + length, self.region_size);
+        buf += &hexdump(&cells, |cell| if cell == 0 { RAMP[0] } else { RAMP[1 + (cell as usize - 1) * (RAMP.len() - 2) / 254] });
+        buf
+    }
+}
+
+pub type SharedMemoryStats = Arc<Mutex<MemoryStats>>;
+
+macro_rules! impl_instrumented_read {
+    ( $type:ty, $name:ident, $kind:ident, $width:literal ) => {
+        fn $name(&self, at: usize) -> $type {
+            let value = self.inner.$name(at);
+            self.record(AccessKind::$kind, at, $width);
+            value
+        }
+    };
+}
+
+macro_rules! impl_instrumented_write {
+    ( $type:ty, $name:ident, $width:literal ) => {
+        fn $name(&mut self, at: usize, value: $type) {
+            self.inner.$name(at, value);
+            self.record(AccessKind::Write, at, $width);
+        }
+    };
+}
+
+#[derive(Debug)]
+pub struct InstrumentedMemory<M: MemorySliceTrait> {
+    inner: M,
+    stats: SharedMemoryStats,
+}
+
+impl<M: MemorySliceTrait> InstrumentedMemory<M> {
+    pub fn new(inner: M, region_size: usize) -> Self {
+        Self {
+            inner,
+            stats: Arc::new(Mutex::new(MemoryStats::new(region_size))),
+        }
+    }
+
+    pub fn stats(&self) -> SharedMemoryStats {
+        Arc::clone(&self.stats)
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    fn record(&self, kind: AccessKind, at: usize, width: usize) {
+        self.stats.lock().unwrap().record(kind, at, width);
+    }
+
+    fn record_range(&self, kind: AccessKind, at: usize, length: usize) {
+        self.stats.lock().unwrap().record_range(kind, at, length);
+    }
+}
+
+impl<M: MemorySliceTrait> MemorySliceTrait for InstrumentedMemory<M> {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn assert_access_range(&self, at: usize, length: usize) {
+        self.inner.assert_access_range(at, length);
+    }
+
+    impl_instrumented_read!(u8, read_u8, Read, 1);
+    impl_instrumented_read!(i8, read_i8, Read, 1);
+    impl_instrumented_write!(u8, write_u8, 1);
+    impl_instrumented_write!(i8, write_i8, 1);
+
+    impl_instrumented_read!(u16, read_u16, Read, 2);
+    impl_instrumented_read!(i16, read_i16, Read, 2);
+    impl_instrumented_write!(u16, write_u16, 2);
+    impl_instrumented_write!(i16, write_i16, 2);
+
+    impl_instrumented_read!(u32, read_u32, Read, 4);
+    impl_instrumented_read!(i32, read_i32, Read, 4);
+    impl_instrumented_write!(u32, write_u32, 4);
+    impl_instrumented_write!(i32, write_i32, 4);
+
+    impl_instrumented_read!(u64, read_u64, Read, 8);
+    impl_instrumented_read!(i64, read_i64, Read, 8);
+    impl_instrumented_write!(u64, write_u64, 8);
+    impl_instrumented_write!(i64, write_i64, 8);
+
+    impl_instrumented_read!(u128, read_u128, Read, 16);
+    impl_instrumented_read!(i128, read_i128, Read, 16);
+    impl_instrumented_write!(u128, write_u128, 16);
+    impl_instrumented_write!(i128, write_i128, 16);
+
+    impl_instrumented_read!(f32, read_f32, Read, 4);
+    impl_instrumented_write!(f32, write_f32, 4);
+    impl_instrumented_read!(f64, read_f64, Read, 8);
+    impl_instrumented_write!(f64, write_f64, 8);
+
+    impl_instrumented_read!(u8, fetch_u8, Fetch, 1);
+    impl_instrumented_read!(u16, fetch_u16, Fetch, 2);
+    impl_instrumented_read!(u32, fetch_u32, Fetch, 4);
+    impl_instrumented_read!(u64, fetch_u64, Fetch, 8);
+
+    fn read_bytes(&self, at: usize, length: usize) -> Vec<u8> {
+        let bytes = self.inner.read_bytes(at, length);
+        self.record_range(AccessKind::Read, at, length);
+        bytes
+    }
+
+    fn read_into(&self, at: usize, buffer: &mut [u8]) {
+        self.inner.read_into(at, buffer);
+        self.record_range(AccessKind::Read, at, buffer.len());
+    }
+
+    fn write_bytes(&mut self, at: usize, bytes: &[u8]) {
+        self.inner.write_bytes(at, bytes);
+        self.record_range(AccessKind::Write, at, bytes.len());
+    }
+
+    fn read_string(&self, at: usize, length: usize) -> String {
+        let string = self.inner.read_string(at, length);
+        self.record_range(AccessKind::Read, at, length);
+        string
+    }
+
+    fn write_string(&mut self, at: usize, value: &str) {
+        self.inner.write_string(at, value);
+        self.record_range(AccessKind::Write, at, value.len());
+    }
+
+    fn fetch_bytes(&self, at: usize, length: usize) -> Vec<u8> {
+        let bytes = self.inner.fetch_bytes(at, length);
+        self.record_range(AccessKind::Fetch, at, length);
+        bytes
+    }
+
+    // Lending a slice isn't an access, views borrow one every time they're
+    // dereferenced.
+    fn as_slice(&self, at: usize, length: usize) -> Option<&[u8]> {
+        self.inner.as_slice(at, length)
+    }
+
+    fn as_mut_slice(&mut self, at: usize, length: usize) -> Option<&mut [u8]> {
+        self.inner.as_mut_slice(at, length)
+    }
+
+    fn fill(&mut self, at: usize, length: usize, value: u8) {
+        self.inner.fill(at, length, value);
+        self.record_range(AccessKind::Write, at, length);
+    }
+
+    fn copy_within(&mut self, from: usize, to: usize, length: usize) {
+        self.inner.copy_within(from, to, length);
+        self.record_range(AccessKind::Read, from, length);
+        self.record_range(AccessKind::Write, to, length);
+    }
+
+    fn compare(&self, at: usize, other: usize, length: usize) -> Ordering {
+        let ordering = self.inner.compare(at, other, length);
+        self.record_range(AccessKind::Read, at, length);
+        self.record_range(AccessKind::Read, other, length);
+        ordering
+    }
+
+    fn compare_bytes(&self, at: usize, bytes: &[u8]) -> Ordering {
+        let ordering = self.inner.compare_bytes(at, bytes);
+        self.record_range(AccessKind::Read, at, bytes.len());
+        ordering
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn display(&self, at: usize, length: usize) -> String {
+        self.inner.display(at, length)
+    }
+}
+
+pub fn create_instrumented_memory<M: MemorySliceTrait + Send + 'static>(memory: M) -> (Memory, SharedMemoryStats) {
+    let memory = InstrumentedMemory::new(memory, DEFAULT_REGION_SIZE);
+    let stats = memory.stats();
+    (Arc::new(Mutex::new(memory)), stats)
+}
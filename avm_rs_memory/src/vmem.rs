@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use crate::{access_memory, mem::{Memory, MemorySliceTrait}, pointer::Pointer, share_memory};
+use crate::{access_memory, mem::Memory, pointer::Pointer, share_memory};
 
 #[derive(Debug)]
 pub struct MappedMemory {
@@ -19,22 +19,22 @@ impl VirtualMemory {
     pub fn new(memory: Memory) -> Self {
         Self {
             mapped_memory: Vec::new(),
-            memory: memory,
+            memory,
             last_address: 0,
         }
     }
 
-    fn find_fit(&mut self, size: usize) -> Option<usize> {
-        let address = self.last_address;
-        if address + size > access_memory!(self.memory).len() {
+    fn find_fit(&mut self, size: usize, alignment: usize) -> Option<usize> {
+        let address = self.last_address.div_ceil(alignment).checked_mul(alignment)?;
+        if address.checked_add(size)? > access_memory!(self.memory).len() {
             return None;
         }
         self.last_address = address + size;
         Some(address)
     }
 
-    fn find_mapped_memory(&self, address: usize) -> Option<&MappedMemory> {
-        self.mapped_memory.iter().find(|x| x.address <= address && x.address + x.size > address)
+    fn find_mapped_memory(&self, address: usize) -> Option<usize> {
+        self.mapped_memory.iter().position(|x| x.address <= address && x.address + x.size > address)
     }
 
     fn map(&mut self, address: usize, size: usize) -> usize {
@@ -47,12 +47,12 @@ impl VirtualMemory {
     }
 
     pub fn allocate(&mut self, size: usize) -> Pointer {
-        let address = self.find_fit(size);
-        match address {
-            Some(address) => {
-                self.map(address, size);
-                Pointer::new(share_memory!(self.memory), address, size)
-            },
+        self.allocate_aligned(size, 1)
+    }
+
+    pub fn allocate_aligned(&mut self, size: usize, alignment: usize) -> Pointer {
+        match self.try_allocate_aligned(size, alignment) {
+            Some(pointer) => pointer,
             None => {
                 let left_memory = access_memory!(self.memory).len() - self.last_address;
                 panic!("Out of memory, memory left is 0x{left_memory:04X}({left_memory}) but trying to allocate 0x{size:04X}({size})");
@@ -60,9 +60,19 @@ impl VirtualMemory {
         }
     }
 
+    // Like `allocate_aligned`, but None when the memory left is too small.
+    pub fn try_allocate_aligned(&mut self, size: usize, alignment: usize) -> Option<Pointer> {
+        if !alignment.is_power_of_two() {
+            panic!("Alignment must be a power of two, but is {alignment}");
+        }
+        let address = self.find_fit(size, alignment)?;
+        self.map(address, size);
+        Some(Pointer::new(share_memory!(self.memory), address, size))
+    }
+
     pub fn deallocate(&mut self, address: usize) {
-        if let Some(mapped_memory) = self.find_mapped_memory(address) {
-            self.unmap(mapped_memory.address);
+        if let Some(index) = self.find_mapped_memory(address) {
+            self.unmap(index);
             return;
         }
         panic!("Unmapped address: 0x{address:04X}");
@@ -79,7 +89,7 @@ impl Display for VirtualMemory {
         for i in 0..self.mapped_memory.len() {
             write!(f, "address: 0x{:04X}, size: 0x{:04X}", self.mapped_memory[i].address, self.mapped_memory[i].address + self.mapped_memory[i].size)?;
             if i < self.mapped_memory.len() - 1 {
-                write!(f, "\n")?;
+                writeln!(f)?;
             }
         }
         Ok(())
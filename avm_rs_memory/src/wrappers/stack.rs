@@ -1,4 +1,4 @@
-use crate::{mem::{Memory, MemorySliceTrait}, pointer::Pointer};
+use crate::{mem::MemorySliceTrait, pointer::Pointer};
 
 macro_rules! impl_stack_push {
     ( $type:ident, $name:ident, $write:ident, $width:literal ) => {
@@ -27,7 +27,7 @@ pub struct Stack {
 impl Stack {
     pub fn new(pointer: Pointer) -> Self {
         Self {
-            pointer: pointer,
+            pointer,
             top: 0,
         }
     }
@@ -36,6 +36,10 @@ impl Stack {
         self.top = 0;
     }
 
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
     pub fn set_top(&mut self, top: usize) {
         if self.top > self.pointer.size {
             panic!("Stack overflow");
@@ -74,7 +78,7 @@ impl Stack {
 
     pub fn pop_bytes(&mut self, length: usize) -> Vec<u8> {
         self.top -= length;
-        self.pointer.read_bytes(self.top, length).to_vec()
+        self.pointer.read_bytes(self.top, length)
     }
 
     pub fn push_string(&mut self, string: &str) {
@@ -0,0 +1,1134 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, fmt::Display};
+
+use avm_rs_memory::{access_memory, mem::Memory};
+
+use crate::image::RelocationKind;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for AssemblerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AssemblerError {}
+
+// An instruction operand as written in the source. `#expr` is an
+// `Immediate`, a bare expression is an `Address`, which targets may encode
+// relative to the instruction, as branches do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(u8),
+    FloatRegister(u8),
+    Immediate(i64),
+    Address(i64),
+}
+
+// Where a relocatable operand was encoded, `offset` bytes into the
+// instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperandField {
+    pub operand: usize,
+    pub offset: usize,
+    pub kind: RelocationKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Encoded {
+    pub bytes: Vec<u8>,
+    pub fields: Vec<OperandField>,
+}
+
+pub trait AssemblerTarget {
+    // Encodes one instruction placed at `address`. Before the last pass,
+    // symbols that aren't defined yet evaluate to `address`, and so do
+    // imported ones. Operands flagged in `relocatable` are addresses that are
+    // only known after linking or loading, the encoding must report a field
+    // for each of them.
+    fn encode(&self, mnemonic: &str, operands: &[Operand], relocatable: &[bool], address: u64) -> Result<Encoded, String>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Identifier(String),
+    Number(i64),
+    String(Vec<u8>),
+    Punct(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+type LineError = (usize, String);
+
+const PUNCTUATION: &[&str] = &["<<", ">>", ",", ":", "#", "(", ")", "+", "-", "*", "/", "%", "&", "|", "^", "~", "="];
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '$'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+fn unescape(chars: &[char], position: &mut usize, column: usize) -> Result<u8, LineError> {
+    let c = chars[*position];
+    *position += 1;
+    if c != '\\' {
+        if !c.is_ascii() {
+            return Err((column, format!("Non-ASCII character '{c}'")));
+        }
+        return Ok(c as u8);
+    }
+    let Some(&escape) = chars.get(*position) else {
+        return Err((column, "Unterminated escape".to_string()));
+    };
+    *position += 1;
+    Ok(match escape {
+        'n' => b'\n',
+        't' => b'\t',
+        'r' => b'\r',
+        '0' => 0,
+        '\\' => b'\\',
+        '\'' => b'\'',
+        '"' => b'"',
+        'x' => {
+            let digits: String = chars.iter().skip(*position).take(2).collect();
+            *position += 2;
+            u8::from_str_radix(&digits, 16).map_err(|_| (column, format!("Invalid escape \\x{digits}")))?
+        }
+        other => return Err((column, format!("Unknown escape \\{other}"))),
+    })
+}
+
+// Splits one line into tokens, dropping `;` and `//` comments. Columns are
+// 1-based.
+fn tokenize(line: &str) -> Result<Vec<Token>, LineError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while position < chars.len() {
+        let c = chars[position];
+        let column = position + 1;
+        if c.is_whitespace() {
+            position += 1;
+        } else if c == ';' || (c == '/' && chars.get(position + 1) == Some(&'/')) {
+            break;
+        } else if is_identifier_start(c) {
+            let start = position;
+            while position < chars.len() && is_identifier_char(chars[position]) {
+                position += 1;
+            }
+            tokens.push(Token { kind: TokenKind::Identifier(chars[start..position].iter().collect()), column });
+        } else if c.is_ascii_digit() {
+            let start = position;
+            while position < chars.len() && (chars[position].is_ascii_alphanumeric() || chars[position] == '_') {
+                position += 1;
+            }
+            let text: String = chars[start..position].iter().filter(|&&c| c != '_').collect();
+            let lower = text.to_ascii_lowercase();
+            let parsed = if let Some(digits) = lower.strip_prefix("0x") {
+                u64::from_str_radix(digits, 16)
+            } else if let Some(digits) = lower.strip_prefix("0b") {
+                u64::from_str_radix(digits, 2)
+            } else if let Some(digits) = lower.strip_prefix("0o") {
+                u64::from_str_radix(digits, 8)
+            } else {
+                lower.parse::<u64>()
+            };
+            let value = parsed.map_err(|_| (column, format!("Invalid number '{text}'")))?;
+            tokens.push(Token { kind: TokenKind::Number(value as i64), column });
+        } else if c == '\'' {
+            position += 1;
+            if position >= chars.len() {
+                return Err((column, "Unterminated character literal".to_string()));
+            }
+            let value = unescape(&chars, &mut position, column)?;
+            if chars.get(position) != Some(&'\'') {
+                return Err((column, "Unterminated character literal".to_string()));
+            }
+            position += 1;
+            tokens.push(Token { kind: TokenKind::Number(value as i64), column });
+        } else if c == '"' {
+            position += 1;
+            let mut bytes = Vec::new();
+            loop {
+                match chars.get(position) {
+                    None => return Err((column, "Unterminated string".to_string())),
+                    Some('"') => break,
+                    Some(_) => bytes.push(unescape(&chars, &mut position, column)?),
+                }
+            }
+            position += 1;
+            tokens.push(Token { kind: TokenKind::String(bytes), column });
+        } else {
+            let rest: String = chars[position..chars.len().min(position + 2)].iter().collect();
+            let Some(punct) = PUNCTUATION.iter().find(|p| rest.starts_with(**p)) else {
+                return Err((column, format!("Unexpected character '{c}'")));
+            };
+            position += punct.len();
+            tokens.push(Token { kind: TokenKind::Punct(punct), column });
+        }
+    }
+    Ok(tokens)
+}
+
+// Splits on commas outside parentheses.
+fn split_arguments(tokens: &[Token]) -> Vec<&[Token]> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    let mut arguments = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Punct("(") => depth += 1,
+            TokenKind::Punct(")") => depth -= 1,
+            TokenKind::Punct(",") if depth == 0 => {
+                arguments.push(&tokens[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    arguments.push(&tokens[start..]);
+    arguments
+}
+
+fn parse_register(name: &str) -> Option<Operand> {
+    let (prefix, number) = name.split_at_checked(1)?;
+    let number: u8 = number.parse().ok()?;
+    if number.to_string() != name[1..] {
+        return None;
+    }
+    match prefix {
+        "r" | "R" => Some(Operand::Register(number)),
+        "f" | "F" => Some(Operand::FloatRegister(number)),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+enum StatementKind {
+    Instruction { mnemonic: String, operands: Vec<Vec<Token>> },
+    Directive { name: String, arguments: Vec<Vec<Token>> },
+    Assignment { name: String, expression: Vec<Token> },
+}
+
+#[derive(Debug)]
+struct Statement {
+    line: usize,
+    column: usize,
+    labels: Vec<(String, usize)>,
+    kind: Option<StatementKind>,
+}
+
+fn parse_line(line: usize, text: &str) -> Result<Statement, LineError> {
+    let tokens = tokenize(text)?;
+    let mut labels = Vec::new();
+    let mut rest = &tokens[..];
+    while let [Token { kind: TokenKind::Identifier(name), column }, Token { kind: TokenKind::Punct(":"), .. }, tail @ ..] = rest {
+        labels.push((name.clone(), *column));
+        rest = tail;
+    }
+    let column = rest.first().map_or(1, |token| token.column);
+    let kind = match rest {
+        [] => None,
+        [Token { kind: TokenKind::Identifier(name), .. }, Token { kind: TokenKind::Punct("="), .. }, expression @ ..] => Some(StatementKind::Assignment {
+            name: name.clone(),
+            expression: expression.to_vec(),
+        }),
+        [Token { kind: TokenKind::Identifier(name), .. }, arguments @ ..] if name.starts_with('.') => Some(StatementKind::Directive {
+            name: name.to_ascii_lowercase(),
+            arguments: split_arguments(arguments).into_iter().map(|x| x.to_vec()).collect(),
+        }),
+        [Token { kind: TokenKind::Identifier(name), .. }, operands @ ..] => Some(StatementKind::Instruction {
+            mnemonic: name.to_ascii_lowercase(),
+            operands: split_arguments(operands).into_iter().map(|x| x.to_vec()).collect(),
+        }),
+        [token, ..] => return Err((token.column, "Expected a label, instruction or directive".to_string())),
+    };
+    Ok(Statement { line, column, labels, kind })
+}
+
+// What a relocatable value is relative to: a section of this program, or an
+// imported symbol by its index in the `.extern` declarations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Base {
+    Section(usize),
+    External(usize),
+}
+
+// The value of an expression. `base` is set when it's an address that moves
+// with a section or comes from another object, `value` is then the address
+// for this program's layout, or the offset from an imported symbol. Only
+// adding or subtracting a constant keeps an address relocatable, anything
+// else makes it a plain number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Value {
+    value: i64,
+    base: Option<Base>,
+}
+
+impl Value {
+    fn absolute(value: i64) -> Self {
+        Self { value, base: None }
+    }
+}
+
+struct Context<'a> {
+    previous: &'a HashMap<String, Value>,
+    current: &'a HashMap<String, Value>,
+    externs: &'a [String],
+    address: i64,
+    // How far into `section` the address is.
+    offset: u64,
+    section: usize,
+    scope: &'a str,
+    last_pass: bool,
+}
+
+impl Context<'_> {
+    // Labels starting with a dot are local to the last label without one.
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('.') && name.len() > 1 {
+            format!("{}{name}", self.scope)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn here(&self) -> Value {
+        Value { value: self.address, base: Some(Base::Section(self.section)) }
+    }
+
+    fn lookup(&self, name: &str, column: usize) -> Result<Value, LineError> {
+        if name == "." {
+            return Ok(self.here());
+        }
+        let name = self.qualify(name);
+        if let Some(value) = self.current.get(&name).or_else(|| self.previous.get(&name)) {
+            return Ok(*value);
+        }
+        if let Some(index) = self.externs.iter().position(|x| *x == name) {
+            return Ok(Value { value: 0, base: Some(Base::External(index)) });
+        }
+        if self.last_pass {
+            return Err((column, format!("Undefined symbol '{name}'")));
+        }
+        Ok(self.here())
+    }
+}
+
+// Precedence climbing over `| ^ & << >> + - * / %` and unary `- ~ +`.
+struct Evaluator<'a, 'b> {
+    tokens: &'a [Token],
+    position: usize,
+    context: &'a Context<'b>,
+}
+
+const BINARY_OPERATORS: &[&[&str]] = &[&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+impl Evaluator<'_, '_> {
+    fn end_column(&self) -> usize {
+        self.tokens.last().map_or(1, |token| token.column + 1)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Value, LineError> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(Token { kind: TokenKind::Punct(op), column }) = self.tokens.get(self.position) {
+            if !BINARY_OPERATORS[level].contains(op) {
+                break;
+            }
+            let (op, column) = (*op, *column);
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            let base = match (op, value.base, right.base) {
+                ("+", base, None) | ("+", None, base) | ("-", base, None) => base,
+                _ => None,
+            };
+            let (left, right) = (value.value, right.value);
+            let result = match op {
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                _ if right == 0 => return Err((column, "Division by zero".to_string())),
+                "/" => left.wrapping_div(right),
+                _ => left.wrapping_rem(right),
+            };
+            value = Value { value: result, base };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<Value, LineError> {
+        match self.tokens.get(self.position).map(|token| &token.kind) {
+            Some(TokenKind::Punct("-")) => {
+                self.position += 1;
+                Ok(Value::absolute(self.unary()?.value.wrapping_neg()))
+            }
+            Some(TokenKind::Punct("~")) => {
+                self.position += 1;
+                Ok(Value::absolute(!self.unary()?.value))
+            }
+            Some(TokenKind::Punct("+")) => {
+                self.position += 1;
+                self.unary()
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Value, LineError> {
+        let Some(token) = self.tokens.get(self.position) else {
+            return Err((self.end_column(), "Expected an expression".to_string()));
+        };
+        self.position += 1;
+        match &token.kind {
+            TokenKind::Number(value) => Ok(Value::absolute(*value)),
+            TokenKind::Identifier(name) => self.context.lookup(name, token.column),
+            TokenKind::Punct("(") => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.position) {
+                    Some(Token { kind: TokenKind::Punct(")"), .. }) => {
+                        self.position += 1;
+                        Ok(value)
+                    }
+                    Some(token) => Err((token.column, "Expected ')'".to_string())),
+                    None => Err((self.end_column(), "Expected ')'".to_string())),
+                }
+            }
+            _ => Err((token.column, "Expected an expression".to_string())),
+        }
+    }
+}
+
+fn evaluate(tokens: &[Token], context: &Context, column: usize) -> Result<Value, LineError> {
+    if tokens.is_empty() {
+        return Err((column, "Expected an expression".to_string()));
+    }
+    let mut evaluator = Evaluator { tokens, position: 0, context };
+    let value = evaluator.binary(0)?;
+    if let Some(token) = tokens.get(evaluator.position) {
+        return Err((token.column, "Unexpected token after expression".to_string()));
+    }
+    Ok(value)
+}
+
+fn parse_operand(tokens: &[Token], context: &Context, column: usize) -> Result<(Operand, Value), LineError> {
+    match tokens {
+        [Token { kind: TokenKind::Identifier(name), .. }] if parse_register(name).is_some() => Ok((parse_register(name).unwrap(), Value::absolute(0))),
+        [Token { kind: TokenKind::Punct("#"), column }, expression @ ..] => {
+            let value = evaluate(expression, context, column + 1)?;
+            Ok((Operand::Immediate(value.value), value))
+        }
+        _ => {
+            let value = evaluate(tokens, context, column)?;
+            Ok((Operand::Address(value.value), value))
+        }
+    }
+}
+
+// The symbol names a `.global` or `.extern` statement lists.
+fn declared_names(arguments: &[Vec<Token>]) -> impl Iterator<Item = &str> {
+    arguments.iter().filter_map(|argument| match &argument[..] {
+        [Token { kind: TokenKind::Identifier(name), .. }] if !name.starts_with('.') => Some(name.as_str()),
+        _ => None,
+    })
+}
+
+fn fits(value: i64, width: usize) -> bool {
+    width >= 8 || (value >= -(1i64 << (width * 8 - 1)) && value < (1i64 << (width * 8)))
+}
+
+pub const TEXT: usize = 0;
+pub const DATA: usize = 1;
+pub const BSS: usize = 2;
+const SECTION_NAMES: [&str; 3] = [".text", ".data", ".bss"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembledSection {
+    pub name: &'static str,
+    pub address: u64,
+    pub size: u64,
+    // Empty for `.bss`, `size` bytes otherwise.
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelocationTarget {
+    Section(usize),
+    External(String),
+}
+
+// A field `offset` bytes into `section` holding the address `addend` bytes
+// past `target`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembledRelocation {
+    pub section: usize,
+    pub offset: u64,
+    pub kind: RelocationKind,
+    pub target: RelocationTarget,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembledProgram {
+    pub sections: Vec<AssembledSection>,
+    pub symbols: BTreeMap<String, u64>,
+    // The section of every symbol that is an address.
+    pub symbol_sections: BTreeMap<String, usize>,
+    // Symbols exported with `.global` and imported with `.extern`.
+    pub globals: BTreeSet<String>,
+    pub externs: Vec<String>,
+    pub relocations: Vec<AssembledRelocation>,
+    // Address of every instruction and data statement with its source line.
+    pub lines: Vec<(u64, usize)>,
+}
+
+impl AssembledProgram {
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn start(&self) -> u64 {
+        self.sections.iter().filter(|x| x.size > 0).map(|x| x.address).min().unwrap_or(0)
+    }
+
+    pub fn end(&self) -> u64 {
+        self.sections.iter().map(|x| x.address + x.size).max().unwrap_or(0)
+    }
+
+    // Flat binary of the sections with contents, gaps between them are
+    // zeros. `.bss` is left to whoever loads it.
+    pub fn image(&self) -> (u64, Vec<u8>) {
+        let contents = || self.sections.iter().filter(|x| !x.bytes.is_empty());
+        let start = contents().map(|x| x.address).min().unwrap_or(0);
+        let end = contents().map(|x| x.address + x.size).max().unwrap_or(0);
+        let mut image = vec![0u8; (end.saturating_sub(start)) as usize];
+        for section in contents() {
+            let at = (section.address - start) as usize;
+            image[at..at + section.bytes.len()].copy_from_slice(&section.bytes);
+        }
+        (start, image)
+    }
+
+    pub fn write_to(&self, memory: &Memory) {
+        let mut memory = access_memory!(memory);
+        for section in self.sections.iter().filter(|x| x.size > 0) {
+            match section.bytes.is_empty() {
+                true => memory.fill(section.address as usize, section.size as usize, 0),
+                false => memory.write_bytes(section.address as usize, &section.bytes),
+            }
+        }
+    }
+}
+
+// What a statement does to the pass besides emitting `bytes` followed by
+// `zeros` zero bytes. Relocations are an offset into `bytes`, the kind of
+// field and the address it holds.
+#[derive(Debug, Default)]
+struct Effect {
+    bytes: Vec<u8>,
+    zeros: u64,
+    relocations: Vec<(usize, RelocationKind, Value)>,
+    assignment: Option<(String, Value)>,
+    section: Option<usize>,
+    data_base: Option<u64>,
+}
+
+impl Effect {
+    fn bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes, ..Default::default() }
+    }
+
+    fn zeros(zeros: u64) -> Self {
+        Self { zeros, ..Default::default() }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pass {
+    sections: [Vec<u8>; 3],
+    offsets: [u64; 3],
+    symbols: HashMap<String, Value>,
+    relocations: Vec<AssembledRelocation>,
+    lines: Vec<(u64, usize)>,
+    data_base: Option<u64>,
+    // Line and column of the `.data` statement that set `data_base`.
+    data_statement: Option<(usize, usize)>,
+    errors: Vec<AssemblerError>,
+}
+
+impl Pass {
+    fn emit(&mut self, section: usize, effect: &Effect, bases: &[u64; 3], externs: &[String]) {
+        let at = self.offsets[section];
+        for &(offset, kind, value) in &effect.relocations {
+            let (target, addend) = match value.base {
+                Some(Base::Section(target)) => (RelocationTarget::Section(target), value.value.wrapping_sub(bases[target] as i64)),
+                Some(Base::External(index)) => (RelocationTarget::External(externs[index].clone()), value.value),
+                None => continue,
+            };
+            self.relocations.push(AssembledRelocation { section, offset: at + offset as u64, kind, target, addend });
+        }
+        let length = effect.bytes.len() as u64 + effect.zeros;
+        // `.bss` only counts its size.
+        if section != BSS {
+            let (at, bytes) = (at as usize, &effect.bytes);
+            let buffer = &mut self.sections[section];
+            buffer.resize(buffer.len().max(at + length as usize), 0);
+            buffer[at..at + bytes.len()].copy_from_slice(bytes);
+        }
+        self.offsets[section] += length;
+    }
+}
+
+// Three sections, `.text` at the origin, then `.data` and `.bss` each aligned
+// to 16 bytes, unless `.data` is given an address. `.bss` only reserves zeros.
+// Every pass lays out the whole source with the symbol values of the pass
+// before, until nothing moves; only the last pass reports errors.
+//
+// Symbols named by `.extern` come from other objects and are left to the
+// linker, `.global` exports symbols to them.
+pub struct Assembler<T: AssemblerTarget> {
+    target: T,
+    origin: u64,
+}
+
+const MAX_PASSES: usize = 16;
+
+// Sections with contents are built in memory, this keeps a stray `.space` or
+// `.org` from asking for more. `.bss` only counts its size and can reach the
+// end of the address space.
+const MAX_SECTION_SIZE: u64 = 1 << 32;
+
+impl<T: AssemblerTarget> Assembler<T> {
+    pub fn new(target: T) -> Self {
+        Self { target, origin: 0 }
+    }
+
+    pub fn with_origin(mut self, origin: u64) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn assemble(&self, source: &str) -> Result<AssembledProgram, Vec<AssemblerError>> {
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+        for (index, text) in source.lines().enumerate() {
+            match parse_line(index + 1, text) {
+                Ok(statement) => statements.push(statement),
+                Err((column, message)) => errors.push(AssemblerError { line: index + 1, column, message }),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut globals = Vec::new();
+        let mut externs = Vec::new();
+        for statement in &statements {
+            match &statement.kind {
+                Some(StatementKind::Directive { name, arguments }) if name == ".global" => {
+                    globals.extend(declared_names(arguments).map(|name| (name.to_string(), statement.line, statement.column)));
+                }
+                Some(StatementKind::Directive { name, arguments }) if name == ".extern" => {
+                    externs.extend(declared_names(arguments).map(|name| name.to_string()).filter(|name| !externs.contains(name)).collect::<Vec<_>>());
+                }
+                _ => {}
+            }
+        }
+
+        let mut symbols = HashMap::new();
+        let mut bases = [self.origin; 3];
+        for _ in 0..MAX_PASSES {
+            let pass = self.pass(&statements, bases, &symbols, &externs, false);
+            let next = self.bases(&pass);
+            if pass.symbols == symbols && next == bases {
+                let mut pass = self.pass(&statements, bases, &symbols, &externs, true);
+                for (name, line, column) in &globals {
+                    if !pass.symbols.contains_key(name) {
+                        pass.errors.push(AssemblerError { line: *line, column: *column, message: format!("Global symbol '{name}' is never defined") });
+                    }
+                }
+                pass.errors.extend(Self::overlap(&pass, bases));
+                if !pass.errors.is_empty() {
+                    return Err(pass.errors);
+                }
+                let mut program = self.program(pass, bases);
+                program.globals = globals.into_iter().map(|(name, ..)| name).collect();
+                program.externs = externs.into_iter().filter(|name| !program.symbols.contains_key(name)).collect();
+                return Ok(program);
+            }
+            symbols = pass.symbols;
+            bases = next;
+        }
+        Err(vec![AssemblerError { line: 1, column: 1, message: format!("Symbol addresses didn't settle after {MAX_PASSES} passes") }])
+    }
+
+    pub fn assemble_into(&self, source: &str, memory: &Memory) -> Result<AssembledProgram, Vec<AssemblerError>> {
+        let program = self.assemble(source)?;
+        program.write_to(memory);
+        Ok(program)
+    }
+
+    fn bases(&self, pass: &Pass) -> [u64; 3] {
+        let text_end = self.origin + pass.offsets[TEXT];
+        let data = pass.data_base.unwrap_or(text_end.div_ceil(16) * 16);
+        let data_end = data + pass.offsets[DATA];
+        [self.origin, data, data_end.div_ceil(16) * 16]
+    }
+
+    // Only `.data` given an address can land on another section, `.bss`
+    // follows it there.
+    fn overlap(pass: &Pass, bases: [u64; 3]) -> Option<AssemblerError> {
+        let (line, column) = pass.data_statement?;
+        let ranges: Vec<_> = (0..3).filter(|&x| pass.offsets[x] > 0).map(|x| (x, bases[x], bases[x].saturating_add(pass.offsets[x]))).collect();
+        for (index, &(first, start, end)) in ranges.iter().enumerate() {
+            if let Some(&(second, ..)) = ranges[index + 1..].iter().find(|&&(_, other_start, other_end)| start < other_end && other_start < end) {
+                return Some(AssemblerError { line, column, message: format!("Sections {} and {} overlap", SECTION_NAMES[first], SECTION_NAMES[second]) });
+            }
+        }
+        None
+    }
+
+    fn program(&self, pass: Pass, bases: [u64; 3]) -> AssembledProgram {
+        let sections = pass.sections.into_iter().enumerate()
+            .map(|(index, bytes)| AssembledSection { name: SECTION_NAMES[index], address: bases[index], size: pass.offsets[index], bytes })
+            .collect();
+        AssembledProgram {
+            sections,
+            symbol_sections: pass.symbols.iter().filter_map(|(name, value)| match value.base {
+                Some(Base::Section(section)) => Some((name.clone(), section)),
+                _ => None,
+            }).collect(),
+            symbols: pass.symbols.into_iter().map(|(name, value)| (name, value.value as u64)).collect(),
+            globals: BTreeSet::new(),
+            externs: Vec::new(),
+            relocations: pass.relocations,
+            lines: pass.lines,
+        }
+    }
+
+    fn pass(&self, statements: &[Statement], bases: [u64; 3], previous: &HashMap<String, Value>, externs: &[String], last_pass: bool) -> Pass {
+        let mut pass = Pass::default();
+        let mut section = TEXT;
+        let mut scope = String::new();
+        for statement in statements {
+            let address = bases[section] + pass.offsets[section];
+            for (label, column) in &statement.labels {
+                if !label.starts_with('.') {
+                    scope = label.clone();
+                }
+                let context = Context { previous, current: &pass.symbols, externs, address: address as i64, offset: pass.offsets[section], section, scope: &scope, last_pass };
+                let name = context.qualify(label);
+                if pass.symbols.insert(name.clone(), context.here()).is_some() {
+                    pass.errors.push(AssemblerError { line: statement.line, column: *column, message: format!("Symbol '{name}' is already defined") });
+                }
+            }
+            let Some(kind) = &statement.kind else {
+                continue;
+            };
+            let context = Context { previous, current: &pass.symbols, externs, address: address as i64, offset: pass.offsets[section], section, scope: &scope, last_pass };
+            let result = match kind {
+                StatementKind::Assignment { name, expression } => evaluate(expression, &context, statement.column).map(|value| Effect {
+                    assignment: Some((context.qualify(name), value)),
+                    ..Default::default()
+                }),
+                StatementKind::Instruction { .. } if section == BSS => Err((statement.column, "Instructions can't go in .bss".to_string())),
+                StatementKind::Instruction { mnemonic, operands } => self.instruction(mnemonic, operands, &context, statement.column),
+                StatementKind::Directive { name, arguments } => self.directive(name, arguments, &context, statement.column),
+            };
+            let result = result.and_then(|effect| match section == BSS && (effect.bytes.iter().any(|&x| x != 0) || !effect.relocations.is_empty()) {
+                true => Err((statement.column, ".bss can only hold zeros".to_string())),
+                false => Ok(effect),
+            });
+            match result {
+                Ok(mut effect) => {
+                    if let Some((name, value)) = effect.assignment.take() {
+                        if pass.symbols.insert(name.clone(), value).is_some() {
+                            pass.errors.push(AssemblerError { line: statement.line, column: statement.column, message: format!("Symbol '{name}' is already defined") });
+                        }
+                    }
+                    if effect.data_base.is_some() {
+                        pass.data_base = effect.data_base;
+                        pass.data_statement = Some((statement.line, statement.column));
+                    }
+                    if !effect.bytes.is_empty() || effect.zeros > 0 {
+                        pass.lines.push((bases[section] + pass.offsets[section], statement.line));
+                        pass.emit(section, &effect, &bases, externs);
+                    }
+                    section = effect.section.unwrap_or(section);
+                }
+                Err((column, message)) => pass.errors.push(AssemblerError { line: statement.line, column, message }),
+            }
+        }
+        pass
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Vec<Token>], context: &Context, column: usize) -> Result<Effect, LineError> {
+        let operand_column = |index: usize| operands[index].first().map_or(column, |x| x.column);
+        let mut parsed = Vec::new();
+        let mut values = Vec::new();
+        for (index, tokens) in operands.iter().enumerate() {
+            let (mut operand, value) = parse_operand(tokens, context, operand_column(index))?;
+            // Imported addresses aren't known yet, encode them as if they
+            // were this instruction's.
+            if let (Operand::Address(_), Some(Base::External(_))) = (operand, value.base) {
+                operand = Operand::Address(context.address.wrapping_add(value.value));
+            }
+            parsed.push(operand);
+            values.push(value);
+        }
+        let relocatable: Vec<bool> = values.iter().map(|x| x.base.is_some()).collect();
+        let encoded = self.target.encode(mnemonic, &parsed, &relocatable, context.address as u64).map_err(|message| (column, message))?;
+
+        let mut effect = Effect::bytes(encoded.bytes);
+        for (index, value) in values.into_iter().enumerate().filter(|(_, x)| x.base.is_some()) {
+            match encoded.fields.iter().find(|x| x.operand == index) {
+                // Relative to its own section, it moves along with it.
+                Some(field) if field.kind.is_relative() && value.base == Some(Base::Section(context.section)) => {}
+                Some(field) => effect.relocations.push((field.offset, field.kind, value)),
+                None if context.last_pass => return Err((operand_column(index), format!("{mnemonic} can't take a relocatable address here"))),
+                None => {}
+            }
+        }
+        Ok(effect)
+    }
+
+    fn directive(&self, name: &str, arguments: &[Vec<Token>], context: &Context, column: usize) -> Result<Effect, LineError> {
+        let argument_column = |index: usize| arguments.get(index).and_then(|x| x.first()).map_or(column, |x| x.column);
+        let value = |index: usize| evaluate(arguments.get(index).map_or(&[][..], |x| &x[..]), context, argument_column(index));
+        let expression = |index: usize| value(index).map(|x| x.value);
+        let expect_count = |range: std::ops::RangeInclusive<usize>| {
+            if range.contains(&arguments.len()) {
+                Ok(())
+            } else {
+                Err((column, format!("{name} takes {} arguments, but {} were given", if range.start() == range.end() { range.start().to_string() } else { format!("{} to {}", range.start(), range.end()) }, arguments.len())))
+            }
+        };
+        // Checks that `length` more bytes still fit in the section.
+        let reserve = |length: u64, column: usize| {
+            let section = SECTION_NAMES[context.section];
+            if (context.address as u64).checked_add(length).is_none() {
+                return Err((column, format!("{name} runs {section} past the end of the address space")));
+            }
+            if context.section != BSS && context.offset + length > MAX_SECTION_SIZE {
+                return Err((column, format!("{name} grows {section} past 0x{MAX_SECTION_SIZE:X} bytes")));
+            }
+            Ok(())
+        };
+        match name {
+            ".text" => {
+                expect_count(0..=0)?;
+                Ok(Effect { section: Some(TEXT), ..Default::default() })
+            }
+            ".data" => {
+                expect_count(0..=1)?;
+                let data_base = if arguments.is_empty() { None } else { Some(expression(0)? as u64) };
+                Ok(Effect { section: Some(DATA), data_base, ..Default::default() })
+            }
+            ".bss" => {
+                expect_count(0..=0)?;
+                Ok(Effect { section: Some(BSS), ..Default::default() })
+            }
+            // Collected before the first pass.
+            ".global" | ".extern" => {
+                if arguments.is_empty() {
+                    return Err((column, format!("{name} takes at least one symbol")));
+                }
+                match (0..arguments.len()).find(|&index| declared_names(&arguments[index..=index]).next().is_none()) {
+                    Some(index) => Err((argument_column(index), "Expected a global symbol name".to_string())),
+                    None => Ok(Effect::default()),
+                }
+            }
+            ".org" => {
+                expect_count(1..=1)?;
+                let target = expression(0)?;
+                if target < context.address {
+                    return Err((argument_column(0), format!(".org can't move back from 0x{:X} to 0x{target:X}", context.address)));
+                }
+                let length = (target as u64).wrapping_sub(context.address as u64);
+                reserve(length, argument_column(0))?;
+                Ok(Effect::zeros(length))
+            }
+            ".align" => {
+                expect_count(1..=1)?;
+                let alignment = expression(0)?;
+                if alignment <= 0 || alignment & (alignment - 1) != 0 {
+                    return Err((argument_column(0), format!("Alignment must be a power of two, but is {alignment}")));
+                }
+                let address = context.address as u64;
+                let length = address.wrapping_neg() & (alignment as u64 - 1);
+                reserve(length, argument_column(0))?;
+                Ok(Effect::zeros(length))
+            }
+            ".space" => {
+                expect_count(1..=2)?;
+                let length = expression(0)?;
+                let fill = if arguments.len() > 1 { expression(1)? } else { 0 };
+                if length < 0 {
+                    return Err((argument_column(0), format!("Space length must not be negative, but is {length}")));
+                }
+                reserve(length as u64, argument_column(0))?;
+                match fill as u8 {
+                    0 => Ok(Effect::zeros(length as u64)),
+                    fill => Ok(Effect::bytes(vec![fill; length as usize])),
+                }
+            }
+            ".equ" => {
+                expect_count(2..=2)?;
+                let Some(Token { kind: TokenKind::Identifier(symbol), .. }) = arguments[0].first().filter(|_| arguments[0].len() == 1) else {
+                    return Err((argument_column(0), "Expected a symbol name".to_string()));
+                };
+                Ok(Effect { assignment: Some((context.qualify(symbol), value(1)?)), ..Default::default() })
+            }
+            ".ascii" | ".string" => {
+                let mut bytes = Vec::new();
+                for (index, argument) in arguments.iter().enumerate() {
+                    let [Token { kind: TokenKind::String(string), .. }] = &argument[..] else {
+                        return Err((argument_column(index), "Expected a string".to_string()));
+                    };
+                    bytes.extend_from_slice(string);
+                    if name == ".string" {
+                        bytes.push(0);
+                    }
+                }
+                Ok(Effect::bytes(bytes))
+            }
+            ".byte" | ".half" | ".word" | ".dword" => {
+                let (width, kind) = match name {
+                    ".byte" => (1, None),
+                    ".half" => (2, Some(RelocationKind::Absolute16)),
+                    ".word" => (4, Some(RelocationKind::Absolute32)),
+                    _ => (8, Some(RelocationKind::Absolute64)),
+                };
+                let mut effect = Effect::default();
+                for (index, argument) in arguments.iter().enumerate() {
+                    if let [Token { kind: TokenKind::String(string), .. }] = &argument[..] {
+                        if width == 1 {
+                            effect.bytes.extend_from_slice(string);
+                            continue;
+                        }
+                    }
+                    let value = value(index)?;
+                    if context.last_pass && !fits(value.value, width) {
+                        return Err((argument_column(index), format!("Value {} doesn't fit in {width} bytes", value.value)));
+                    }
+                    if value.base.is_some() {
+                        match kind {
+                            Some(kind) => effect.relocations.push((effect.bytes.len(), kind, value)),
+                            None if context.last_pass => return Err((argument_column(index), format!("{name} can't hold a relocatable address"))),
+                            None => {}
+                        }
+                    }
+                    effect.bytes.extend_from_slice(&data_bytes(value.value as u64, width));
+                }
+                Ok(effect)
+            }
+            _ => Err((column, format!("Unknown directive {name}"))),
+        }
+    }
+}
+
+// Same byte order as `MemorySliceTrait`: 16 and 32-bit values are
+// big-endian, 64-bit values are the low word followed by the high word.
+fn data_bytes(value: u64, width: usize) -> Vec<u8> {
+    match width {
+        1 => vec![value as u8],
+        2 => (value as u16).to_be_bytes().to_vec(),
+        4 => (value as u32).to_be_bytes().to_vec(),
+        _ => [(value as u32).to_be_bytes(), ((value >> 32) as u32).to_be_bytes()].concat(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, AssembledProgram, AssembledRelocation, Assembler, AssemblerError, RelocationTarget, TokenKind, DATA, TEXT};
+    use crate::{image::RelocationKind, target::RegisterMachineTarget};
+
+    fn assemble(source: &str) -> Result<AssembledProgram, Vec<AssemblerError>> {
+        Assembler::new(RegisterMachineTarget).assemble(source)
+    }
+
+    fn error(source: &str) -> String {
+        let errors = assemble(source).unwrap_err();
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        errors[0].to_string()
+    }
+
+    fn symbol(program: &AssembledProgram, name: &str) -> i64 {
+        program.symbol(name).unwrap() as i64
+    }
+
+    #[test]
+    fn tokenizes_lines() {
+        let tokens = tokenize(r#"loop: .byte 0x1F, 0b101, 0o17, 1_000, 'a', '\n', "hi\x21" ; comment"#).unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|x| (x.kind.clone(), x.column)).collect();
+        assert_eq!(kinds, [
+            (TokenKind::Identifier("loop".to_string()), 1),
+            (TokenKind::Punct(":"), 5),
+            (TokenKind::Identifier(".byte".to_string()), 7),
+            (TokenKind::Number(0x1F), 13),
+            (TokenKind::Punct(","), 17),
+            (TokenKind::Number(0b101), 19),
+            (TokenKind::Punct(","), 24),
+            (TokenKind::Number(0o17), 26),
+            (TokenKind::Punct(","), 30),
+            (TokenKind::Number(1000), 32),
+            (TokenKind::Punct(","), 37),
+            (TokenKind::Number(b'a' as i64), 39),
+            (TokenKind::Punct(","), 42),
+            (TokenKind::Number(b'\n' as i64), 44),
+            (TokenKind::Punct(","), 48),
+            (TokenKind::String(b"hi!".to_vec()), 50),
+        ]);
+        assert_eq!(tokenize("a << b // comment").unwrap().len(), 3);
+
+        assert_eq!(tokenize("  .ascii \"open"), Err((10, "Unterminated string".to_string())));
+        assert_eq!(tokenize("movi r0, #0xZZ"), Err((11, "Invalid number '0xZZ'".to_string())));
+        assert_eq!(tokenize("halt @"), Err((6, "Unexpected character '@'".to_string())));
+        assert_eq!(tokenize("'\\q'"), Err((1, "Unknown escape \\q".to_string())));
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let program = assemble("
+a = 1 + 2 * 3
+b = (1 + 2) * 3
+c = -8 >> 1
+d = ~0 & 0xFF
+e = 1 << 4 | 3 ^ 1
+f = 7 % 4 - -2
+g = a * b
+").unwrap();
+        let values: Vec<_> = ["a", "b", "c", "d", "e", "f", "g"].iter().map(|x| symbol(&program, x)).collect();
+        assert_eq!(values, [7, 9, -4, 0xFF, 0x12, 5, 63]);
+
+        assert_eq!(error("x = 4 / (2 - 2)"), "1:7: Division by zero");
+        assert_eq!(error("x = (1 + 2"), "1:11: Expected ')'");
+        assert_eq!(error("x = 1 2"), "1:7: Unexpected token after expression");
+        assert_eq!(error("x = y"), "1:5: Undefined symbol 'y'");
+    }
+
+    #[test]
+    fn resolves_forward_references() {
+        let program = assemble("
+start:  .dword end
+        .space gap
+end:    halt
+        b start
+gap = size / 2
+size = 8
+").unwrap();
+        // Passes take the address a symbol is used at until it's defined,
+        // `end` moves once `gap` is known, and `gap` once `size` is.
+        assert_eq!(symbol(&program, "gap"), 4);
+        assert_eq!(symbol(&program, "end"), 12);
+        assert_eq!(program.sections[TEXT].size, 20);
+    }
+
+    #[test]
+    fn scopes_local_labels() {
+        let program = assemble("
+first:  halt
+.loop:  b .loop
+second: halt
+.loop:  b .loop
+        b first.loop
+").unwrap();
+        assert_eq!(symbol(&program, "first.loop"), 4);
+        assert_eq!(symbol(&program, "second.loop"), 12);
+        assert_eq!(program.symbol(".loop"), None);
+
+        assert_eq!(error("first:\n.loop: halt\n.loop: halt"), "3:1: Symbol 'first.loop' is already defined");
+    }
+
+    #[test]
+    fn defines_symbols_with_equ() {
+        let program = assemble("
+        .equ size, 4 * 4
+scope:  .equ .offset, size + 1
+        .equ after, later
+later:  halt
+").unwrap();
+        assert_eq!(symbol(&program, "size"), 16);
+        assert_eq!(symbol(&program, "scope.offset"), 17);
+        assert_eq!(symbol(&program, "after"), 0);
+        assert!(!program.symbol_sections.contains_key("size"));
+        assert_eq!(program.symbol_sections.get("after"), Some(&TEXT));
+
+        assert_eq!(error(".equ 5, 3"), "1:6: Expected a symbol name");
+        assert_eq!(error(".equ size"), "1:1: .equ takes 2 arguments, but 1 were given");
+        assert_eq!(error(".equ size, 1\n.equ size, 2"), "2:1: Symbol 'size' is already defined");
+    }
+
+    #[test]
+    fn emits_relocations() {
+        let program = assemble("
+        .extern ext
+        movi r0, #here
+        .data
+here:   .dword here + 4, ext + 8
+        .word 3
+").unwrap();
+        assert_eq!(program.sections[DATA].address, 16);
+        assert_eq!(program.externs, ["ext"]);
+        let relocations: Vec<_> = program.relocations.iter().filter(|x| x.section == DATA).cloned().collect();
+        assert_eq!(relocations, [
+            AssembledRelocation { section: DATA, offset: 0, kind: RelocationKind::Absolute64, target: RelocationTarget::Section(DATA), addend: 4 },
+            AssembledRelocation { section: DATA, offset: 8, kind: RelocationKind::Absolute64, target: RelocationTarget::External("ext".to_string()), addend: 8 },
+        ]);
+        let text: Vec<_> = program.relocations.iter().filter(|x| x.section == TEXT).map(|x| (x.offset, &x.target, x.addend)).collect();
+        assert_eq!(text, [(0, &RelocationTarget::Section(DATA), 0)]);
+
+        // Plain numbers and differences of addresses aren't relocated.
+        let program = assemble("a: .dword 5, b - a\nb: halt").unwrap();
+        assert!(program.relocations.is_empty());
+
+        assert_eq!(error("here: .byte here"), "1:13: .byte can't hold a relocatable address");
+    }
+
+    #[test]
+    fn reports_errors_at_their_line_and_column() {
+        let errors = assemble("
+        halt
+        .bogus 1
+        movi r0, #(1
+        .bss
+        .byte 1
+").unwrap_err();
+        let errors: Vec<_> = errors.iter().map(|x| x.to_string()).collect();
+        assert_eq!(errors, ["3:9: Unknown directive .bogus", "4:21: Expected ')'", "6:9: .bss can only hold zeros"]);
+
+        assert_eq!(error("        .ascii \"open"), "1:16: Unterminated string");
+        assert_eq!(error("        .global nowhere"), "1:9: Global symbol 'nowhere' is never defined");
+    }
+
+    #[test]
+    fn reports_overlapping_sections() {
+        assert_eq!(error("halt\nhalt\n.data 4\n.word 1"), "3:1: Sections .text and .data overlap");
+        assert!(assemble("halt\nhalt\n.data 8\n.word 1").is_ok());
+
+        // `.bss` follows `.data` below the origin onto `.text`.
+        let errors = Assembler::new(RegisterMachineTarget).with_origin(0x100).assemble("halt\n.data 0xF0\n.word 1\n.bss\n.space 0x20").unwrap_err();
+        assert_eq!(errors, [AssemblerError { line: 2, column: 1, message: "Sections .text and .bss overlap".to_string() }]);
+    }
+}
@@ -0,0 +1,65 @@
+use std::{env, fs, path::PathBuf, process::ExitCode};
+
+use avm_rs_toolchain::{
+    assembler::{AssembledProgram, Assembler, AssemblerError},
+    object::Object,
+    target::{RegisterMachineTarget, StackMachineTarget},
+};
+
+const USAGE: &str = "Usage: avm-as [--target register|stack] [-o output.o] input.s";
+
+// Assembles one source file into an object file for avm-ld.
+fn main() -> ExitCode {
+    let mut target = "register".to_string();
+    let mut output = None;
+    let mut input = None;
+    let mut arguments = env::args().skip(1);
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--target" => target = arguments.next().unwrap_or_default(),
+            "-o" => output = arguments.next().map(PathBuf::from),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if input.is_none() && !argument.starts_with('-') => input = Some(PathBuf::from(argument)),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(input) = input else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let output = output.unwrap_or_else(|| input.with_extension("o"));
+
+    let source = match fs::read_to_string(&input) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("avm-as: {}: {error}", input.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let program: Result<AssembledProgram, Vec<AssemblerError>> = match target.as_str() {
+        "register" => Assembler::new(RegisterMachineTarget).assemble(&source),
+        "stack" => Assembler::new(StackMachineTarget).assemble(&source),
+        _ => {
+            eprintln!("avm-as: Unknown target '{target}', expected register or stack");
+            return ExitCode::FAILURE;
+        }
+    };
+    let program = match program {
+        Ok(program) => program,
+        Err(errors) => {
+            errors.iter().for_each(|error| eprintln!("{}:{error}", input.display()));
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(error) = Object::from_program(&program).write_file(&output) {
+        eprintln!("avm-as: {}: {error}", output.display());
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
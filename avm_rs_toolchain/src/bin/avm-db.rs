@@ -0,0 +1,127 @@
+use std::{env, io, path::PathBuf, process::ExitCode};
+
+use avm_rs_component::{debug::DebugTarget, register_machine::RegisterCPU, stack_machine::StackCPU};
+use avm_rs_memory::{mem::{create_memory, Memory}, vmem::VirtualMemory, wrappers::stack::Stack};
+use avm_rs_toolchain::{
+    debugger::Debugger,
+    disassembler::DisassemblerTarget,
+    gdb::GdbStub,
+    image::Image,
+    loader::{load, LoadedImage},
+    repl::Repl,
+    target::{RegisterMachineTarget, StackMachineTarget},
+};
+
+const USAGE: &str = "Usage: avm-db [--target register|stack] [--memory bytes] [--cycle-limit cycles] [--gdb tcp:PORT|unix:PATH] image";
+const STACK_SIZE: usize = 64 * 1024;
+// The terminal can't interrupt a guest that loops, so `continue` gives up
+// after this many cycles unless `--cycle-limit` says otherwise, 0 for none.
+// GDB interrupts it instead.
+const DEFAULT_CYCLE_LIMIT: u64 = 10_000_000;
+
+struct Options {
+    cycle_limit: Option<u64>,
+    gdb: Option<String>,
+}
+
+fn debug<C: DebugTarget, T: DisassemblerTarget>(mut cpu: C, memory: Memory, target: T, loaded: LoadedImage, options: Options) -> ExitCode {
+    loaded.start(&mut cpu);
+    let mut debugger = Debugger::new(cpu, memory, target).with_symbols(loaded.symbols);
+    let default = if options.gdb.is_none() { Some(DEFAULT_CYCLE_LIMIT) } else { None };
+    if let Some(cycles) = options.cycle_limit.or(default).filter(|&x| x > 0) {
+        debugger = debugger.with_cycle_limit(cycles);
+    }
+    let result = match options.gdb.as_deref() {
+        None => Repl::new(debugger).run(io::stdin().lock(), io::stdout()),
+        Some(address) => {
+            let mut stub = GdbStub::new(debugger);
+            match address.split_once(':') {
+                Some(("tcp", port)) if port.parse::<u16>().is_ok() => stub.listen_tcp(port.parse().unwrap()),
+                #[cfg(unix)]
+                Some(("unix", path)) => stub.listen_unix(path),
+                _ => {
+                    eprintln!("avm-db: Expected tcp:PORT or unix:PATH, got '{address}'");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("avm-db: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Loads an image from avm-ld and debugs it from the terminal, or serves it
+// to GDB.
+fn main() -> ExitCode {
+    let mut target = "register".to_string();
+    let mut size = 1024 * 1024;
+    let mut options = Options { cycle_limit: None, gdb: None };
+    let mut input = None;
+    let mut arguments = env::args().skip(1);
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--target" => target = arguments.next().unwrap_or_default(),
+            "--memory" => match arguments.next().and_then(|x| x.parse().ok()) {
+                Some(bytes) => size = bytes,
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--cycle-limit" => match arguments.next().and_then(|x| x.parse().ok()) {
+                Some(cycles) => options.cycle_limit = Some(cycles),
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--gdb" => options.gdb = arguments.next(),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if input.is_none() && !argument.starts_with('-') => input = Some(PathBuf::from(argument)),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(input) = input else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let image = match Image::read_file(&input) {
+        Ok(image) => image,
+        Err(error) => {
+            eprintln!("avm-db: {}: {error}", input.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let memory = create_memory(size);
+    let mut virtual_memory = VirtualMemory::new(memory.clone());
+    let loaded = match load(&image, &mut virtual_memory) {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            eprintln!("avm-db: {}: {error}", input.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    match target.as_str() {
+        "register" => debug(RegisterCPU::new(memory.clone(), 0), memory, RegisterMachineTarget, loaded, options),
+        "stack" => {
+            let stack = Stack::new(virtual_memory.allocate(STACK_SIZE));
+            debug(StackCPU::new(memory.clone(), stack, 0), memory, StackMachineTarget, loaded, options)
+        }
+        _ => {
+            eprintln!("avm-db: Unknown target '{target}', expected register or stack");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::{env, path::PathBuf, process::ExitCode};
+
+use avm_rs_toolchain::{linker::Linker, object::Object};
+
+const USAGE: &str = "Usage: avm-ld [-o output] [--entry symbol] [--base section=address]... input.o...";
+
+fn parse_address(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Links object files from avm-as into an executable image.
+fn main() -> ExitCode {
+    let mut linker = Linker::new();
+    let mut output = PathBuf::from("a.out");
+    let mut inputs = Vec::new();
+    let mut arguments = env::args().skip(1);
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "-o" => output = arguments.next().map(PathBuf::from).unwrap_or(output),
+            "-e" | "--entry" => linker = linker.with_entry(&arguments.next().unwrap_or_default()),
+            "--base" => {
+                let base = arguments.next().unwrap_or_default();
+                let Some((section, address)) = base.split_once('=').and_then(|(section, address)| Some((section, parse_address(address)?))) else {
+                    eprintln!("avm-ld: Expected section=address, got '{base}'");
+                    return ExitCode::FAILURE;
+                };
+                linker = linker.with_base(section, address);
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if !argument.starts_with('-') => inputs.push(PathBuf::from(argument)),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if inputs.is_empty() {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+
+    for input in &inputs {
+        match Object::read_file(input) {
+            Ok(object) => linker.add(&input.display().to_string(), object),
+            Err(error) => {
+                eprintln!("avm-ld: {}: {error}", input.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let image = match linker.link() {
+        Ok(image) => image,
+        Err(errors) => {
+            errors.iter().for_each(|error| eprintln!("avm-ld: {error}"));
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(error) = image.write_file(&output) {
+        eprintln!("avm-ld: {}: {error}", output.display());
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
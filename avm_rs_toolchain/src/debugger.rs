@@ -0,0 +1,506 @@
+use std::collections::BTreeMap;
+
+use avm_rs_component::{cpu::StepResult, debug::{DebugTarget, InstructionKind}, register::RegisterValue};
+use avm_rs_memory::{access_memory, mem::{hexdump, Memory}};
+
+use crate::disassembler::{DisassembledInstruction, Disassembler, DisassemblerTarget, SymbolTable};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub address: u64,
+    // Expression that has to be non-zero for the breakpoint to stop.
+    pub condition: Option<String>,
+    pub enabled: bool,
+    pub hits: u64,
+    // `condition` parsed once, it's evaluated at every hit.
+    expression: Option<Expression>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    // The step, step over or step out finished.
+    Stepped,
+    Breakpoint(usize),
+    ConditionError { breakpoint: usize, message: String },
+    // The CPU returned something other than `Continue`, a `brk` in the
+    // program shows up as `StepResult::Breakpoint`.
+    Cpu(StepResult),
+    CycleLimit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub address: u64,
+    // `name` or `name+0x10`, see `SymbolTable::describe`.
+    pub symbol: Option<String>,
+}
+
+// Runs a CPU under control of breakpoints. Breakpoints are checked by the
+// debugger before every step instead of being patched into memory, so they
+// never show up in memory or disassembly, and the one at the program counter
+// is skipped when execution resumes.
+pub struct Debugger<C: DebugTarget, T: DisassemblerTarget> {
+    cpu: C,
+    memory: Memory,
+    disassembler: Disassembler<T>,
+    symbols: BTreeMap<String, u64>,
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint: usize,
+    cycle_limit: Option<u64>,
+}
+
+impl<C: DebugTarget, T: DisassemblerTarget> Debugger<C, T> {
+    pub fn new(cpu: C, memory: Memory, target: T) -> Self {
+        Self {
+            cpu,
+            memory,
+            disassembler: Disassembler::new(target),
+            symbols: BTreeMap::new(),
+            breakpoints: Vec::new(),
+            next_breakpoint: 1,
+            cycle_limit: None,
+        }
+    }
+
+    // Addresses of symbols, as in `LoadedImage::symbols`.
+    pub fn with_symbols(mut self, symbols: BTreeMap<String, u64>) -> Self {
+        let mut table = SymbolTable::new();
+        symbols.iter().for_each(|(name, &address)| table.insert(name, address));
+        self.disassembler = self.disassembler.with_symbols(table);
+        self.symbols = symbols;
+        self
+    }
+
+    // Stops running after `cycles` steps, instead of never returning from
+    // a program that doesn't stop.
+    pub fn with_cycle_limit(mut self, cycles: u64) -> Self {
+        self.cycle_limit = Some(cycles);
+        self
+    }
+
+    pub fn set_cycle_limit(&mut self, cycles: Option<u64>) {
+        self.cycle_limit = cycles;
+    }
+
+    pub fn cpu(&self) -> &C {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut C {
+        &mut self.cpu
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn cycle_limit(&self) -> Option<u64> {
+        self.cycle_limit
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn describe(&self, address: u64) -> Option<String> {
+        self.disassembler.symbols().describe(address)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // `location` is an expression, usually a symbol or an address. The
+    // condition is only checked here, it's evaluated at every hit.
+    pub fn add_breakpoint(&mut self, location: &str, condition: Option<&str>) -> Result<usize, String> {
+        let address = self.evaluate(location)?;
+        let expression = condition.map(Expression::parse).transpose()?;
+        if let Some(expression) = &expression {
+            expression.check(self)?;
+        }
+        let id = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.push(Breakpoint { id, address, condition: condition.map(|x| x.to_string()), enabled: true, hits: 0, expression });
+        Ok(id)
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|x| x.id != id);
+        self.breakpoints.len() != count
+    }
+
+    pub fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        match self.breakpoints.iter_mut().find(|x| x.id == id) {
+            Some(breakpoint) => {
+                breakpoint.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    // One instruction, ignoring breakpoints.
+    pub fn step(&mut self) -> StopReason {
+        self.run(|_| true)
+    }
+
+    // Like `step`, but runs called functions until they return.
+    pub fn step_over(&mut self) -> StopReason {
+        let mut depth = 0;
+        self.run(move |kind| {
+            depth += Self::depth_change(kind);
+            depth <= 0
+        })
+    }
+
+    // Runs until the current function returns.
+    pub fn step_out(&mut self) -> StopReason {
+        let mut depth = 0;
+        self.run(move |kind| {
+            depth += Self::depth_change(kind);
+            depth < 0
+        })
+    }
+
+    pub fn cont(&mut self) -> StopReason {
+        self.run(|_| false)
+    }
+
+    fn depth_change(kind: Option<InstructionKind>) -> i64 {
+        match kind {
+            Some(InstructionKind::Call) => 1,
+            Some(InstructionKind::Return) => -1,
+            _ => 0,
+        }
+    }
+
+    // Steps until `finished` holds for the kind of the instruction that just
+    // ran, checking breakpoints before every step but the first.
+    fn run(&mut self, mut finished: impl FnMut(Option<InstructionKind>) -> bool) -> StopReason {
+        let mut cycles = 0;
+        loop {
+            if cycles > 0 {
+                if let Some(reason) = self.check_breakpoints() {
+                    return reason;
+                }
+            }
+            if self.cycle_limit.is_some_and(|limit| cycles >= limit) {
+                return StopReason::CycleLimit;
+            }
+            let kind = self.cpu.instruction_kind(self.cpu.pc());
+            let result = self.cpu.step();
+            cycles += 1;
+            if !result.is_continue() {
+                return StopReason::Cpu(result);
+            }
+            if finished(kind) {
+                return StopReason::Stepped;
+            }
+        }
+    }
+
+    fn check_breakpoints(&mut self) -> Option<StopReason> {
+        let pc = self.cpu.pc();
+        for index in 0..self.breakpoints.len() {
+            let breakpoint = &self.breakpoints[index];
+            if !breakpoint.enabled || breakpoint.address != pc {
+                continue;
+            }
+            let id = breakpoint.id;
+            if let Some(expression) = &breakpoint.expression {
+                match expression.evaluate(self) {
+                    Ok(0) => continue,
+                    Ok(_) => {}
+                    Err(message) => return Some(StopReason::ConditionError { breakpoint: id, message }),
+                }
+            }
+            self.breakpoints[index].hits += 1;
+            return Some(StopReason::Breakpoint(id));
+        }
+        None
+    }
+
+    pub fn registers(&self) -> Vec<(&'static str, RegisterValue)> {
+        self.cpu.registers()
+    }
+
+    // Float registers get `value` converted to a float, the others its low
+    // bits.
+    pub fn set_register(&mut self, name: &str, value: u64) -> Result<(), String> {
+        let index = self.cpu.register_index(name).ok_or_else(|| format!("Unknown register '{name}'"))?;
+        let value = match self.cpu.registers()[index].1 {
+            RegisterValue::F32(_) => RegisterValue::F32(value as i64 as f32),
+            RegisterValue::F64(_) => RegisterValue::F64(value as i64 as f64),
+            current => RegisterValue::from_bits(current.width(), value as u128),
+        };
+        self.cpu.set_register(index, value).map_err(|error| error.to_string())
+    }
+
+    pub fn read_memory(&self, address: u64, length: usize) -> Result<Vec<u8>, String> {
+        let memory = access_memory!(self.memory);
+        if address.checked_add(length as u64).is_none_or(|end| end > memory.len() as u64) {
+            return Err(format!("Cannot access memory at 0x{address:x}"));
+        }
+        Ok(memory.read_bytes(address as usize, length))
+    }
+
+    pub fn write_memory(&mut self, address: u64, bytes: &[u8]) -> Result<(), String> {
+        let mut memory = access_memory!(self.memory);
+        if address.checked_add(bytes.len() as u64).is_none_or(|end| end > memory.len() as u64) {
+            return Err(format!("Cannot access memory at 0x{address:x}"));
+        }
+        memory.write_bytes(address as usize, bytes);
+        Ok(())
+    }
+
+    pub fn read_u64(&self, address: u64) -> Result<u64, String> {
+        let memory = access_memory!(self.memory);
+        if address.checked_add(8).is_none_or(|end| end > memory.len() as u64) {
+            return Err(format!("Cannot access memory at 0x{address:x}"));
+        }
+        Ok(memory.read_u64(address as usize))
+    }
+
+    // The hexdump view of memory with the address of every line in front.
+    pub fn hexdump(&self, address: u64, length: usize) -> Result<String, String> {
+        let bytes = self.read_memory(address, length)?;
+        let dump = hexdump(&bytes, |c| if !(32..=126).contains(&c) { '.' } else { c as char });
+        Ok(dump.lines().enumerate().map(|(index, line)| format!("{:08x}: {line}\n", address + index as u64 * 16)).collect())
+    }
+
+    pub fn disassemble(&self, address: u64, count: usize) -> Vec<DisassembledInstruction> {
+        let memory = access_memory!(self.memory);
+        let mut instructions = Vec::new();
+        let mut address = address;
+        while instructions.len() < count && (address as usize) < memory.len() {
+            let instruction = self.disassembler.decode(&*memory, address);
+            address += instruction.bytes.len() as u64;
+            instructions.push(instruction);
+        }
+        instructions
+    }
+
+    pub fn format(&self, instruction: &DisassembledInstruction) -> String {
+        self.disassembler.format(instruction)
+    }
+
+    // The program counter, then the return addresses the CPU knows of.
+    pub fn backtrace(&self) -> Vec<Frame> {
+        std::iter::once(self.cpu.pc())
+            .chain(self.cpu.return_addresses())
+            .map(|address| Frame { address, symbol: self.describe(address) })
+            .collect()
+    }
+
+    // Integer expression over registers, symbols and memory: C operators,
+    // comparisons giving 0 or 1, and `[address]` for the u64 there. Float
+    // registers are truncated to integers.
+    pub fn evaluate(&self, expression: &str) -> Result<u64, String> {
+        Expression::parse(expression)?.evaluate(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Name(String),
+    Operator(&'static str),
+}
+
+const OPERATORS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+    "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "~", "!", "(", ")", "[", "]",
+];
+
+const BINARY_OPERATORS: &[&[&str]] = &[
+    &["||"], &["&&"], &["==", "!="], &["<", "<=", ">", ">="],
+    &["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"],
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expression {
+    tokens: Vec<Token>,
+}
+
+// Dry evaluations only check the expression, taking the value of every
+// register, symbol and memory read as 1. They don't divide, the values at a
+// hit may well be different.
+struct Evaluation<'a, C: DebugTarget, T: DisassemblerTarget> {
+    tokens: &'a [Token],
+    position: usize,
+    debugger: &'a Debugger<C, T>,
+    dry: bool,
+}
+
+impl Expression {
+    fn parse(text: &str) -> Result<Self, String> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut tokens = Vec::new();
+        let mut position = 0;
+        while position < chars.len() {
+            let c = chars[position];
+            if c.is_whitespace() {
+                position += 1;
+            } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                let start = position;
+                while position < chars.len() && (chars[position].is_ascii_alphanumeric() || chars[position] == '_' || chars[position] == '.') {
+                    position += 1;
+                }
+                let word: String = chars[start..position].iter().collect();
+                if !c.is_ascii_digit() {
+                    tokens.push(Token::Name(word));
+                    continue;
+                }
+                let lower = word.to_ascii_lowercase();
+                let parsed = if let Some(digits) = lower.strip_prefix("0x") {
+                    u64::from_str_radix(digits, 16)
+                } else if let Some(digits) = lower.strip_prefix("0b") {
+                    u64::from_str_radix(digits, 2)
+                } else {
+                    lower.parse()
+                };
+                tokens.push(Token::Number(parsed.map_err(|_| format!("Invalid number '{word}'"))?));
+            } else {
+                let rest: String = chars[position..chars.len().min(position + 2)].iter().collect();
+                let Some(operator) = OPERATORS.iter().find(|x| rest.starts_with(**x)) else {
+                    return Err(format!("Unexpected character '{c}'"));
+                };
+                position += operator.len();
+                tokens.push(Token::Operator(operator));
+            }
+        }
+        if tokens.is_empty() {
+            return Err("Expected an expression".to_string());
+        }
+        Ok(Self { tokens })
+    }
+
+    fn check<C: DebugTarget, T: DisassemblerTarget>(&self, debugger: &Debugger<C, T>) -> Result<(), String> {
+        self.run(debugger, true).map(|_| ())
+    }
+
+    fn evaluate<C: DebugTarget, T: DisassemblerTarget>(&self, debugger: &Debugger<C, T>) -> Result<u64, String> {
+        self.run(debugger, false)
+    }
+
+    fn run<C: DebugTarget, T: DisassemblerTarget>(&self, debugger: &Debugger<C, T>, dry: bool) -> Result<u64, String> {
+        let mut evaluation = Evaluation { tokens: &self.tokens, position: 0, debugger, dry };
+        let value = evaluation.binary(0)?;
+        if evaluation.position < self.tokens.len() {
+            return Err("Unexpected token after expression".to_string());
+        }
+        Ok(value)
+    }
+}
+
+impl<C: DebugTarget, T: DisassemblerTarget> Evaluation<'_, C, T> {
+    fn binary(&mut self, level: usize) -> Result<u64, String> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(&Token::Operator(operator)) = self.tokens.get(self.position) {
+            if !BINARY_OPERATORS[level].contains(&operator) {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = match operator {
+                "||" => (left != 0 || right != 0) as u64,
+                "&&" => (left != 0 && right != 0) as u64,
+                "==" => (left == right) as u64,
+                "!=" => (left != right) as u64,
+                "<" => (left < right) as u64,
+                "<=" => (left <= right) as u64,
+                ">" => (left > right) as u64,
+                ">=" => (left >= right) as u64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                _ if self.dry => 1,
+                _ if right == 0 => return Err("Division by zero".to_string()),
+                "/" => left / right,
+                _ => left % right,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<u64, String> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator("-")) => {
+                self.position += 1;
+                Ok(self.unary()?.wrapping_neg())
+            }
+            Some(Token::Operator("~")) => {
+                self.position += 1;
+                Ok(!self.unary()?)
+            }
+            Some(Token::Operator("!")) => {
+                self.position += 1;
+                Ok((self.unary()? == 0) as u64)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<u64, String> {
+        let Some(token) = self.tokens.get(self.position) else {
+            return Err("Expected an expression".to_string());
+        };
+        self.position += 1;
+        match token {
+            Token::Number(value) => Ok(*value),
+            Token::Name(name) => self.lookup(name),
+            Token::Operator("(") => {
+                let value = self.binary(0)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Token::Operator("[") => {
+                let address = self.binary(0)?;
+                self.expect("]")?;
+                if self.dry {
+                    return Ok(1);
+                }
+                self.debugger.read_u64(address)
+            }
+            _ => Err("Expected an expression".to_string()),
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), String> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(found)) if *found == operator => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(format!("Expected '{operator}'")),
+        }
+    }
+
+    // Registers first, then symbols.
+    fn lookup(&self, name: &str) -> Result<u64, String> {
+        let cpu = &self.debugger.cpu;
+        let value = match cpu.register_index(name) {
+            Some(index) => match cpu.registers()[index].1 {
+                RegisterValue::F32(value) => value as i64 as u64,
+                RegisterValue::F64(value) => value as i64 as u64,
+                value => value.to_bits() as u64,
+            },
+            None => self.debugger.symbol(name).ok_or_else(|| format!("No symbol '{name}'"))?,
+        };
+        Ok(if self.dry { 1 } else { value })
+    }
+}
@@ -0,0 +1,154 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use avm_rs_component::decode::DecodeError;
+use avm_rs_memory::mem::MemorySliceTrait;
+
+use crate::assembler::{AssembledProgram, Operand};
+
+pub trait DisassemblerTarget {
+    // Bytes skipped over when decoding fails.
+    const ALIGNMENT: usize;
+
+    // Decodes the instruction at `address` into its length, mnemonic and
+    // operands. Branch and call targets are returned as absolute `Address`
+    // operands so they can be named.
+    fn decode(&self, memory: &dyn MemorySliceTrait, address: u64) -> Result<(usize, &'static str, Vec<Operand>), DecodeError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    pub address: u64,
+    pub bytes: Vec<u8>,
+    // None for bytes that don't decode.
+    pub mnemonic: Option<&'static str>,
+    pub operands: Vec<Operand>,
+}
+
+// Addresses to names. When several symbols share an address, global labels
+// win over local ones, then the first in alphabetical order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    names: BTreeMap<u64, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The labels of a program, constants are left out.
+    pub fn from_program(program: &AssembledProgram) -> Self {
+        let mut table = Self::new();
+        for name in program.symbol_sections.keys() {
+            table.insert(name, program.symbols[name]);
+        }
+        table
+    }
+
+    pub fn insert(&mut self, name: &str, address: u64) {
+        let rank = |name: &str| (name.contains('.'), name.to_string());
+        match self.names.get(&address) {
+            Some(existing) if rank(existing) <= rank(name) => {}
+            _ => {
+                self.names.insert(address, name.to_string());
+            }
+        }
+    }
+
+    pub fn name(&self, address: u64) -> Option<&str> {
+        self.names.get(&address).map(|x| x.as_str())
+    }
+
+    // `name` or `name+0x10` for the closest symbol at or below `address`.
+    pub fn describe(&self, address: u64) -> Option<String> {
+        let (&base, name) = self.names.range(..=address).next_back()?;
+        Some(match address - base {
+            0 => name.clone(),
+            offset => format!("{name}+0x{offset:x}"),
+        })
+    }
+}
+
+pub struct Disassembler<T: DisassemblerTarget> {
+    target: T,
+    symbols: SymbolTable,
+}
+
+impl<T: DisassemblerTarget> Disassembler<T> {
+    pub fn new(target: T) -> Self {
+        Self { target, symbols: SymbolTable::new() }
+    }
+
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn decode(&self, memory: &dyn MemorySliceTrait, address: u64) -> DisassembledInstruction {
+        let (length, mnemonic, operands) = match self.target.decode(memory, address) {
+            Ok((length, mnemonic, operands)) => (length, Some(mnemonic), operands),
+            Err(_) => (T::ALIGNMENT, None, Vec::new()),
+        };
+        let length = length.min(memory.len().saturating_sub(address as usize));
+        DisassembledInstruction {
+            address,
+            bytes: memory.fetch_bytes(address as usize, length),
+            mnemonic,
+            operands,
+        }
+    }
+
+    // Every instruction starting in `start..end`, stopping early at the end
+    // of memory.
+    pub fn disassemble(&self, memory: &dyn MemorySliceTrait, start: u64, end: u64) -> Vec<DisassembledInstruction> {
+        let mut instructions = Vec::new();
+        let mut address = start;
+        while address < end && (address as usize) < memory.len() {
+            let instruction = self.decode(memory, address);
+            address += instruction.bytes.len() as u64;
+            instructions.push(instruction);
+        }
+        instructions
+    }
+
+    // `mnemonic operands` with symbols substituted for addresses.
+    pub fn format(&self, instruction: &DisassembledInstruction) -> String {
+        let Some(mnemonic) = instruction.mnemonic else {
+            return "(bad)".to_string();
+        };
+        let operands: Vec<String> = instruction.operands.iter().map(|operand| match *operand {
+            Operand::Register(register) => format!("r{register}"),
+            Operand::FloatRegister(register) => format!("f{register}"),
+            Operand::Immediate(value) if value < 0 => format!("#{value}"),
+            Operand::Immediate(value) => format!("#0x{value:X}"),
+            Operand::Address(address) => match self.symbols.describe(address as u64) {
+                Some(name) => format!("0x{address:x} <{name}>"),
+                None => format!("0x{address:x}"),
+            },
+        }).collect();
+        match operands.is_empty() {
+            true => mnemonic.to_string(),
+            false => format!("{mnemonic} {}", operands.join(", ")),
+        }
+    }
+
+    // An `objdump -d` style listing of `start..end`, with a heading at every
+    // symbol.
+    pub fn listing(&self, memory: &dyn MemorySliceTrait, start: u64, end: u64) -> String {
+        let instructions = self.disassemble(memory, start, end);
+        let width = instructions.iter().map(|x| x.bytes.len() * 3).max().unwrap_or(0);
+        let mut output = String::new();
+        for instruction in &instructions {
+            if let Some(name) = self.symbols.name(instruction.address) {
+                let _ = writeln!(output, "\n{:016x} <{name}>:", instruction.address);
+            }
+            let bytes: String = instruction.bytes.iter().map(|x| format!("{x:02x} ")).collect();
+            let _ = writeln!(output, "{:8x}:\t{bytes:width$}\t{}", instruction.address, self.format(instruction));
+        }
+        output
+    }
+}
@@ -0,0 +1,566 @@
+use std::{
+    fmt::Write as _,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use avm_rs_component::{cpu::{CPUFault, StepResult}, debug::DebugTarget, register::RegisterValue};
+
+use crate::{debugger::{Debugger, StopReason}, disassembler::DisassemblerTarget};
+
+// Steps run between checks for an interrupt from GDB while continuing.
+const SLICE: u64 = 100_000;
+const PACKET_SIZE: usize = 0x4000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+// A stream GDB is connected over. Continuing polls it for interrupts without
+// blocking.
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+enum Resumed {
+    Stopped(StopReason),
+    Interrupted,
+}
+
+// Server side of the GDB remote serial protocol for one CPU, a single thread
+// as far as GDB is concerned. Registers go over the wire in the order of
+// `DebugTarget::registers` and big-endian, the order memory stores u16 and
+// u32 in. Breakpoints are the debugger's, so they never change memory.
+pub struct GdbStub<C: DebugTarget, T: DisassemblerTarget> {
+    debugger: Debugger<C, T>,
+    acknowledge: bool,
+    last_stop: String,
+}
+
+impl<C: DebugTarget, T: DisassemblerTarget> GdbStub<C, T> {
+    pub fn new(debugger: Debugger<C, T>) -> Self {
+        Self { debugger, acknowledge: true, last_stop: format!("S{SIGTRAP:02x}") }
+    }
+
+    pub fn debugger(&self) -> &Debugger<C, T> {
+        &self.debugger
+    }
+
+    pub fn into_debugger(self) -> Debugger<C, T> {
+        self.debugger
+    }
+
+    // Accepts one connection on localhost and serves it until GDB detaches.
+    pub fn listen_tcp(&mut self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        self.serve(stream)
+    }
+
+    #[cfg(unix)]
+    pub fn listen_unix(&mut self, path: impl AsRef<std::path::Path>) -> io::Result<()> {
+        let listener = std::os::unix::net::UnixListener::bind(&path)?;
+        let result = listener.accept().and_then(|(stream, _)| self.serve(stream));
+        let _ = std::fs::remove_file(path);
+        result
+    }
+
+    // Handles packets until GDB detaches, kills the target or disconnects.
+    pub fn serve(&mut self, mut stream: impl Connection) -> io::Result<()> {
+        self.acknowledge = true;
+        while let Some(packet) = self.receive(&mut stream)? {
+            let Some(reply) = self.handle(&packet, &mut stream)? else {
+                return Ok(());
+            };
+            self.send(&mut stream, &reply)?;
+        }
+        Ok(())
+    }
+
+    // The description of the register file GDB asks for as target.xml.
+    pub fn target_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n  <feature name=\"org.avm.core\">\n");
+        for (index, (name, value)) in self.debugger.registers().into_iter().enumerate() {
+            let kind = match (name, value) {
+                (_, RegisterValue::F32(_)) => "ieee_single",
+                (_, RegisterValue::F64(_)) => "ieee_double",
+                ("pc", _) => "code_ptr",
+                ("sp" | "fp", _) => "data_ptr",
+                _ => "int",
+            };
+            let _ = writeln!(xml, "    <reg name=\"{name}\" bitsize=\"{}\" type=\"{kind}\" regnum=\"{index}\"/>", value.width());
+        }
+        xml + "  </feature>\n</target>\n"
+    }
+
+    // Reads up to the next packet, acknowledging it. None when the
+    // connection is closed.
+    fn receive(&mut self, stream: &mut impl Connection) -> io::Result<Option<String>> {
+        loop {
+            match Self::read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                // Acks and interrupts while stopped have nothing to do.
+                Some(_) => continue,
+            }
+            // The checksum covers the bytes as sent, escapes included.
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            let mut escaped = false;
+            loop {
+                let Some(byte) = Self::read_byte(stream)? else {
+                    return Ok(None);
+                };
+                if byte == b'#' && !escaped {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                match (escaped, byte) {
+                    (true, _) => data.push(byte ^ 0x20),
+                    (false, b'}') => {}
+                    (false, _) => data.push(byte),
+                }
+                escaped = !escaped && byte == b'}';
+            }
+            let mut checksum = [0; 2];
+            stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|x| u8::from_str_radix(x, 16).ok());
+            if self.acknowledge {
+                match expected == Some(sum) {
+                    true => stream.write_all(b"+")?,
+                    false => {
+                        stream.write_all(b"-")?;
+                        continue;
+                    }
+                }
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, stream: &mut impl Connection, data: &str) -> io::Result<()> {
+        let escaped = Self::escape(data.as_bytes());
+        let mut packet = vec![b'$'];
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", Self::checksum(&escaped)).as_bytes());
+        loop {
+            stream.write_all(&packet)?;
+            stream.flush()?;
+            if !self.acknowledge {
+                return Ok(());
+            }
+            match Self::read_byte(stream)? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_byte(stream: &mut impl Connection) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match stream.read(&mut byte) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(error) if error.kind() == ErrorKind::Interrupted => Self::read_byte(stream),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn escape(data: &[u8]) -> Vec<u8> {
+        let mut escaped = Vec::with_capacity(data.len());
+        for &byte in data {
+            match byte {
+                b'$' | b'#' | b'}' | b'*' => escaped.extend_from_slice(&[b'}', byte ^ 0x20]),
+                _ => escaped.push(byte),
+            }
+        }
+        escaped
+    }
+
+    fn checksum(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+    }
+
+    // The reply to `packet`, empty for packets that aren't supported. None
+    // ends the session.
+    fn handle(&mut self, packet: &str, stream: &mut impl Connection) -> io::Result<Option<String>> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => self.last_stop.clone(),
+            Some(b'g') => self.read_registers(),
+            Some(b'G') => Self::status(self.write_registers(&packet[1..])),
+            Some(b'p') => self.read_register(&packet[1..]).unwrap_or_else(|| "E01".to_string()),
+            Some(b'P') => Self::status(self.write_register(&packet[1..])),
+            Some(b'm') => self.read_memory(&packet[1..]).unwrap_or_else(|| "E01".to_string()),
+            Some(b'M') => Self::status(self.write_memory(&packet[1..])),
+            Some(b'Z') | Some(b'z') => self.breakpoint(packet),
+            Some(b's') | Some(b'c') => {
+                if let Some(address) = Self::parse_hex(&packet[1..]) {
+                    self.debugger.cpu_mut().set_pc(address);
+                }
+                let resumed = self.resume(packet.starts_with('s'), stream)?;
+                self.stop_reply(resumed)
+            }
+            Some(b'D') => {
+                self.send(stream, "OK")?;
+                return Ok(None);
+            }
+            Some(b'k') => return Ok(None),
+            Some(b'H') => "OK".to_string(),
+            Some(b'q') | Some(b'Q') => self.query(packet),
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+");
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = range.split_once(',').and_then(|(offset, length)| Some((Self::parse_hex(offset)? as usize, Self::parse_hex(length)? as usize))) else {
+                return "E01".to_string();
+            };
+            let xml = self.target_xml();
+            let start = offset.min(xml.len());
+            let end = start.saturating_add(length).min(xml.len());
+            return format!("{}{}", if end == xml.len() { 'l' } else { 'm' }, &xml[start..end]);
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.acknowledge = false;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn status(result: Option<()>) -> String {
+        match result {
+            Some(()) => "OK".to_string(),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn parse_hex(text: &str) -> Option<u64> {
+        u64::from_str_radix(text, 16).ok()
+    }
+
+    fn decode_hex(text: &str) -> Option<Vec<u8>> {
+        if !text.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+    }
+
+    fn encode_register(value: RegisterValue) -> String {
+        let bytes = value.width() as usize / 8;
+        value.to_bits().to_be_bytes()[16 - bytes..].iter().map(|x| format!("{x:02x}")).collect()
+    }
+
+    // `bytes` as a value of the same type as `current`.
+    fn decode_register(current: RegisterValue, bytes: &[u8]) -> RegisterValue {
+        let bits = bytes.iter().fold(0u128, |bits, &byte| bits << 8 | byte as u128);
+        match current {
+            RegisterValue::F32(_) => RegisterValue::F32(f32::from_bits(bits as u32)),
+            RegisterValue::F64(_) => RegisterValue::F64(f64::from_bits(bits as u64)),
+            _ => RegisterValue::from_bits(current.width(), bits),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        self.debugger.registers().into_iter().map(|(_, value)| Self::encode_register(value)).collect()
+    }
+
+    fn write_registers(&mut self, text: &str) -> Option<()> {
+        let mut bytes = Self::decode_hex(text)?;
+        for (index, (_, current)) in self.debugger.registers().into_iter().enumerate() {
+            let width = current.width() as usize / 8;
+            if bytes.len() < width {
+                return None;
+            }
+            let rest = bytes.split_off(width);
+            self.debugger.cpu_mut().set_register(index, Self::decode_register(current, &bytes)).ok()?;
+            bytes = rest;
+        }
+        Some(())
+    }
+
+    fn read_register(&self, text: &str) -> Option<String> {
+        let index = Self::parse_hex(text)? as usize;
+        self.debugger.registers().get(index).map(|&(_, value)| Self::encode_register(value))
+    }
+
+    fn write_register(&mut self, text: &str) -> Option<()> {
+        let (index, value) = text.split_once('=')?;
+        let index = Self::parse_hex(index)? as usize;
+        let current = self.debugger.registers().get(index)?.1;
+        let bytes = Self::decode_hex(value)?;
+        if bytes.len() != current.width() as usize / 8 {
+            return None;
+        }
+        self.debugger.cpu_mut().set_register(index, Self::decode_register(current, &bytes)).ok()
+    }
+
+    fn read_memory(&self, text: &str) -> Option<String> {
+        let (address, length) = text.split_once(',')?;
+        let length = (Self::parse_hex(length)? as usize).min(PACKET_SIZE / 2);
+        let bytes = self.debugger.read_memory(Self::parse_hex(address)?, length).ok()?;
+        Some(bytes.iter().map(|x| format!("{x:02x}")).collect())
+    }
+
+    fn write_memory(&mut self, text: &str) -> Option<()> {
+        let (range, data) = text.split_once(':')?;
+        let (address, length) = range.split_once(',')?;
+        let bytes = Self::decode_hex(data)?;
+        if bytes.len() as u64 != Self::parse_hex(length)? {
+            return None;
+        }
+        self.debugger.write_memory(Self::parse_hex(address)?, &bytes).ok()
+    }
+
+    // Only software breakpoints, `Z0,address,kind`.
+    fn breakpoint(&mut self, packet: &str) -> String {
+        let mut fields = packet[1..].split(',');
+        if fields.next() != Some("0") {
+            return String::new();
+        }
+        let Some(address) = fields.next().and_then(Self::parse_hex) else {
+            return "E01".to_string();
+        };
+        let existing = self.debugger.breakpoints().iter().find(|x| x.address == address && x.condition.is_none()).map(|x| x.id);
+        match (packet.starts_with('Z'), existing) {
+            (true, Some(_)) => "OK".to_string(),
+            (true, None) => Self::status(self.debugger.add_breakpoint(&format!("0x{address:x}"), None).ok().map(|_| ())),
+            (false, Some(id)) => Self::status(self.debugger.remove_breakpoint(id).then_some(())),
+            (false, None) => "OK".to_string(),
+        }
+    }
+
+    // Continues in slices, checking for the interrupt GDB sends as a single
+    // 0x03 byte in between. The debugger's cycle limit still counts for the
+    // whole run.
+    fn resume(&mut self, step: bool, stream: &mut impl Connection) -> io::Result<Resumed> {
+        if step {
+            return Ok(Resumed::Stopped(self.debugger.step()));
+        }
+        let limit = self.debugger.cycle_limit();
+        let mut left = limit;
+        let resumed = loop {
+            let slice = left.map_or(SLICE, |x| x.min(SLICE));
+            self.debugger.set_cycle_limit(Some(slice));
+            match self.debugger.cont() {
+                StopReason::CycleLimit if left == Some(slice) => break Ok(Resumed::Stopped(StopReason::CycleLimit)),
+                StopReason::CycleLimit => left = left.map(|x| x - slice),
+                reason => break Ok(Resumed::Stopped(reason)),
+            }
+            stream.set_nonblocking(true)?;
+            let mut byte = [0];
+            let read = stream.read(&mut byte);
+            stream.set_nonblocking(false)?;
+            match read {
+                Ok(1) if byte[0] == 0x03 => break Ok(Resumed::Interrupted),
+                Ok(0) => break Err(ErrorKind::UnexpectedEof.into()),
+                Ok(_) => {}
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                Err(error) => break Err(error),
+            }
+        };
+        self.debugger.set_cycle_limit(limit);
+        resumed
+    }
+
+    // A halted CPU is reported as the program exiting.
+    fn stop_reply(&mut self, resumed: Resumed) -> String {
+        let signal = match resumed {
+            Resumed::Interrupted => SIGINT,
+            Resumed::Stopped(StopReason::Cpu(StepResult::Halted)) => {
+                self.last_stop = "W00".to_string();
+                return self.last_stop.clone();
+            }
+            Resumed::Stopped(StopReason::Cpu(StepResult::Fault(fault))) => match fault {
+                CPUFault::IllegalInstruction { .. } => SIGILL,
+                CPUFault::DivideByZero { .. } => SIGFPE,
+                CPUFault::MemoryFault { .. } | CPUFault::StackFault { .. } => SIGSEGV,
+            },
+            Resumed::Stopped(_) => SIGTRAP,
+        };
+        self.last_stop = format!("S{signal:02x}");
+        self.last_stop.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::VecDeque};
+
+    use avm_rs_component::register_machine::RegisterCPU;
+    use avm_rs_memory::mem::create_memory;
+
+    use crate::{assembler::Assembler, target::RegisterMachineTarget};
+
+    use super::*;
+
+    type Stub = GdbStub<RegisterCPU, RegisterMachineTarget>;
+
+    const PROGRAM: &str = "start: addi r1, r1, #1\n b start\n";
+
+    // GDB's side of the connection, reading past `input` blocks or ends it.
+    struct Pipe {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+        nonblocking: Cell<bool>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            if self.input.is_empty() && self.nonblocking.get() {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            self.input.read(buffer)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.output.write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // `serve` takes the connection, the test keeps the pipe to look at it.
+    impl Connection for &mut Pipe {
+        fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+            self.nonblocking.set(nonblocking);
+            Ok(())
+        }
+    }
+
+    fn stub() -> Stub {
+        let memory = create_memory(0x1000);
+        Assembler::new(RegisterMachineTarget).assemble_into(PROGRAM, &memory).unwrap();
+        GdbStub::new(Debugger::new(RegisterCPU::new(memory.clone(), 0), memory, RegisterMachineTarget))
+    }
+
+    fn serve(stub: &mut Stub, input: &[u8]) -> Vec<u8> {
+        let mut pipe = Pipe { input: input.iter().copied().collect(), output: Vec::new(), nonblocking: Cell::new(false) };
+        stub.serve(&mut pipe).unwrap();
+        pipe.output
+    }
+
+    fn frame(data: &str) -> String {
+        let escaped = Stub::escape(data.as_bytes());
+        format!("${}#{:02x}", String::from_utf8(escaped.clone()).unwrap(), Stub::checksum(&escaped))
+    }
+
+    // Checks the checksum of every reply in `output` and unescapes it.
+    fn replies(output: &[u8]) -> Vec<String> {
+        let mut replies = Vec::new();
+        let mut rest = output;
+        while let Some(start) = rest.iter().position(|&x| x == b'$') {
+            let end = start + rest[start..].iter().position(|&x| x == b'#').unwrap();
+            let data = &rest[start + 1..end];
+            assert_eq!(&rest[end + 1..end + 3], format!("{:02x}", Stub::checksum(data)).as_bytes());
+            let mut reply = Vec::new();
+            let mut bytes = data.iter();
+            while let Some(&byte) = bytes.next() {
+                reply.push(if byte == b'}' { bytes.next().unwrap() ^ 0x20 } else { byte });
+            }
+            replies.push(String::from_utf8(reply).unwrap());
+            rest = &rest[end + 3..];
+        }
+        replies
+    }
+
+    // Sends `packets` without acknowledgements and returns the replies.
+    fn session(stub: &mut Stub, packets: &[&str]) -> Vec<String> {
+        let input: String = std::iter::once("QStartNoAckMode").chain(packets.iter().copied()).map(frame).collect();
+        let mut replies = replies(&serve(stub, input.as_bytes()));
+        assert_eq!(replies.remove(0), "OK");
+        replies
+    }
+
+    #[test]
+    fn acknowledges_packets_by_checksum() {
+        assert_eq!(serve(&mut stub(), b"$?#00$?#3f+"), b"-+$S05#b8");
+    }
+
+    #[test]
+    fn escapes_packets() {
+        assert_eq!(Stub::escape(b"a#b$c}d*"), b"a}\x03b}\x04c}]d}\x0a");
+        // `p1` with its `p` escaped.
+        let input = format!("{}$}}P1#{:02x}", frame("QStartNoAckMode"), Stub::checksum(b"}P1"));
+        assert_eq!(replies(&serve(&mut stub(), input.as_bytes())), ["OK", "0000000000000000"]);
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let mut stub = stub();
+        let replies = session(&mut stub, &["P1=00000000000000ff", "p1", "P1=ff", "p99", "g"]);
+        assert_eq!(replies[..4], ["OK", "00000000000000ff", "E01", "E01"]);
+        // 32 registers of 64 bits, 32-bit flags and the 64-bit pc.
+        let registers = &replies[4];
+        assert_eq!(registers.len(), (32 * 8 + 4 + 8) * 2);
+        assert_eq!(registers[16..32], *"00000000000000ff");
+
+        let written = format!("G{}0000000000000102{}", &registers[..32], &registers[48..]);
+        assert_eq!(session(&mut stub, &[&written, "p2", "p21"]), ["OK", "0000000000000102", "0000000000000000"]);
+        assert_eq!(session(&mut stub, &["G00"]), ["E01"]);
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let replies = session(&mut stub(), &["M800,4:deadbeef", "m800,4", "m802,2", "M800,2:aa", "mffffffff,4"]);
+        assert_eq!(replies, ["OK", "deadbeef", "beef", "E01", "E01"]);
+    }
+
+    #[test]
+    fn sets_and_clears_breakpoints() {
+        let mut stub = stub();
+        assert_eq!(session(&mut stub, &["Z0,4,4", "Z0,4,4", "c"]), ["OK", "OK", "S05"]);
+        assert_eq!(stub.debugger().breakpoints().len(), 1);
+        assert_eq!(stub.debugger().cpu().pc(), 4);
+        assert_eq!(session(&mut stub, &["z0,4,4", "z0,4,4", "Z1,4,4"]), ["OK", "OK", ""]);
+        assert!(stub.debugger().breakpoints().is_empty());
+    }
+
+    #[test]
+    fn continuing_counts_the_cycle_limit_across_slices() {
+        let mut stub = GdbStub::new(stub().into_debugger().with_cycle_limit(2 * SLICE + 1));
+        // Bytes that come in while running are polled for an interrupt, so
+        // `c` goes last.
+        assert_eq!(session(&mut stub, &["c"]), ["S05"]);
+        assert_eq!(session(&mut stub, &["p1"]), [format!("{:016x}", SLICE + 1)]);
+        assert_eq!(stub.debugger().cycle_limit(), Some(2 * SLICE + 1));
+    }
+
+    #[test]
+    fn transfers_the_target_description() {
+        let mut stub = stub();
+        let xml = stub.target_xml();
+        let replies = session(&mut stub, &["qXfer:features:read:target.xml:0,10", &format!("qXfer:features:read:target.xml:10,{:x}", xml.len()), "qXfer:features:read:target.xml:x"]);
+        assert_eq!(replies, [format!("m{}", &xml[..0x10]), format!("l{}", &xml[0x10..]), "E01".to_string()]);
+    }
+}
@@ -0,0 +1,491 @@
+use std::{fmt::Display, fs, io, path::Path};
+
+use avm_rs_component::define_flags;
+use avm_rs_memory::mem::MemorySliceTrait;
+
+use crate::assembler::{AssembledProgram, RelocationTarget, BSS, DATA, TEXT};
+
+pub const IMAGE_MAGIC: [u8; 4] = *b"AVMX";
+pub const IMAGE_VERSION: u16 = 1;
+
+define_flags!(Protection, u8, {
+    READ = 0,
+    WRITE = 1,
+    EXECUTE = 2,
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Code,
+    Data,
+    // Zero-filled when loaded, the image only stores its size.
+    Bss,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub kind: SectionKind,
+    pub protection: Protection,
+    // Link address, the one the section's contents were assembled for.
+    pub address: u64,
+    pub size: u64,
+    pub alignment: u64,
+    // Empty for `Bss`, `size` bytes otherwise.
+    pub bytes: Vec<u8>,
+}
+
+// `value` is an offset into `section`, or a plain number without one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Option<usize>,
+    pub value: u64,
+    pub global: bool,
+}
+
+// How a relocated address is written back. The absolute kinds use the same
+// byte order as `MemorySliceTrait`, `Field` replaces bits `start..end` of a
+// big-endian 32-bit instruction word and `Leb128` rewrites a LEB128 value
+// padded to `length` bytes. `Relative` is a signed field holding the distance
+// from the field to the address, shifted right by `shift`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+    Absolute16,
+    Absolute32,
+    Absolute64,
+    Field { start: u8, end: u8, signed: bool },
+    Leb128 { length: u8, signed: bool },
+    Relative { start: u8, end: u8, shift: u8 },
+}
+
+fn fits(value: i64, bits: u32, signed: bool) -> bool {
+    if bits >= 64 {
+        return true;
+    }
+    if signed {
+        value >= -(1i64 << (bits - 1)) && value < (1i64 << (bits - 1))
+    } else {
+        value >= 0 && value < (1i64 << bits)
+    }
+}
+
+// `value` as exactly `length` LEB128 bytes, padding with continuation bytes.
+// None when it doesn't fit.
+pub fn padded_leb128(value: i64, length: usize, signed: bool) -> Option<Vec<u8>> {
+    if !(1..=10).contains(&length) || !fits(value, 7 * length as u32, signed) {
+        return None;
+    }
+    Some((0..length).map(|index| {
+        let byte = (value >> (7 * index)) as u8 & 0x7F;
+        if index + 1 < length { byte | 0x80 } else { byte }
+    }).collect())
+}
+
+impl RelocationKind {
+    // Bytes the relocated field spans.
+    pub fn length(&self) -> usize {
+        match *self {
+            RelocationKind::Absolute16 => 2,
+            RelocationKind::Absolute32 | RelocationKind::Field { .. } | RelocationKind::Relative { .. } => 4,
+            RelocationKind::Absolute64 => 8,
+            RelocationKind::Leb128 { length, .. } => length as usize,
+        }
+    }
+
+    pub fn is_relative(&self) -> bool {
+        matches!(self, RelocationKind::Relative { .. })
+    }
+
+    // Writes `value` at `at`, returns false when it doesn't fit. Relative
+    // kinds take the distance from `at`.
+    pub fn apply(&self, memory: &mut dyn MemorySliceTrait, at: usize, value: i64) -> bool {
+        match *self {
+            RelocationKind::Relative { start, end, shift } if value & ((1 << shift) - 1) == 0 => {
+                return RelocationKind::Field { start, end, signed: true }.apply(memory, at, value >> shift);
+            }
+            RelocationKind::Absolute16 if fits(value, 16, false) => memory.write_u16(at, value as u16),
+            RelocationKind::Absolute32 if fits(value, 32, false) => memory.write_u32(at, value as u32),
+            RelocationKind::Absolute64 => memory.write_u64(at, value as u64),
+            RelocationKind::Field { start, end, signed } if fits(value, (end - start) as u32, signed) => {
+                let mask = (u32::MAX >> (32 - (end - start))) << start;
+                let word = memory.read_u32(at) & !mask | ((value as u32) << start) & mask;
+                memory.write_u32(at, word);
+            }
+            RelocationKind::Leb128 { length, signed } => match padded_leb128(value, length as usize, signed) {
+                Some(bytes) => memory.write_bytes(at, &bytes),
+                None => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+// Writes the address of `symbol` plus `addend` into the field `offset` bytes
+// into `section`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: usize,
+    pub offset: u64,
+    pub kind: RelocationKind,
+    pub symbol: usize,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    Malformed(String),
+    RelocationOverflow { section: String, offset: u64, value: i64 },
+    OutOfMemory { section: String, size: u64 },
+    UnresolvedImport(String),
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::BadMagic => write!(f, "Unknown file format"),
+            ImageError::UnsupportedVersion(version) => write!(f, "Format version {version} isn't supported"),
+            ImageError::Truncated => write!(f, "File is truncated"),
+            ImageError::Malformed(message) => write!(f, "Malformed file: {message}"),
+            ImageError::RelocationOverflow { section, offset, value } => write!(f, "Relocated value 0x{value:X} doesn't fit at {section}+0x{offset:X}"),
+            ImageError::OutOfMemory { section, size } => write!(f, "Not enough memory left for section {section} of 0x{size:X} bytes"),
+            ImageError::UnresolvedImport(name) => write!(f, "Program imports '{name}', it has to be linked"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<ImageError> for io::Error {
+    fn from(error: ImageError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+// On disk, all numbers big-endian:
+//
+//   magic "AVMX", version u16, flags u16, entry u64,
+//   section, symbol and relocation counts u32
+//   sections:    name, kind u8, protection u8, address u64, size u64,
+//                alignment u64, then `size` bytes unless it's bss
+//   symbols:     name, section u32 (u32::MAX when absolute), value u64,
+//                flags u8 (bit 0: global)
+//   relocations: section u32, offset u64, kind u8 with three parameter
+//                bytes, symbol u32, addend i64
+//
+// Names are a u16 length followed by UTF-8. Object files share the section
+// and relocation encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    // Link address of the first instruction.
+    pub entry: u64,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+pub(crate) const ABSOLUTE: u32 = u32::MAX;
+pub(crate) const GLOBAL: u8 = 1;
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    // Checks the magic and version at the start.
+    pub(crate) fn header(&mut self, magic: [u8; 4], version: u16) -> Result<(), ImageError> {
+        if self.take(4).map_err(|_| ImageError::BadMagic)? != magic {
+            return Err(ImageError::BadMagic);
+        }
+        match self.u16()? {
+            found if found == version => Ok(()),
+            found => Err(ImageError::UnsupportedVersion(found)),
+        }
+    }
+
+    pub(crate) fn finish(&self) -> Result<(), ImageError> {
+        match self.bytes.len() - self.position {
+            0 => Ok(()),
+            trailing => Err(ImageError::Malformed(format!("{trailing} trailing bytes"))),
+        }
+    }
+
+    pub(crate) fn take(&mut self, length: usize) -> Result<&[u8], ImageError> {
+        let end = self.position.checked_add(length).filter(|&end| end <= self.bytes.len()).ok_or(ImageError::Truncated)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, ImageError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, ImageError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, ImageError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, ImageError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn name(&mut self) -> Result<String, ImageError> {
+        let length = self.u16()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| ImageError::Malformed("name isn't UTF-8".to_string()))
+    }
+}
+
+// The non-empty sections of a program at their link addresses, and the index
+// each of the program's sections ended up at.
+pub(crate) fn program_sections(program: &AssembledProgram) -> (Vec<Section>, Vec<Option<usize>>) {
+    let mut sections = Vec::new();
+    let mut indices = vec![None; program.sections.len()];
+    for (index, section) in program.sections.iter().enumerate().filter(|(_, x)| x.size > 0) {
+        let (kind, protection) = match index {
+            TEXT => (SectionKind::Code, Protection::READ | Protection::EXECUTE),
+            DATA => (SectionKind::Data, Protection::READ | Protection::WRITE),
+            BSS => (SectionKind::Bss, Protection::READ | Protection::WRITE),
+            _ => unreachable!(),
+        };
+        indices[index] = Some(sections.len());
+        sections.push(Section {
+            name: section.name.to_string(),
+            kind,
+            protection: Protection(protection),
+            address: section.address,
+            size: section.size,
+            alignment: 16,
+            bytes: section.bytes.clone(),
+        });
+    }
+    (sections, indices)
+}
+
+pub(crate) fn write_name(output: &mut Vec<u8>, name: &str) {
+    output.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    output.extend_from_slice(name.as_bytes());
+}
+
+pub(crate) fn write_section(output: &mut Vec<u8>, section: &Section) {
+    write_name(output, &section.name);
+    output.push(match section.kind {
+        SectionKind::Code => 0,
+        SectionKind::Data => 1,
+        SectionKind::Bss => 2,
+    });
+    output.push(section.protection.0);
+    for value in [section.address, section.size, section.alignment] {
+        output.extend_from_slice(&value.to_be_bytes());
+    }
+    output.extend_from_slice(&section.bytes);
+}
+
+pub(crate) fn read_section(reader: &mut Reader) -> Result<Section, ImageError> {
+    let name = reader.name()?;
+    let kind = match reader.u8()? {
+        0 => SectionKind::Code,
+        1 => SectionKind::Data,
+        2 => SectionKind::Bss,
+        other => return Err(ImageError::Malformed(format!("section {name} has unknown kind {other}"))),
+    };
+    let protection = Protection(reader.u8()?);
+    let (address, size, alignment) = (reader.u64()?, reader.u64()?, reader.u64()?);
+    let bytes = match kind {
+        SectionKind::Bss => Vec::new(),
+        _ => reader.take(usize::try_from(size).map_err(|_| ImageError::Truncated)?)?.to_vec(),
+    };
+    Ok(Section { name, kind, protection, address, size, alignment, bytes })
+}
+
+pub(crate) fn write_relocation(output: &mut Vec<u8>, relocation: &Relocation) {
+    output.extend_from_slice(&(relocation.section as u32).to_be_bytes());
+    output.extend_from_slice(&relocation.offset.to_be_bytes());
+    output.extend_from_slice(&match relocation.kind {
+        RelocationKind::Absolute16 => [0, 0, 0, 0],
+        RelocationKind::Absolute32 => [1, 0, 0, 0],
+        RelocationKind::Absolute64 => [2, 0, 0, 0],
+        RelocationKind::Field { start, end, signed } => [3, start, end, signed as u8],
+        RelocationKind::Leb128 { length, signed } => [4, length, signed as u8, 0],
+        RelocationKind::Relative { start, end, shift } => [5, start, end, shift],
+    });
+    output.extend_from_slice(&(relocation.symbol as u32).to_be_bytes());
+    output.extend_from_slice(&relocation.addend.to_be_bytes());
+}
+
+pub(crate) fn read_relocation(reader: &mut Reader) -> Result<Relocation, ImageError> {
+    let section = reader.u32()? as usize;
+    let offset = reader.u64()?;
+    let parameters = reader.take(4)?;
+    let field = parameters[1] < parameters[2] && parameters[2] <= 32;
+    let kind = match parameters[0] {
+        0 => RelocationKind::Absolute16,
+        1 => RelocationKind::Absolute32,
+        2 => RelocationKind::Absolute64,
+        3 if field => RelocationKind::Field { start: parameters[1], end: parameters[2], signed: parameters[3] != 0 },
+        4 if (1..=10).contains(&parameters[1]) => RelocationKind::Leb128 { length: parameters[1], signed: parameters[2] != 0 },
+        5 if field && parameters[3] < 32 => RelocationKind::Relative { start: parameters[1], end: parameters[2], shift: parameters[3] },
+        _ => return Err(ImageError::Malformed(format!("invalid relocation kind {parameters:02X?}"))),
+    };
+    let symbol = reader.u32()? as usize;
+    let addend = reader.u64()? as i64;
+    Ok(Relocation { section, offset, kind, symbol, addend })
+}
+
+// Checks that a relocation is inside a section that has contents.
+pub(crate) fn validate_relocation(sections: &[Section], relocation: &Relocation) -> Result<(), ImageError> {
+    let Some(section) = sections.get(relocation.section) else {
+        return Err(ImageError::Malformed(format!("relocation in missing section {}", relocation.section)));
+    };
+    if section.kind == SectionKind::Bss || relocation.offset.saturating_add(relocation.kind.length() as u64) > section.size {
+        return Err(ImageError::Malformed(format!("relocation at {}+0x{:X} is out of range", section.name, relocation.offset)));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_section(section: &Section) -> Result<(), ImageError> {
+    if !section.alignment.is_power_of_two() {
+        return Err(ImageError::Malformed(format!("section {} has alignment {}", section.name, section.alignment)));
+    }
+    let stored = if section.kind == SectionKind::Bss { 0 } else { section.size };
+    if section.bytes.len() as u64 != stored {
+        return Err(ImageError::Malformed(format!("section {} holds {} bytes, but its size is {}", section.name, section.bytes.len(), section.size)));
+    }
+    Ok(())
+}
+
+impl Image {
+    // An image of an assembled program starting at `entry`. Every section
+    // gets a symbol of its own name for relocations to refer to. Empty
+    // sections are left out, labels in them become plain numbers. Programs
+    // that import symbols have to go through the linker instead.
+    pub fn from_program(program: &AssembledProgram, entry: u64) -> Result<Self, ImageError> {
+        let (sections, indices) = program_sections(program);
+        let mut image = Image { entry, sections, symbols: Vec::new(), relocations: Vec::new() };
+        for (index, section) in image.sections.iter().enumerate() {
+            image.symbols.push(Symbol { name: section.name.clone(), section: Some(index), value: 0, global: false });
+        }
+        for (name, &value) in &program.symbols {
+            let section = program.symbol_sections.get(name).and_then(|&x| indices[x].map(|index| (x, index)));
+            let global = program.globals.contains(name);
+            image.symbols.push(match section {
+                Some((assembled, index)) => Symbol { name: name.clone(), section: Some(index), value: value - program.sections[assembled].address, global },
+                None => Symbol { name: name.clone(), section: None, value, global },
+            });
+        }
+        for relocation in &program.relocations {
+            let target = match &relocation.target {
+                RelocationTarget::Section(target) => indices[*target],
+                RelocationTarget::External(name) => return Err(ImageError::UnresolvedImport(name.clone())),
+            };
+            let (Some(section), Some(target)) = (indices[relocation.section], target) else {
+                continue;
+            };
+            image.relocations.push(Relocation {
+                section,
+                offset: relocation.offset,
+                kind: relocation.kind,
+                symbol: target,
+                addend: relocation.addend,
+            });
+        }
+        Ok(image)
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|x| x.name == name)
+    }
+
+    // Checks that every index is in range and every relocation fits in its
+    // section.
+    pub fn validate(&self) -> Result<(), ImageError> {
+        for section in &self.sections {
+            validate_section(section)?;
+        }
+        for symbol in &self.symbols {
+            if symbol.section.is_some_and(|x| x >= self.sections.len()) {
+                return Err(ImageError::Malformed(format!("symbol {} is in a missing section", symbol.name)));
+            }
+        }
+        for relocation in &self.relocations {
+            if relocation.symbol >= self.symbols.len() {
+                return Err(ImageError::Malformed(format!("relocation against missing symbol {}", relocation.symbol)));
+            }
+            validate_relocation(&self.sections, relocation)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.extend_from_slice(&IMAGE_MAGIC);
+        output.extend_from_slice(&IMAGE_VERSION.to_be_bytes());
+        output.extend_from_slice(&0u16.to_be_bytes());
+        output.extend_from_slice(&self.entry.to_be_bytes());
+        for count in [self.sections.len(), self.symbols.len(), self.relocations.len()] {
+            output.extend_from_slice(&(count as u32).to_be_bytes());
+        }
+        for section in &self.sections {
+            write_section(&mut output, section);
+        }
+        for symbol in &self.symbols {
+            write_name(&mut output, &symbol.name);
+            output.extend_from_slice(&symbol.section.map_or(ABSOLUTE, |x| x as u32).to_be_bytes());
+            output.extend_from_slice(&symbol.value.to_be_bytes());
+            output.push(if symbol.global { GLOBAL } else { 0 });
+        }
+        for relocation in &self.relocations {
+            write_relocation(&mut output, relocation);
+        }
+        output
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ImageError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(IMAGE_MAGIC, IMAGE_VERSION)?;
+        reader.u16()?;
+        let entry = reader.u64()?;
+        let (sections, symbols, relocations) = (reader.u32()?, reader.u32()?, reader.u32()?);
+        let mut image = Image { entry, sections: Vec::new(), symbols: Vec::new(), relocations: Vec::new() };
+
+        for _ in 0..sections {
+            image.sections.push(read_section(&mut reader)?);
+        }
+        for _ in 0..symbols {
+            let name = reader.name()?;
+            let section = match reader.u32()? {
+                ABSOLUTE => None,
+                index => Some(index as usize),
+            };
+            let value = reader.u64()?;
+            let global = reader.u8()? & GLOBAL != 0;
+            image.symbols.push(Symbol { name, section, value, global });
+        }
+        for _ in 0..relocations {
+            image.relocations.push(read_relocation(&mut reader)?);
+        }
+        reader.finish()?;
+        image.validate()?;
+        Ok(image)
+    }
+
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn read_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::from_bytes(&fs::read(path)?)?)
+    }
+}
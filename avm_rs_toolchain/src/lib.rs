@@ -0,0 +1,10 @@
+pub mod assembler;
+pub mod debugger;
+pub mod disassembler;
+pub mod gdb;
+pub mod image;
+pub mod linker;
+pub mod loader;
+pub mod object;
+pub mod repl;
+pub mod target;
@@ -0,0 +1,379 @@
+use std::{collections::HashMap, fmt::Display};
+
+use avm_rs_memory::mem::{MemorySliceTrait, _Memory};
+
+use crate::{
+    image::{Image, Protection, Relocation, Section, SectionKind, Symbol},
+    object::{Binding, Object},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    UndefinedSymbol { name: String, object: String },
+    DuplicateSymbol { name: String, first: String, second: String },
+    UndefinedEntry(String),
+    ConflictingSection { name: String, object: String, kind: SectionKind, expected: SectionKind },
+    OverlappingSections { first: String, second: String },
+    SectionOverflow { name: String, address: u64, size: u64 },
+    SymbolOverflow { name: String, section: String, value: u64 },
+    RelocationOverflow { object: String, section: String, offset: u64, value: i64 },
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::UndefinedSymbol { name, object } => write!(f, "Undefined symbol '{name}' referenced in {object}"),
+            LinkError::DuplicateSymbol { name, first, second } => write!(f, "Duplicate symbol '{name}' defined in {first} and {second}"),
+            LinkError::UndefinedEntry(name) => write!(f, "Entry symbol '{name}' isn't defined"),
+            LinkError::ConflictingSection { name, object, kind, expected } => write!(f, "Section {name} in {object} is {kind:?}, but {expected:?} elsewhere"),
+            LinkError::OverlappingSections { first, second } => write!(f, "Sections {first} and {second} overlap"),
+            LinkError::SectionOverflow { name, address, size } => write!(f, "Section {name} at 0x{address:X} with 0x{size:X} bytes doesn't fit in the address space"),
+            LinkError::SymbolOverflow { name, section, value } => write!(f, "Symbol '{name}' at {section}+0x{value:X} doesn't fit in the address space"),
+            LinkError::RelocationOverflow { object, section, offset, value } => write!(f, "Relocated value 0x{value:X} doesn't fit at {section}+0x{offset:X} in {object}"),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+// Input sections with the same name are concatenated into one output section,
+// each aligned to its own alignment. Code goes first, then data and bss, each
+// at its configured base or right after the section before it.
+struct OutputSection {
+    name: String,
+    kind: SectionKind,
+    protection: u8,
+    alignment: u64,
+    address: u64,
+    size: u64,
+}
+
+// Merges objects into an executable image. Exported symbols are visible to
+// every object, local ones only to their own. The image keeps every
+// relocation so the loader can still move it.
+pub struct Linker {
+    objects: Vec<(String, Object)>,
+    bases: Vec<(String, u64)>,
+    entry: String,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self { objects: Vec::new(), bases: Vec::new(), entry: "start".to_string() }
+    }
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_base(mut self, section: &str, address: u64) -> Self {
+        self.bases.retain(|(name, _)| name != section);
+        self.bases.push((section.to_string(), address));
+        self
+    }
+
+    pub fn with_entry(mut self, symbol: &str) -> Self {
+        self.entry = symbol.to_string();
+        self
+    }
+
+    // `name` is how errors refer to the object, usually its file name.
+    pub fn add(&mut self, name: &str, object: Object) {
+        self.objects.push((name.to_string(), object));
+    }
+
+    pub fn with_object(mut self, name: &str, object: Object) -> Self {
+        self.add(name, object);
+        self
+    }
+
+    pub fn link(&self) -> Result<Image, Vec<LinkError>> {
+        let mut errors = Vec::new();
+        let (sections, placements) = self.layout(&mut errors);
+        let mut image = Image { entry: 0, sections: Vec::new(), symbols: Vec::new(), relocations: Vec::new() };
+        for (index, section) in sections.iter().enumerate() {
+            image.symbols.push(Symbol { name: section.name.clone(), section: Some(index), value: 0, global: false });
+        }
+
+        // Every input symbol becomes an output symbol and an addend, section
+        // symbols turn into the output section's plus where they start in it.
+        let mut exports: HashMap<&str, (usize, usize)> = HashMap::new();
+        let mut resolved: Vec<Vec<Option<(usize, i64)>>> = Vec::new();
+        for (index, (object_name, object)) in self.objects.iter().enumerate() {
+            let mut symbols = Vec::new();
+            for symbol in &object.symbols {
+                let placement = symbol.section.map(|x| placements[index][x]);
+                if let Some((output, offset)) = placement.filter(|_| symbol.value == 0 && symbol.binding == Binding::Local && object.sections[symbol.section.unwrap()].name == symbol.name) {
+                    symbols.push(Some((output, offset as i64)));
+                    continue;
+                }
+                if symbol.binding == Binding::Imported {
+                    symbols.push(None);
+                    continue;
+                }
+                if symbol.binding == Binding::Exported {
+                    if let Some(&(first, _)) = exports.get(symbol.name.as_str()) {
+                        errors.push(LinkError::DuplicateSymbol { name: symbol.name.clone(), first: self.objects[first].0.clone(), second: object_name.clone() });
+                    } else {
+                        exports.insert(&symbol.name, (index, image.symbols.len()));
+                    }
+                }
+                image.symbols.push(match placement {
+                    Some((output, offset)) => Symbol { name: symbol.name.clone(), section: Some(output), value: offset + symbol.value, global: symbol.binding == Binding::Exported },
+                    None => Symbol { name: symbol.name.clone(), section: None, value: symbol.value, global: symbol.binding == Binding::Exported },
+                });
+                symbols.push(Some((image.symbols.len() - 1, 0)));
+            }
+            resolved.push(symbols);
+        }
+        // Imports nothing refers to may stay undefined.
+        for (index, (object_name, object)) in self.objects.iter().enumerate() {
+            for (symbol_index, symbol) in object.symbols.iter().enumerate().filter(|(_, x)| x.binding == Binding::Imported) {
+                match exports.get(symbol.name.as_str()) {
+                    Some(&(_, output)) => resolved[index][symbol_index] = Some((output, 0)),
+                    None if object.relocations.iter().any(|x| x.symbol == symbol_index) => {
+                        errors.push(LinkError::UndefinedSymbol { name: symbol.name.clone(), object: object_name.clone() });
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        let address = |image: &Image, symbol: usize| {
+            let symbol = &image.symbols[symbol];
+            match symbol.section {
+                Some(x) => sections[x].address.checked_add(symbol.value).ok_or_else(|| LinkError::SymbolOverflow { name: symbol.name.clone(), section: sections[x].name.clone(), value: symbol.value }),
+                None => Ok(symbol.value),
+            }
+        };
+        match exports.get(self.entry.as_str()).map(|x| x.1).or_else(|| image.symbols.iter().position(|x| x.name == self.entry && !x.global)) {
+            Some(symbol) => match address(&image, symbol) {
+                Ok(entry) => image.entry = entry,
+                Err(error) => errors.push(error),
+            },
+            None => errors.push(LinkError::UndefinedEntry(self.entry.clone())),
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut contents: Vec<_> = sections.iter().map(|x| _Memory::new(if x.kind == SectionKind::Bss { 0 } else { x.size as usize })).collect();
+        for (index, (object_name, object)) in self.objects.iter().enumerate() {
+            for (section_index, section) in object.sections.iter().enumerate().filter(|(_, x)| x.kind != SectionKind::Bss) {
+                let (output, offset) = placements[index][section_index];
+                contents[output].write_bytes(offset as usize, &section.bytes);
+            }
+            for relocation in &object.relocations {
+                let (output, offset) = placements[index][relocation.section];
+                let (symbol, addend) = resolved[index][relocation.symbol].unwrap();
+                let relocation = Relocation { section: output, offset: offset + relocation.offset, symbol, addend: relocation.addend + addend, ..*relocation };
+                let target = match address(&image, symbol) {
+                    Ok(target) => target,
+                    Err(error) => {
+                        errors.push(error);
+                        continue;
+                    }
+                };
+                let mut value = (target as i64).wrapping_add(relocation.addend);
+                if relocation.kind.is_relative() {
+                    value = value.wrapping_sub((sections[output].address + relocation.offset) as i64);
+                }
+                if !relocation.kind.apply(&mut contents[output], relocation.offset as usize, value) {
+                    let section = object.sections[relocation.section.min(object.sections.len() - 1)].name.clone();
+                    errors.push(LinkError::RelocationOverflow { object: object_name.clone(), section, offset: relocation.offset - offset, value });
+                }
+                image.relocations.push(relocation);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for (section, contents) in sections.into_iter().zip(contents) {
+            image.sections.push(Section {
+                bytes: contents.read_bytes(0, contents.len()),
+                name: section.name,
+                kind: section.kind,
+                protection: Protection(section.protection),
+                address: section.address,
+                size: section.size,
+                alignment: section.alignment,
+            });
+        }
+        Ok(image)
+    }
+
+    // Output sections, and for every input section the output section it
+    // went to with its offset there.
+    fn layout(&self, errors: &mut Vec<LinkError>) -> (Vec<OutputSection>, Vec<Vec<(usize, u64)>>) {
+        let mut sections: Vec<OutputSection> = Vec::new();
+        let mut placements = Vec::new();
+        for (object_name, object) in &self.objects {
+            let mut placement = Vec::new();
+            for section in &object.sections {
+                let index = match sections.iter().position(|x| x.name == section.name) {
+                    Some(index) => index,
+                    None => {
+                        sections.push(OutputSection { name: section.name.clone(), kind: section.kind, protection: 0, alignment: 1, address: 0, size: 0 });
+                        sections.len() - 1
+                    }
+                };
+                let output = &mut sections[index];
+                if output.kind != section.kind {
+                    errors.push(LinkError::ConflictingSection { name: section.name.clone(), object: object_name.clone(), kind: section.kind, expected: output.kind });
+                }
+                let offset = output.size.div_ceil(section.alignment) * section.alignment;
+                output.size = offset + section.size;
+                output.alignment = output.alignment.max(section.alignment);
+                output.protection |= section.protection.0;
+                placement.push((index, offset));
+            }
+            placements.push(placement);
+        }
+
+        // Sorting moves the output sections, so the placements follow them.
+        let mut order: Vec<usize> = (0..sections.len()).collect();
+        order.sort_by_key(|&index| sections[index].kind as u8);
+        let mut moved_to = vec![0; sections.len()];
+        for (position, &index) in order.iter().enumerate() {
+            moved_to[index] = position;
+        }
+        let mut sorted: Vec<Option<OutputSection>> = sections.into_iter().map(Some).collect();
+        let mut sections: Vec<OutputSection> = order.iter().map(|&index| sorted[index].take().unwrap()).collect();
+        for placement in placements.iter_mut().flatten() {
+            placement.0 = moved_to[placement.0];
+        }
+
+        // A section that doesn't fit is reported once, the ones following it
+        // without a base of their own can't be placed either. They're all
+        // left out of the overlap check.
+        let mut next = Some(0u64);
+        for section in &mut sections {
+            let address = match self.bases.iter().find(|(name, _)| *name == section.name) {
+                Some(&(_, base)) => Some(base),
+                None => next.and_then(|x| x.checked_next_multiple_of(section.alignment)),
+            };
+            let end = address.and_then(|x| x.checked_add(section.size));
+            if let (None, Some(start)) = (end, address.or(next)) {
+                errors.push(LinkError::SectionOverflow { name: section.name.clone(), address: start, size: section.size });
+            }
+            section.address = address.unwrap_or(u64::MAX);
+            next = end;
+        }
+        let mut by_address: Vec<&OutputSection> = sections.iter().filter(|x| x.size > 0 && x.address.checked_add(x.size).is_some()).collect();
+        by_address.sort_by_key(|x| x.address);
+        for pair in by_address.windows(2) {
+            if pair[0].address + pair[0].size > pair[1].address {
+                errors.push(LinkError::OverlappingSections { first: pair[0].name.clone(), second: pair[1].name.clone() });
+            }
+        }
+        (sections, placements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use avm_rs_memory::mem::{MemorySliceTrait, _Memory};
+
+    use super::{LinkError, Linker};
+    use crate::{assembler::Assembler, image::Image, object::Object, target::RegisterMachineTarget};
+
+    const MAIN: &str = "
+        .global start
+        .extern value
+start:  halt
+        .data
+first:  .byte 1
+        .dword value
+";
+
+    const VALUE: &str = "
+        .global value
+        .data
+value:  .word 0x12345678
+";
+
+    fn object(source: &str) -> Object {
+        Object::from_program(&Assembler::new(RegisterMachineTarget).assemble(source).unwrap())
+    }
+
+    fn link(sources: &[&str]) -> Result<Image, Vec<LinkError>> {
+        let mut linker = Linker::new();
+        for (index, source) in sources.iter().enumerate() {
+            linker.add(&format!("{index}.o"), object(source));
+        }
+        linker.link()
+    }
+
+    fn contents(image: &Image, name: &str) -> _Memory {
+        let section = image.sections.iter().find(|x| x.name == name).unwrap();
+        let mut memory = _Memory::new(section.bytes.len());
+        memory.write_bytes(0, &section.bytes);
+        memory
+    }
+
+    #[test]
+    fn merges_and_realigns_sections() {
+        let image = link(&[MAIN, VALUE]).unwrap();
+        let names: Vec<_> = image.sections.iter().map(|x| (x.name.as_str(), x.address, x.size)).collect();
+        assert_eq!(names, [(".text", 0, 4), (".data", 16, 20)]);
+        assert_eq!(image.entry, 0);
+
+        // The second object's .data starts at the next 16 byte boundary.
+        let value = image.symbol("value").unwrap();
+        assert_eq!((value.section, value.value), (Some(1), 16));
+        let data = contents(&image, ".data");
+        assert_eq!(data.read_u8(0), 1);
+        assert_eq!(data.read_u64(1), 32);
+        assert_eq!(data.read_u32(16), 0x12345678);
+    }
+
+    #[test]
+    fn places_sections_at_their_bases() {
+        let image = Linker::new()
+            .with_base(".data", 0x400)
+            .with_object("main.o", object(MAIN))
+            .with_object("value.o", object(VALUE))
+            .link()
+            .unwrap();
+        assert_eq!(image.sections[1].address, 0x400);
+        assert_eq!(contents(&image, ".data").read_u64(1), 0x410);
+    }
+
+    #[test]
+    fn reports_undefined_symbols() {
+        let errors = link(&[MAIN]).unwrap_err();
+        assert_eq!(errors, [LinkError::UndefinedSymbol { name: "value".to_string(), object: "0.o".to_string() }]);
+
+        let errors = link(&[VALUE]).unwrap_err();
+        assert_eq!(errors, [LinkError::UndefinedEntry("start".to_string())]);
+    }
+
+    #[test]
+    fn reports_duplicate_symbols() {
+        let errors = link(&[MAIN, VALUE, VALUE]).unwrap_err();
+        assert_eq!(errors, [LinkError::DuplicateSymbol { name: "value".to_string(), first: "1.o".to_string(), second: "2.o".to_string() }]);
+    }
+
+    #[test]
+    fn reports_sections_past_the_address_space() {
+        let errors = Linker::new().with_base(".text", 0xFFFF_FFFF_FFFF_FFF0).with_object("main.o", object(MAIN)).with_object("value.o", object(VALUE)).link().unwrap_err();
+        assert_eq!(errors, [
+            LinkError::SectionOverflow { name: ".data".to_string(), address: 0xFFFF_FFFF_FFFF_FFF4, size: 20 },
+        ]);
+
+        let errors = Linker::new().with_base(".text", 0xFFFF_FFFF_FFFF_FFFE).with_object("main.o", object(MAIN)).with_object("value.o", object(VALUE)).link().unwrap_err();
+        assert_eq!(errors, [LinkError::SectionOverflow { name: ".text".to_string(), address: 0xFFFF_FFFF_FFFF_FFFE, size: 4 }]);
+    }
+
+    #[test]
+    fn objects_and_images_round_trip() {
+        let main = object(MAIN);
+        assert_eq!(Object::from_bytes(&main.to_bytes()).unwrap(), main);
+
+        let image = link(&[MAIN, VALUE]).unwrap();
+        assert_eq!(Image::from_bytes(&image.to_bytes()).unwrap(), image);
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+
+use avm_rs_component::cpu::CPUTrait;
+use avm_rs_memory::{mem::MemorySliceTrait, pointer::Pointer, vmem::VirtualMemory};
+
+use crate::image::{Image, ImageError, Protection, SectionKind};
+
+#[derive(Debug)]
+pub struct LoadedSection {
+    pub name: String,
+    pub kind: SectionKind,
+    pub protection: Protection,
+    pub memory: Pointer,
+}
+
+impl LoadedSection {
+    pub fn address(&self) -> u64 {
+        self.memory.address as u64
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        (self.address()..self.address() + self.memory.size as u64).contains(&address)
+    }
+}
+
+#[derive(Debug)]
+pub struct LoadedImage {
+    pub entry: u64,
+    pub sections: Vec<LoadedSection>,
+    // Load address of every symbol, absolute ones keep their value.
+    pub symbols: BTreeMap<String, u64>,
+}
+
+impl LoadedImage {
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn section(&self, name: &str) -> Option<&LoadedSection> {
+        self.sections.iter().find(|x| x.name == name)
+    }
+
+    pub fn start(&self, cpu: &mut impl CPUTrait) {
+        cpu.start_at(self.entry);
+    }
+
+    pub fn unload(mut self, memory: &mut VirtualMemory) {
+        for section in &mut self.sections {
+            memory.free(&mut section.memory);
+        }
+    }
+}
+
+// Allocates every section from `memory` wherever it fits, copies it in and
+// applies the relocations for where it ended up. An entry point inside a
+// section moves with it. Empty sections are rejected, they'd take no memory
+// to give back.
+pub fn load(image: &Image, memory: &mut VirtualMemory) -> Result<LoadedImage, ImageError> {
+    image.validate()?;
+    if let Some(section) = image.sections.iter().find(|x| x.size == 0) {
+        return Err(ImageError::Malformed(format!("section {} is empty", section.name)));
+    }
+    let mut loaded = LoadedImage { entry: image.entry, sections: Vec::new(), symbols: BTreeMap::new() };
+    for section in &image.sections {
+        let allocation = usize::try_from(section.size).ok().and_then(|size| memory.try_allocate_aligned(size, section.alignment as usize));
+        let Some(mut pointer) = allocation else {
+            let error = ImageError::OutOfMemory { section: section.name.clone(), size: section.size };
+            loaded.unload(memory);
+            return Err(error);
+        };
+        match section.kind {
+            SectionKind::Bss => pointer.fill(0, section.size as usize, 0),
+            _ => pointer.write_bytes(0, &section.bytes),
+        }
+        loaded.sections.push(LoadedSection { name: section.name.clone(), kind: section.kind, protection: section.protection, memory: pointer });
+    }
+
+    let addresses: Vec<u64> = image.symbols.iter()
+        .map(|symbol| symbol.section.map_or(symbol.value, |x| loaded.sections[x].address().wrapping_add(symbol.value)))
+        .collect();
+    for relocation in &image.relocations {
+        let section = &mut loaded.sections[relocation.section];
+        let mut value = (addresses[relocation.symbol] as i64).wrapping_add(relocation.addend);
+        if relocation.kind.is_relative() {
+            value = value.wrapping_sub((section.address() + relocation.offset) as i64);
+        }
+        if !relocation.kind.apply(&mut section.memory, relocation.offset as usize, value) {
+            let error = ImageError::RelocationOverflow { section: section.name.clone(), offset: relocation.offset, value };
+            loaded.unload(memory);
+            return Err(error);
+        }
+    }
+
+    if let Some(index) = image.sections.iter().position(|x| (x.address..x.address.saturating_add(x.size)).contains(&image.entry)) {
+        loaded.entry = loaded.sections[index].address() + image.entry - image.sections[index].address;
+    }
+    loaded.symbols = image.symbols.iter().zip(addresses).map(|(symbol, address)| (symbol.name.clone(), address)).collect();
+    Ok(loaded)
+}
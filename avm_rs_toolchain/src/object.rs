@@ -0,0 +1,179 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    assembler::{AssembledProgram, RelocationTarget},
+    image::{program_sections, read_relocation, read_section, validate_relocation, validate_section, write_name, write_relocation, write_section, ImageError, Reader, Relocation, Section, ABSOLUTE, GLOBAL},
+};
+
+pub const OBJECT_MAGIC: [u8; 4] = *b"AVMO";
+pub const OBJECT_VERSION: u16 = 1;
+
+const IMPORTED: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Local,
+    Exported,
+    Imported,
+}
+
+// Defined symbols are an offset into `section`, or a plain number without
+// one. Imported symbols have neither, the linker finds them in another object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectSymbol {
+    pub name: String,
+    pub binding: Binding,
+    pub section: Option<usize>,
+    pub value: u64,
+}
+
+// An assembled file waiting to be linked. Sections start at 0 and every
+// address in them is covered by a relocation, the linker decides where they
+// go. The format is the one of `Image` with "AVMO" as magic, no entry point
+// and bit 1 of the symbol flags set for imported symbols.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<ObjectSymbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Object {
+    // Every section gets a local symbol of its own name for relocations to
+    // refer to, like in `Image::from_program`.
+    pub fn from_program(program: &AssembledProgram) -> Self {
+        let (mut sections, indices) = program_sections(program);
+        let mut symbols = Vec::new();
+        for (index, section) in sections.iter().enumerate() {
+            symbols.push(ObjectSymbol { name: section.name.clone(), binding: Binding::Local, section: Some(index), value: 0 });
+        }
+        for (name, &value) in &program.symbols {
+            let binding = if program.globals.contains(name) { Binding::Exported } else { Binding::Local };
+            let section = program.symbol_sections.get(name).and_then(|&x| indices[x].map(|index| (x, index)));
+            symbols.push(match section {
+                Some((assembled, index)) => ObjectSymbol { name: name.clone(), binding, section: Some(index), value: value - program.sections[assembled].address },
+                None => ObjectSymbol { name: name.clone(), binding, section: None, value },
+            });
+        }
+        let imports = symbols.len();
+        for name in &program.externs {
+            symbols.push(ObjectSymbol { name: name.clone(), binding: Binding::Imported, section: None, value: 0 });
+        }
+
+        let mut relocations = Vec::new();
+        for relocation in &program.relocations {
+            let symbol = match &relocation.target {
+                RelocationTarget::Section(target) => indices[*target],
+                RelocationTarget::External(name) => program.externs.iter().position(|x| x == name).map(|index| imports + index),
+            };
+            let (Some(section), Some(symbol)) = (indices[relocation.section], symbol) else {
+                continue;
+            };
+            relocations.push(Relocation { section, offset: relocation.offset, kind: relocation.kind, symbol, addend: relocation.addend });
+        }
+        for section in &mut sections {
+            section.address = 0;
+        }
+        Object { sections, symbols, relocations }
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&ObjectSymbol> {
+        self.symbols.iter().find(|x| x.name == name)
+    }
+
+    pub fn exports(&self) -> impl Iterator<Item = &ObjectSymbol> {
+        self.symbols.iter().filter(|x| x.binding == Binding::Exported)
+    }
+
+    pub fn imports(&self) -> impl Iterator<Item = &ObjectSymbol> {
+        self.symbols.iter().filter(|x| x.binding == Binding::Imported)
+    }
+
+    pub fn validate(&self) -> Result<(), ImageError> {
+        for section in &self.sections {
+            validate_section(section)?;
+        }
+        for symbol in &self.symbols {
+            if symbol.section.is_some_and(|x| x >= self.sections.len()) {
+                return Err(ImageError::Malformed(format!("symbol {} is in a missing section", symbol.name)));
+            }
+            if symbol.binding == Binding::Imported && symbol.section.is_some() {
+                return Err(ImageError::Malformed(format!("imported symbol {} has a section", symbol.name)));
+            }
+        }
+        for relocation in &self.relocations {
+            if relocation.symbol >= self.symbols.len() {
+                return Err(ImageError::Malformed(format!("relocation against missing symbol {}", relocation.symbol)));
+            }
+            validate_relocation(&self.sections, relocation)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.extend_from_slice(&OBJECT_MAGIC);
+        output.extend_from_slice(&OBJECT_VERSION.to_be_bytes());
+        output.extend_from_slice(&0u16.to_be_bytes());
+        for count in [self.sections.len(), self.symbols.len(), self.relocations.len()] {
+            output.extend_from_slice(&(count as u32).to_be_bytes());
+        }
+        for section in &self.sections {
+            write_section(&mut output, section);
+        }
+        for symbol in &self.symbols {
+            write_name(&mut output, &symbol.name);
+            output.extend_from_slice(&symbol.section.map_or(ABSOLUTE, |x| x as u32).to_be_bytes());
+            output.extend_from_slice(&symbol.value.to_be_bytes());
+            output.push(match symbol.binding {
+                Binding::Local => 0,
+                Binding::Exported => GLOBAL,
+                Binding::Imported => GLOBAL | IMPORTED,
+            });
+        }
+        for relocation in &self.relocations {
+            write_relocation(&mut output, relocation);
+        }
+        output
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ImageError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(OBJECT_MAGIC, OBJECT_VERSION)?;
+        reader.u16()?;
+        let (sections, symbols, relocations) = (reader.u32()?, reader.u32()?, reader.u32()?);
+        let mut object = Object { sections: Vec::new(), symbols: Vec::new(), relocations: Vec::new() };
+        for _ in 0..sections {
+            object.sections.push(read_section(&mut reader)?);
+        }
+        for _ in 0..symbols {
+            let name = reader.name()?;
+            let section = match reader.u32()? {
+                ABSOLUTE => None,
+                index => Some(index as usize),
+            };
+            let value = reader.u64()?;
+            let binding = match reader.u8()? {
+                0 => Binding::Local,
+                GLOBAL => Binding::Exported,
+                flags if flags == GLOBAL | IMPORTED => Binding::Imported,
+                flags => return Err(ImageError::Malformed(format!("symbol {name} has invalid flags 0x{flags:X}"))),
+            };
+            object.symbols.push(ObjectSymbol { name, binding, section, value });
+        }
+        for _ in 0..relocations {
+            object.relocations.push(read_relocation(&mut reader)?);
+        }
+        reader.finish()?;
+        object.validate()?;
+        Ok(object)
+    }
+
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn read_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::from_bytes(&fs::read(path)?)?)
+    }
+}
@@ -0,0 +1,250 @@
+use std::{fmt::Write as _, io::{self, BufRead, Write}};
+
+use avm_rs_component::{cpu::StepResult, debug::DebugTarget};
+
+use crate::{debugger::{Debugger, StopReason}, disassembler::DisassemblerTarget};
+
+const HELP: &str = "\
+break LOCATION [if CONDITION]   Stop at LOCATION, an address or symbol expression
+delete ID, enable ID, disable ID
+info breakpoints, info registers [NAME...]
+continue, step [COUNT], next [COUNT], finish
+backtrace                       Show the program counter and the return addresses
+x/LENGTH ADDRESS                Show LENGTH bytes of memory, 64 by default
+disassemble/COUNT [ADDRESS]     Show COUNT instructions, 10 by default
+print EXPRESSION                Evaluate EXPRESSION, [ADDRESS] reads a u64
+set REGISTER = EXPRESSION
+reset, quit
+An empty line repeats the previous step, next, continue, x or disassemble.
+";
+
+// Line-oriented front-end for `Debugger`, with gdb-like commands and
+// abbreviations.
+pub struct Repl<C: DebugTarget, T: DisassemblerTarget> {
+    pub debugger: Debugger<C, T>,
+    previous: String,
+}
+
+impl<C: DebugTarget, T: DisassemblerTarget> Repl<C, T> {
+    pub fn new(debugger: Debugger<C, T>) -> Self {
+        Self { debugger, previous: String::new() }
+    }
+
+    // Reads commands until `input` ends or `quit`, printing a prompt before
+    // each of them.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        write!(output, "{}(avm) ", self.location())?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if matches!(line.trim(), "q" | "quit") {
+                break;
+            }
+            match self.execute(&line) {
+                Ok(text) => write!(output, "{text}")?,
+                Err(message) => writeln!(output, "{message}")?,
+            }
+            write!(output, "(avm) ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    // Runs one command and returns what it prints.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = match line.trim() {
+            "" => self.previous.clone(),
+            line => line.to_string(),
+        };
+        let (command, rest) = line.split_once(char::is_whitespace).map_or((line.as_str(), ""), |(command, rest)| (command, rest.trim()));
+        let (command, suffix) = command.split_once('/').map_or((command, None), |(command, suffix)| (command, Some(suffix)));
+        // Like gdb, only commands that move on or show the next thing repeat.
+        self.previous = match command {
+            "s" | "si" | "step" | "stepi" | "n" | "ni" | "next" | "nexti" | "c" | "continue" | "x" | "disas" | "disassemble" => line.clone(),
+            _ => String::new(),
+        };
+        let count = |default: usize| suffix.map_or(Ok(default), |x| x.parse().map_err(|_| format!("Invalid count '{x}'")));
+        let repeat = || if rest.is_empty() { Ok(1) } else { rest.parse::<usize>().map_err(|_| format!("Invalid count '{rest}'")) };
+
+        match command {
+            "" => Ok(String::new()),
+            "h" | "help" => Ok(HELP.to_string()),
+            "b" | "break" => {
+                let (location, condition) = match rest.split_once(" if ") {
+                    Some((location, condition)) => (location, Some(condition.trim())),
+                    None => (rest, None),
+                };
+                let id = self.debugger.add_breakpoint(location, condition)?;
+                let address = self.debugger.breakpoints().last().unwrap().address;
+                Ok(format!("Breakpoint {id} at {}\n", self.address(address)))
+            }
+            "d" | "delete" => {
+                let id = Self::breakpoint_id(rest)?;
+                match self.debugger.remove_breakpoint(id) {
+                    true => Ok(String::new()),
+                    false => Err(format!("No breakpoint number {id}")),
+                }
+            }
+            "enable" | "disable" => {
+                let id = Self::breakpoint_id(rest)?;
+                match self.debugger.set_breakpoint_enabled(id, command == "enable") {
+                    true => Ok(String::new()),
+                    false => Err(format!("No breakpoint number {id}")),
+                }
+            }
+            "i" | "info" => match rest.split_whitespace().next().unwrap_or("") {
+                "b" | "break" | "breakpoints" => Ok(self.breakpoints()),
+                "r" | "reg" | "registers" => self.registers(rest.split_whitespace().skip(1).collect()),
+                _ => Err("Expected 'info breakpoints' or 'info registers'".to_string()),
+            },
+            "c" | "continue" => {
+                let reason = self.debugger.cont();
+                Ok(self.stop(reason))
+            }
+            "s" | "si" | "step" | "stepi" => {
+                let mut reason = StopReason::Stepped;
+                for _ in 0..repeat()? {
+                    reason = self.debugger.step();
+                    if reason != StopReason::Stepped {
+                        break;
+                    }
+                }
+                Ok(self.stop(reason))
+            }
+            "n" | "ni" | "next" | "nexti" => {
+                let mut reason = StopReason::Stepped;
+                for _ in 0..repeat()? {
+                    reason = self.debugger.step_over();
+                    if reason != StopReason::Stepped {
+                        break;
+                    }
+                }
+                Ok(self.stop(reason))
+            }
+            "fin" | "finish" => {
+                let reason = self.debugger.step_out();
+                Ok(self.stop(reason))
+            }
+            "bt" | "backtrace" | "where" => {
+                let mut output = String::new();
+                for (index, frame) in self.debugger.backtrace().iter().enumerate() {
+                    let _ = writeln!(output, "#{index:<3}0x{:016x} in {}", frame.address, frame.symbol.as_deref().unwrap_or("??"));
+                }
+                Ok(output)
+            }
+            "x" => {
+                let address = self.debugger.evaluate(rest)?;
+                self.debugger.hexdump(address, count(64)?)
+            }
+            "disas" | "disassemble" => {
+                let address = match rest {
+                    "" => self.debugger.cpu().pc(),
+                    rest => self.debugger.evaluate(rest)?,
+                };
+                let pc = self.debugger.cpu().pc();
+                let mut output = String::new();
+                for instruction in self.debugger.disassemble(address, count(10)?) {
+                    let marker = if instruction.address == pc { "=>" } else { "  " };
+                    let _ = writeln!(output, "{marker} {}:\t{}", self.address(instruction.address), self.debugger.format(&instruction));
+                }
+                Ok(output)
+            }
+            "p" | "print" => match self.debugger.cpu().register_index(rest) {
+                Some(index) => Ok(format!("{} = {}\n", rest, self.debugger.registers()[index].1)),
+                None => {
+                    let value = self.debugger.evaluate(rest)?;
+                    Ok(format!("{value} (0x{value:x})\n"))
+                }
+            },
+            "set" => {
+                let Some((register, expression)) = rest.split_once('=') else {
+                    return Err("Expected 'set REGISTER = EXPRESSION'".to_string());
+                };
+                let value = self.debugger.evaluate(expression)?;
+                self.debugger.set_register(register.trim(), value)?;
+                Ok(String::new())
+            }
+            "reset" => {
+                self.debugger.reset();
+                Ok(self.location())
+            }
+            _ => Err(format!("Unknown command '{command}', try 'help'")),
+        }
+    }
+
+    fn breakpoint_id(text: &str) -> Result<usize, String> {
+        text.parse().map_err(|_| format!("Expected a breakpoint number, got '{text}'"))
+    }
+
+    // `0x100 <name+0x4>`, or only the address without a symbol.
+    fn address(&self, address: u64) -> String {
+        match self.debugger.describe(address) {
+            Some(symbol) => format!("0x{address:x} <{symbol}>"),
+            None => format!("0x{address:x}"),
+        }
+    }
+
+    // The instruction at the program counter.
+    fn location(&self) -> String {
+        let pc = self.debugger.cpu().pc();
+        match self.debugger.disassemble(pc, 1).first() {
+            Some(instruction) => format!("{}:\t{}\n", self.address(pc), self.debugger.format(instruction)),
+            None => format!("{}\n", self.address(pc)),
+        }
+    }
+
+    fn stop(&self, reason: StopReason) -> String {
+        let reason = match reason {
+            StopReason::Stepped => String::new(),
+            StopReason::Breakpoint(id) => format!("Breakpoint {id}, "),
+            StopReason::ConditionError { breakpoint, message } => format!("Error in condition of breakpoint {breakpoint}: {message}\n"),
+            StopReason::Cpu(StepResult::Halted) => "Program halted\n".to_string(),
+            StopReason::Cpu(StepResult::Breakpoint) => "Program stopped at brk\n".to_string(),
+            StopReason::Cpu(StepResult::Fault(fault)) => format!("Program stopped: {fault}\n"),
+            StopReason::Cpu(StepResult::WaitingForInterrupt) => "Program is waiting for an interrupt\n".to_string(),
+            StopReason::Cpu(StepResult::Continue) => String::new(),
+            StopReason::CycleLimit => format!("Stopped after {} cycles\n", self.debugger.cycle_limit().unwrap_or(0)),
+        };
+        reason + &self.location()
+    }
+
+    fn breakpoints(&self) -> String {
+        if self.debugger.breakpoints().is_empty() {
+            return "No breakpoints\n".to_string();
+        }
+        let mut output = "Num Enb Address\n".to_string();
+        for breakpoint in self.debugger.breakpoints() {
+            let enabled = if breakpoint.enabled { 'y' } else { 'n' };
+            let _ = write!(output, "{:<3} {enabled}   {}", breakpoint.id, self.address(breakpoint.address));
+            if let Some(condition) = &breakpoint.condition {
+                let _ = write!(output, " if {condition}");
+            }
+            match breakpoint.hits {
+                0 => output.push('\n'),
+                1 => output += ", hit once\n",
+                hits => {
+                    let _ = writeln!(output, ", hit {hits} times");
+                }
+            }
+        }
+        output
+    }
+
+    fn registers(&self, names: Vec<&str>) -> Result<String, String> {
+        let registers = self.debugger.registers();
+        for name in &names {
+            if !registers.iter().any(|(register, _)| register.eq_ignore_ascii_case(name)) {
+                return Err(format!("Unknown register '{name}'"));
+            }
+        }
+        let mut output = String::new();
+        for (name, value) in registers {
+            if !names.is_empty() && !names.iter().any(|x| x.eq_ignore_ascii_case(name)) {
+                continue;
+            }
+            let digits = value.width() as usize / 4;
+            let _ = writeln!(output, "{name:<6} 0x{:0digits$x}  {value}", value.to_bits());
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,121 @@
+use avm_rs_component::{decode::{DecodeError, Endian, InstructionDecoder, InstructionReader}, isa::{find_instructions, IsaError, OperandKind}, register_machine::RegisterInstruction, stack_machine::{StackDecoder, StackInstruction}};
+use avm_rs_memory::mem::MemorySliceTrait;
+
+use crate::{assembler::{AssemblerTarget, Encoded, Operand, OperandField}, disassembler::DisassemblerTarget, image::{padded_leb128, RelocationKind}};
+
+// Targets `RegisterCPU`. A bare expression given for a branch or call
+// offset is the target address, `#n` is the raw offset in words. Relocatable
+// addresses can go in any immediate field.
+pub struct RegisterMachineTarget;
+
+impl AssemblerTarget for RegisterMachineTarget {
+    fn encode(&self, mnemonic: &str, operands: &[Operand], relocatable: &[bool], address: u64) -> Result<Encoded, String> {
+        let mut error = IsaError::UnknownMnemonic(mnemonic.to_string()).to_string();
+        for instruction in find_instructions(RegisterInstruction::INSTRUCTIONS, mnemonic) {
+            let mut values = Vec::new();
+            for (index, operand) in operands.iter().enumerate() {
+                let relative = instruction.fields.get(index).is_some_and(|field| field.name == "offset");
+                values.push(match *operand {
+                    Operand::Register(register) => (OperandKind::Register, register as i64),
+                    Operand::FloatRegister(register) => (OperandKind::FloatRegister, register as i64),
+                    Operand::Immediate(value) => (OperandKind::Immediate, value),
+                    Operand::Address(target) if relative => {
+                        let offset = target.wrapping_sub(address as i64);
+                        if offset % 4 != 0 {
+                            return Err(format!("Branch target 0x{target:X} isn't word aligned"));
+                        }
+                        (OperandKind::SignedImmediate, offset / 4)
+                    }
+                    Operand::Address(value) => (OperandKind::Immediate, value),
+                });
+            }
+            match instruction.encode(&values) {
+                Ok(word) => {
+                    let fields = instruction.fields.iter().enumerate().filter(|(index, _)| relocatable.get(*index) == Some(&true)).map(|(operand, field)| OperandField {
+                        operand,
+                        offset: 0,
+                        kind: match field.name {
+                            "offset" => RelocationKind::Relative { start: field.start as u8, end: field.end as u8, shift: 2 },
+                            _ => RelocationKind::Field { start: field.start as u8, end: field.end as u8, signed: field.kind == OperandKind::SignedImmediate },
+                        },
+                    });
+                    return Ok(Encoded { bytes: (word as u32).to_be_bytes().to_vec(), fields: fields.collect() });
+                }
+                Err(e) => error = e.to_string(),
+            }
+        }
+        Err(error)
+    }
+}
+
+impl DisassemblerTarget for RegisterMachineTarget {
+    const ALIGNMENT: usize = 4;
+
+    fn decode(&self, memory: &dyn MemorySliceTrait, address: u64) -> Result<(usize, &'static str, Vec<Operand>), DecodeError> {
+        let mut reader = InstructionReader::new(memory, address as usize);
+        let word = reader.immediate(4, Endian::Big)?;
+        let Some(instruction) = RegisterInstruction::INSTRUCTIONS.iter().find(|x| x.matches(word)) else {
+            return Err(reader.invalid(word as u32));
+        };
+        let operands = instruction.fields.iter().zip(instruction.decode(word)).map(|(field, (kind, value))| match kind {
+            OperandKind::Register => Operand::Register(value as u8),
+            OperandKind::FloatRegister => Operand::FloatRegister(value as u8),
+            _ if field.name == "offset" => Operand::Address((address as i64).wrapping_add(value * 4)),
+            _ => Operand::Immediate(value),
+        });
+        Ok((4, instruction.mnemonic, operands.collect()))
+    }
+}
+
+// Targets `StackCPU`, every operand is a plain value. Relocatable operands
+// are padded to a fixed length so the loader can rewrite them in place.
+pub struct StackMachineTarget;
+
+const RELOCATABLE_LENGTH: u8 = 5;
+
+impl AssemblerTarget for StackMachineTarget {
+    fn encode(&self, mnemonic: &str, operands: &[Operand], relocatable: &[bool], _address: u64) -> Result<Encoded, String> {
+        let Some(&(name, _, takes_operand)) = StackInstruction::MNEMONICS.iter().find(|x| x.0.eq_ignore_ascii_case(mnemonic)) else {
+            return Err(format!("Unknown instruction: {mnemonic}"));
+        };
+        let operand = match operands {
+            [] => None,
+            [Operand::Immediate(value) | Operand::Address(value)] => Some(*value),
+            [_] => return Err(format!("{name} doesn't take a register operand")),
+            _ => return Err(format!("{name} takes at most one operand, but {} were given", operands.len())),
+        };
+        let Some(instruction) = StackInstruction::from_mnemonic(name, operand) else {
+            let expected = if takes_operand { "one operand" } else { "no operands" };
+            return Err(format!("{name} takes {expected}, but {} were given", operands.len()));
+        };
+        let mut bytes = Vec::new();
+        instruction.encode(&mut bytes);
+        let (Some(value), [true]) = (operand, relocatable) else {
+            return Ok(Encoded { bytes, fields: Vec::new() });
+        };
+        let signed = matches!(instruction, StackInstruction::Push(_));
+        let Some(padded) = padded_leb128(value, RELOCATABLE_LENGTH as usize, signed) else {
+            return Err(format!("Address 0x{value:X} doesn't fit in {RELOCATABLE_LENGTH} LEB128 bytes"));
+        };
+        bytes.truncate(1);
+        bytes.extend_from_slice(&padded);
+        let kind = RelocationKind::Leb128 { length: RELOCATABLE_LENGTH, signed };
+        Ok(Encoded { bytes, fields: vec![OperandField { operand: 0, offset: 1, kind }] })
+    }
+}
+
+impl DisassemblerTarget for StackMachineTarget {
+    const ALIGNMENT: usize = 1;
+
+    fn decode(&self, memory: &dyn MemorySliceTrait, address: u64) -> Result<(usize, &'static str, Vec<Operand>), DecodeError> {
+        use StackInstruction::*;
+        let decoded = StackDecoder.decode_at(memory, address as usize)?;
+        let operand = match decoded.instruction {
+            Jump(target) | JumpIfZero(target) | JumpIfNotZero(target) | Call(target) => Some(Operand::Address(target as i64)),
+            Push(value) => Some(Operand::Immediate(value)),
+            Ret(value) | Enter(value) | LoadLocal(value) | StoreLocal(value) | LoadArg(value) => Some(Operand::Immediate(value as i64)),
+            _ => None,
+        };
+        Ok((decoded.length, decoded.instruction.mnemonic(), operand.into_iter().collect()))
+    }
+}
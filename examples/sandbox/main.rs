@@ -1,93 +1,70 @@
-use std::io::Read;
+use avm_rs_component::{cpu::{CPUTrait, StepResult}, isa::{FReg, Reg}, register_machine::RegisterCPU};
+use avm_rs_memory::{access_memory, mem::create_memory, vmem::VirtualMemory};
+use avm_rs_toolchain::{assembler::Assembler, disassembler::{Disassembler, SymbolTable}, image::Image, loader::load, target::RegisterMachineTarget};
 
-use avm_rs_component::{cpu::CPUTrait, register::{RegisterF64, RegisterTrait, RegisterU64}};
-use avm_rs_memory::{access_memory, mem::{create_memory, Memory, MemorySliceTrait}, share_memory, vmem::VirtualMemory, wrappers::stack::Stack};
+// Computes 10! with a loop and a call, stores it and its square root, then
+// reads both back.
+const PROGRAM: &str = "
+start:
+        movi r0, #10
+        call factorial
+        movi r2, #result
+        st64 r1, r2, #0
+        fcvtif f0, r1
+        fsqrt f1, f0
+        fst f1, r2, #8
+        ld32u r3, r2, #0
+        brk
+        halt
 
-pub struct MiniCPU {
-    memory: Memory,
-    stack: Stack,
-    ipoffset: usize,
+; r1 = r0!
+factorial:
+        movi r1, #1
+.loop:  cmpi r0, #1
+        bleu .done
+        mul r1, r1, r0
+        addi r0, r0, #-1
+        b .loop
+.done:  ret
 
-    pub ru64: [RegisterU64; 7],
-    pub fu64: [RegisterF64; 4],
+        .data
+result: .dword 0, 0
+";
 
-    // sp - 5
-    // ip - 6
-    // fl - 7
-}
-
-const MINICPU_ARRAY_REGISTER_U64: RegisterU64 = RegisterU64(0);
-const MINICPU_ARRAY_REGISTER_F64: RegisterF64 = RegisterF64(0.0);
-impl MiniCPU {
-    pub fn new(memory: Memory, stack: Stack) -> Self {
-        Self {
-            memory: memory,
-            stack: stack,
-            ipoffset: 0,
-            ru64: [MINICPU_ARRAY_REGISTER_U64; 7],
-            fu64: [MINICPU_ARRAY_REGISTER_F64; 4],
+fn main() {
+    let memory = create_memory(1024 * 512);
+    let program = match Assembler::new(RegisterMachineTarget).assemble(PROGRAM) {
+        Ok(program) => program,
+        Err(errors) => {
+            errors.iter().for_each(|error| eprintln!("{error}"));
+            return;
         }
-    }
+    };
 
-    pub fn start_at(&mut self, offset: usize) {
-        self.ipoffset = offset;
-    }
-
-    fn fetch_instruction(&mut self) -> (u8, u8, u8, u8) {
-        let instcode = access_memory!(self.memory).read_u32(self.ipoffset + self.ru64[6].0 as usize);
-        self.ru64[5].0 += 4;
-
-        let inst = ((instcode >> 24) & 0xFF) as u8;
-        let ins2 = ((instcode >> 16) & 0xFF) as u8;
-        let op2 = ((instcode >> 8) & 0xFF) as u8;
-        let op3 = (instcode & 0xFF) as u8;
-        (inst, ins2, op2, op3)
-    }
-}
+    // Goes through the executable format and gets loaded after memory that's
+    // already taken, so every address in it has to be relocated.
+    let image = Image::from_program(&program, program.symbol("start").unwrap()).unwrap().to_bytes();
+    let image = Image::from_bytes(&image).unwrap();
+    let mut virtual_memory = VirtualMemory::new(memory.clone());
+    let _reserved = virtual_memory.allocate(0x100);
+    let loaded = load(&image, &mut virtual_memory).unwrap();
+    println!("{virtual_memory}");
 
-impl CPUTrait for MiniCPU {
-    fn reset(&mut self) {
-        self.stack.reset();
-        self.ru64[6].0 = 0;
-    }
+    let mut symbols = SymbolTable::new();
+    loaded.symbols.iter().for_each(|(name, &address)| symbols.insert(name, address));
+    let text = loaded.section(".text").unwrap();
+    let disassembler = Disassembler::new(RegisterMachineTarget).with_symbols(symbols);
+    print!("{}", disassembler.listing(&*access_memory!(memory), text.address(), text.address() + text.memory.size as u64));
 
-    fn step(&mut self) {
-        let (inst, ins2, op2, op3) = self.fetch_instruction();
+    let mut cpu = RegisterCPU::new(memory.clone(), 0);
+    loaded.start(&mut cpu);
+    let result = cpu.run_for(1000);
+    println!("{result:?} at 0x{:04X}", cpu.pc);
+    assert_eq!(result, StepResult::Breakpoint);
+    println!("r1 = {}, r3 = 0x{:X}, f1 = {}", cpu.register(Reg(1)), cpu.register(Reg(3)), cpu.float_register(FReg(1)).0);
+    println!("{:?}, flags {}", cpu.run_for(1000), cpu.flags);
 
-        match inst {
-            // mov <X:register(u|f)>, <Y:register(u|f)> | mov <X:register(u|f)>, constant
-            1 => {
-                match ins2 {
-                    // mov ru, ru
-                    0 => {
-                        self.ru64[op2 as usize].0 = self.ru64[op3 as usize].0;
-                    }
-                    // mov fu, fu
-                    1 => {
-                        self.fu64[op2 as usize].0 = self.fu64[op3 as usize].0;
-                    }
-                    // mov ru, constant
-                    2 => {
-                        self.ru64[0].0 = op2 as u64 * op3 as u64;
-                    }
-                    _ => panic!("Unknown instruction: {inst}:{ins2}", )
-                }
-            }
-            _ => panic!("Unknown instruction: {}", inst),
-        }
-    }
+    let result = loaded.symbol("result").unwrap() as usize;
+    let memory = access_memory!(memory);
+    println!("{} {}", memory.read_u64(result), memory.read_f64(result + 8));
 }
-
-fn main() {
-    let mut memory = create_memory(1024 * 512);
-    let mut virtual_memory = VirtualMemory::new(share_memory!(memory));
-    let mut stack = Stack::new(virtual_memory.allocate(1024));
-    let mut cpu = MiniCPU::new(memory.clone(), stack);
-
-    access_memory!(memory).write_bytes(0, vec![1, 2, 3, 4].as_slice());
-
-    cpu.reset();
-    cpu.step();
-    println!("{}", cpu.ru64[0].0);
-    println!("{}", access_memory!(memory));
-}
\ No newline at end of file